@@ -4,6 +4,14 @@ use std::path::PathBuf;
 pub struct Wyvern {
     #[structopt(flatten)]
     pub verbose: clap_verbosity_flag::Verbosity,
+    #[structopt(long = "profile", help = "Profile of the GOG account to use")]
+    pub profile: Option<String>,
+    #[structopt(
+        long = "non-interactive",
+        alias = "yes",
+        help = "Never ask for input. Questions get their default answer, and wyvern exits with code 65 when something it needs can't be asked for. On when stdin isn't a terminal."
+    )]
+    pub non_interactive: bool,
     #[structopt(subcommand)]
     pub command: Command,
 }
@@ -62,6 +70,8 @@ pub enum Command {
         shortcuts: ShortcutOptions,
         #[structopt(short = "w", long = "windows", help = "Install a windows game")]
         windows: bool,
+        #[structopt(flatten)]
+        wine: WineOptions,
         #[structopt(
             short = "e",
             long = "external-zip",
@@ -69,6 +79,35 @@ pub enum Command {
         )]
         external_zip: bool,
     },
+    #[structopt(name = "run", about = "Launch an installed game")]
+    Run {
+        #[structopt(help = "Name or install directory of the game")]
+        game: String,
+        #[structopt(
+            short = "e",
+            long = "env",
+            help = "Set an environment variable for the game, as KEY=VALUE"
+        )]
+        env: Vec<String>,
+        #[structopt(
+            short = "w",
+            long = "wrapper",
+            help = "Run the game through a command, like gamemoderun or mangohud"
+        )]
+        wrappers: Vec<String>,
+        #[structopt(
+            short = "s",
+            long = "save",
+            help = "Remember the given environment variables and wrappers for this game"
+        )]
+        save: bool,
+        #[structopt(
+            short = "S",
+            long = "sync",
+            help = "Pull the game's saves before launching it and push them once it exits"
+        )]
+        sync: bool,
+    },
     #[structopt(
         name = "update",
         about = "Update a game if there is an update available"
@@ -84,6 +123,10 @@ pub enum Command {
         about = "Sync a game's saves to a specific location for backup"
     )]
     Sync(Sync),
+    #[structopt(name = "profile", about = "Manage profiles for several GOG accounts")]
+    Profile(Profiles),
+    #[structopt(name = "tokens", about = "Manage how GOG logins are stored")]
+    Tokens(TokenStore),
     #[structopt(name = "int", alias = "interactive", about = "Enter interactive mode")]
     Interactive,
     #[structopt(name = "login", about = "Force a login to GOG")]
@@ -92,18 +135,40 @@ pub enum Command {
         username: Option<String>,
         #[structopt(short = "p", long = "password", help = "Password to log in with")]
         password: Option<String>,
-        #[structopt(short = "c", long = "code", help = "Use a login code to log in")]
-        code: Option<String>
-    }
+        #[structopt(
+            short = "c",
+            long = "code",
+            help = "Use a login code, or the URL GOG's login page ends up at, to log in"
+        )]
+        code: Option<String>,
+        #[structopt(
+            long = "print-url",
+            help = "Print the URL to log in at, for logging in from another machine with --code"
+        )]
+        print_url: bool,
+    },
+    #[structopt(name = "logout", about = "Remove the stored GOG login")]
+    Logout,
+    #[structopt(name = "whoami", about = "Show which GOG account you're logged in as")]
+    Whoami,
 }
 #[derive(StructOpt, Debug)]
 pub enum Sync {
     #[structopt(name = "saves", about = "Configure where a game's saves are located")]
     Saves {
         #[structopt(parse(from_os_str))]
-        game_dir: PathBuf,
-        #[structopt(parse(from_os_str))]
-        saves: PathBuf,
+        game_dir: Option<PathBuf>,
+        #[structopt(
+            parse(from_os_str),
+            help = "Where the saves are. With --name, this is the only path given"
+        )]
+        saves: Option<PathBuf>,
+        #[structopt(
+            short = "n",
+            long = "name",
+            help = "Label for a game that isn't from GOG or wasn't installed by wyvern, instead of its game directory"
+        )]
+        name: Option<String>,
         #[structopt(short = "d", long = "db", help = "Db to save config to")]
         #[structopt(parse(from_os_str))]
         db: Option<PathBuf>,
@@ -111,35 +176,107 @@ pub enum Sync {
     #[structopt(name = "push", about = "Push save files to sync location")]
     Push {
         #[structopt(parse(from_os_str))]
-        game_dir: PathBuf,
-        #[structopt(parse(from_os_str))]
+        game_dir: Option<PathBuf>,
+        #[structopt(
+            parse(from_os_str),
+            help = "Where to sync to. With --name, this is the only path given"
+        )]
         sync_to: Option<PathBuf>,
+        #[structopt(
+            short = "n",
+            long = "name",
+            help = "Label for a game that isn't from GOG or wasn't installed by wyvern, instead of its game directory"
+        )]
+        name: Option<String>,
+        #[structopt(flatten)]
+        transfer: TransferOptions,
     },
     #[structopt(name = "pull", about = "Pull synced save files")]
     Pull {
         #[structopt(parse(from_os_str))]
-        game_dir: PathBuf,
-        #[structopt(parse(from_os_str))]
+        game_dir: Option<PathBuf>,
+        #[structopt(
+            parse(from_os_str),
+            help = "Where to sync from. With --name, this is the only path given"
+        )]
         sync_from: Option<PathBuf>,
-        #[structopt(short = "f", long = "force", help = "Force syncing even if unneeded")]
+        #[structopt(
+            short = "n",
+            long = "name",
+            help = "Label for a game that isn't from GOG or wasn't installed by wyvern, instead of its game directory"
+        )]
+        name: Option<String>,
+        #[structopt(flatten)]
+        transfer: TransferOptions,
+        #[structopt(
+            short = "f",
+            long = "force",
+            help = "Let the side being synced from win conflicting changes"
+        )]
         force: bool,
         #[structopt(
             short = "i",
             long = "ignore",
-            help = "Automatically refuse syncing save files that are older than the current"
+            help = "Automatically refuse syncing when save files changed on both sides"
         )]
         ignore_older: bool,
     },
+    #[structopt(name = "history", about = "List the snapshots of a game's saves")]
+    History {
+        #[structopt(help = "Name or install directory of the game")]
+        game: Option<String>,
+        #[structopt(
+            short = "n",
+            long = "name",
+            help = "Label the game's saves were pushed with, instead of its name"
+        )]
+        name: Option<String>,
+        #[structopt(short = "d", long = "db", help = "Db the saves are synced to")]
+        #[structopt(parse(from_os_str))]
+        db: Option<PathBuf>,
+    },
+    #[structopt(name = "restore", about = "Restore a game's saves from a snapshot")]
+    Restore {
+        #[structopt(help = "Name or install directory of the game")]
+        game: Option<String>,
+        #[structopt(
+            help = "Id or date of the snapshot, as listed by history. With --name, this is the only argument given"
+        )]
+        snapshot: Option<String>,
+        #[structopt(
+            short = "n",
+            long = "name",
+            help = "Label the game's saves were pushed with, instead of its name"
+        )]
+        name: Option<String>,
+        #[structopt(short = "d", long = "db", help = "Db the saves are synced to")]
+        #[structopt(parse(from_os_str))]
+        db: Option<PathBuf>,
+    },
+    #[structopt(
+        name = "update-locations",
+        about = "Add known save locations from a file, replacing those of the same games"
+    )]
+    UpdateLocations {
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+    },
     #[structopt(name = "db-pull", about = "Pull all save files from a database")]
     DbPull {
         #[structopt(parse(from_os_str))]
         path: Option<PathBuf>,
-        #[structopt(short = "f", long = "force", help = "Force syncing even if unneeded")]
+        #[structopt(flatten)]
+        transfer: TransferOptions,
+        #[structopt(
+            short = "f",
+            long = "force",
+            help = "Let the side being synced from win conflicting changes"
+        )]
         force: bool,
         #[structopt(
             short = "i",
             long = "ignore",
-            help = "Automatically refuse syncing save files that are older than the current"
+            help = "Automatically refuse syncing when save files changed on both sides"
         )]
         ignore_older: bool,
     },
@@ -147,7 +284,13 @@ pub enum Sync {
     DbPush {
         #[structopt(parse(from_os_str))]
         path: Option<PathBuf>,
-        #[structopt(short = "f", long = "force", help = "Force syncing even if unneeded")]
+        #[structopt(flatten)]
+        transfer: TransferOptions,
+        #[structopt(
+            short = "f",
+            long = "force",
+            help = "Let the side being synced from win conflicting changes"
+        )]
         force: bool,
         #[structopt(
             short = "i",
@@ -156,6 +299,150 @@ pub enum Sync {
         )]
         ignore_older: bool,
     },
+    #[structopt(
+        name = "ls",
+        alias = "list",
+        about = "List the games whose saves are synced"
+    )]
+    ListSaves {
+        #[structopt(short = "d", long = "db", help = "Db the saves are synced to")]
+        #[structopt(parse(from_os_str))]
+        db: Option<PathBuf>,
+        #[structopt(short = "j", long = "json", help = "Print results in JSON format")]
+        json: bool,
+    },
+    #[structopt(name = "rm", about = "Stop syncing a game's saves")]
+    RemoveSaves {
+        #[structopt(help = "Label, title or install directory of the game")]
+        game: String,
+        #[structopt(short = "d", long = "db", help = "Db the saves are synced to")]
+        #[structopt(parse(from_os_str))]
+        db: Option<PathBuf>,
+    },
+    #[structopt(name = "set-path", about = "Change where a game's saves are located")]
+    SetPath {
+        #[structopt(help = "Label, title or install directory of the game")]
+        game: String,
+        #[structopt(parse(from_os_str))]
+        path: PathBuf,
+        #[structopt(short = "d", long = "db", help = "Db the saves are synced to")]
+        #[structopt(parse(from_os_str))]
+        db: Option<PathBuf>,
+    },
+    #[structopt(
+        name = "diff",
+        about = "Show how a game's local saves differ from the synced ones"
+    )]
+    Diff {
+        #[structopt(help = "Label, title or install directory of the game")]
+        game: String,
+        #[structopt(short = "d", long = "db", help = "Db the saves are synced to")]
+        #[structopt(parse(from_os_str))]
+        db: Option<PathBuf>,
+    },
+    #[structopt(name = "db", about = "Operations on the database of synced saves")]
+    Db(SaveDb),
+    #[structopt(
+        name = "watch",
+        about = "Watch the save files in a database and push them shortly after they change"
+    )]
+    Watch {
+        #[structopt(parse(from_os_str))]
+        path: Option<PathBuf>,
+        #[structopt(
+            long = "delay",
+            default_value = "10",
+            help = "Seconds to wait after save files stop changing before pushing them"
+        )]
+        delay: u64,
+        #[structopt(flatten)]
+        transfer: TransferOptions,
+        #[structopt(
+            long = "install-service",
+            help = "Install a systemd user service that runs this watch on login, instead of watching now"
+        )]
+        install_service: bool,
+    },
+}
+#[derive(StructOpt, Debug)]
+pub enum SaveDb {
+    #[structopt(
+        name = "check",
+        about = "Check that every game in a database has valid save paths and identifiers"
+    )]
+    Check {
+        #[structopt(parse(from_os_str))]
+        path: Option<PathBuf>,
+    },
+}
+#[derive(StructOpt, Debug)]
+pub struct TransferOptions {
+    #[structopt(
+        long = "delete",
+        help = "Delete synced files that no longer exist where they're synced from"
+    )]
+    pub delete: bool,
+    #[structopt(
+        short = "c",
+        long = "checksum",
+        help = "Compare file contents instead of sizes and modification times"
+    )]
+    pub checksum: bool,
+    #[structopt(
+        long = "prefer",
+        help = "Which side wins when save files changed both locally and remotely: local, remote or newest"
+    )]
+    pub prefer: Option<::merge::Prefer>,
+}
+#[derive(StructOpt, Debug)]
+pub enum Profiles {
+    #[structopt(
+        name = "add",
+        about = "Add a profile. Its account is logged in when it's first used"
+    )]
+    Add {
+        name: String,
+        #[structopt(long = "sync-saves", help = "Where this account's saves are synced")]
+        sync_saves: Option<String>,
+        #[structopt(
+            long = "windows-auto",
+            help = "Always download windows versions of games without a linux one with this account"
+        )]
+        windows_auto: bool,
+        #[structopt(long = "dlc", help = "Always download DLCs with this account")]
+        dlc: bool,
+    },
+    #[structopt(name = "ls", alias = "list", about = "List profiles")]
+    List,
+    #[structopt(
+        name = "rm",
+        alias = "remove",
+        about = "Remove a profile and its login"
+    )]
+    Remove { name: String },
+    #[structopt(
+        name = "default",
+        about = "Use a profile when none is given with --profile. Without one, go back to the account at the top of the config"
+    )]
+    Default { name: Option<String> },
+}
+#[derive(StructOpt, Debug)]
+pub enum TokenStore {
+    #[structopt(
+        name = "encrypt",
+        about = "Move logins out of the config into a store encrypted with a passphrase"
+    )]
+    Encrypt,
+    #[structopt(
+        name = "decrypt",
+        about = "Move logins from the encrypted store back into the config"
+    )]
+    Decrypt,
+    #[structopt(
+        name = "lock",
+        about = "Forget the token store passphrase until it's entered again"
+    )]
+    Lock,
 }
 #[derive(StructOpt, Debug)]
 pub enum Connect {
@@ -201,6 +488,17 @@ pub struct ShortcutOptions {
     pub shortcuts: bool,
 }
 #[derive(StructOpt, Debug, Default)]
+pub struct WineOptions {
+    #[structopt(
+        short = "p",
+        long = "prefix",
+        help = "Create a dedicated wine prefix for an installed windows game"
+    )]
+    pub prefix: bool,
+    #[structopt(long = "wine", help = "Wine binary to use for windows games")]
+    pub wine: Option<String>,
+}
+#[derive(StructOpt, Debug, Default)]
 pub struct DownloadOptions {
     #[structopt(short = "i", long = "id", help = "download id")]
     pub id: Option<i64>,
@@ -257,4 +555,6 @@ pub struct DownloadOptions {
         help = "Use the zip CLI tool to unzip the installer. Faster."
     )]
     pub external_zip: bool,
+    #[structopt(flatten)]
+    pub wine: WineOptions,
 }
@@ -1,15 +1,23 @@
 use curl::easy::{Handler, WriteError};
+use dirsync;
 use fs::File;
 use fs::OpenOptions;
+use gitrepo;
 use gog::token::Token;
 use indicatif::ProgressBar;
+use remote;
 use serde_json;
-use std::collections::HashMap;
+use snapshots;
+use storage;
+use tokens;
+use std::collections::{BTreeMap, HashMap};
 use std::default::Default;
 use std::fs;
 use std::io::{Read, Write};
-use std::path::PathBuf;
-#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Hash)]
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Hash, Clone)]
 pub enum SaveType {
     GOG(i64),
     Other(String),
@@ -19,64 +27,394 @@ type SaveMap = HashMap<String, SaveInfo>;
 pub struct Config {
     pub version: u8,
     pub sync_saves: Option<String>,
+    /// Wine binary used for windows games, when not given on the command line
+    #[serde(default)]
+    pub wine: Option<String>,
+    /// What copies save files when syncing
+    #[serde(default)]
+    pub sync_backend: dirsync::Backend,
+    /// Profile used when none is given with --profile, instead of the account at the top of the config
+    #[serde(default)]
+    pub default_profile: Option<String>,
+    // TOML can't have plain values after tables, so the fields written as tables have to come last
     pub token: Option<Token>,
+    /// How many snapshots of each game's pushed saves to keep
+    #[serde(default)]
+    pub snapshot_retention: snapshots::Retention,
+    /// Whether pushed saves are archived and encrypted
+    #[serde(default)]
+    pub save_storage: storage::Settings,
+    #[serde(default)]
+    pub downloads: DownloadDefaults,
+    /// Whether logins are kept in an encrypted store instead of the config
+    #[serde(default)]
+    pub token_storage: tokens::Settings,
+    /// Remote places to sync saves to, by the name sync_saves refers to them with
+    #[serde(default)]
+    pub sync_profiles: HashMap<String, remote::Profile>,
+    /// Other GOG accounts, by profile name
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Account>,
 }
 impl Default for Config {
     fn default() -> Config {
         Config {
             version: 1,
             sync_saves: None,
+            wine: None,
+            sync_backend: dirsync::Backend::default(),
+            default_profile: None,
             token: None,
+            snapshot_retention: snapshots::Retention::default(),
+            save_storage: storage::Settings::default(),
+            downloads: DownloadDefaults::default(),
+            token_storage: tokens::Settings::default(),
+            sync_profiles: HashMap::new(),
+            profiles: BTreeMap::new(),
         }
     }
 }
+impl Config {
+    /// The account a command runs as: the named profile, or the one at the top of the config without a profile
+    pub fn account(&self, profile: Option<&str>) -> Result<Account, String> {
+        match profile {
+            Some(name) => match self.profiles.get(name) {
+                Some(account) => Ok(Account {
+                    wine: account.wine.clone().or_else(|| self.wine.clone()),
+                    ..account.clone()
+                }),
+                None => Err(format!(
+                    "There is no profile named {}. Add it with wyvern profile add {}",
+                    name, name
+                )),
+            },
+            None => Ok(Account {
+                sync_saves: self.sync_saves.clone(),
+                wine: self.wine.clone(),
+                token: self.token.clone(),
+                downloads: self.downloads.clone(),
+            }),
+        }
+    }
+    /// Stores the login of an account
+    pub fn set_token(&mut self, profile: Option<&str>, token: Option<Token>) {
+        match profile.and_then(|x| self.profiles.get_mut(x)) {
+            Some(account) => account.token = token,
+            None => self.token = token,
+        }
+    }
+}
+/// A GOG account with its own login and settings, selected with --profile
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct Account {
+    /// Where this account's saves are synced, instead of the sync_saves at the top of the config
+    #[serde(default)]
+    pub sync_saves: Option<String>,
+    /// Wine binary used for this account's windows games, instead of the wine at the top of the config
+    #[serde(default)]
+    pub wine: Option<String>,
+    pub token: Option<Token>,
+    #[serde(default)]
+    pub downloads: DownloadDefaults,
+}
+/// Download options that are on without passing them
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct DownloadDefaults {
+    /// Download the windows version of games without a linux one
+    pub windows_auto: bool,
+    /// Download DLCs as well
+    pub dlc: bool,
+}
 #[derive(Serialize, Deserialize)]
 pub struct SaveInfo {
     pub identifier: SaveType,
     pub path: String,
+    /// Name of the game, for listing synced saves
+    #[serde(default)]
+    pub title: Option<String>,
 }
 #[derive(Serialize, Deserialize)]
 pub struct SaveDB {
+    /// Format version, so that older savedbs can be migrated
+    pub version: u64,
     pub saves: SaveMap,
 }
 impl Default for SaveDB {
     fn default() -> SaveDB {
         SaveDB {
+            version: SaveDB::VERSION,
             saves: HashMap::new(),
         }
     }
 }
+/// Upgrades a savedb from one format version to the next
+type Migration = fn(&mut serde_json::Value) -> Result<(), String>;
+/// Savedbs from before the format was versioned only had the saves
+fn from_unversioned(savedb: &mut serde_json::Value) -> Result<(), String> {
+    let savedb = savedb
+        .as_object_mut()
+        .ok_or_else(|| "it is not a JSON object".to_string())?;
+    savedb
+        .entry("saves")
+        .or_insert_with(|| serde_json::Value::Object(Default::default()));
+    savedb.insert("version".to_string(), 1.into());
+    Ok(())
+}
+/// Held while a savedb is changed, so that machines sharing a sync folder don't overwrite each other's changes. Released when dropped.
+/// Remote sync locations are changed in a local mirror, so this only locks the mirror. Uploading it refuses to overwrite a savedb another machine uploaded in the meantime instead.
+pub struct SaveDBLock {
+    path: PathBuf,
+}
+impl SaveDBLock {
+    /// Locks left behind for longer than this are from a command that crashed
+    const STALE: Duration = Duration::from_secs(120);
+    /// How long to wait for a lock before giving up. It outlasts STALE, so that a stale lock is always removed first.
+    const WAIT: Duration = Duration::from_secs(150);
+    /// Waits for the savedb at `path` to be unlocked and locks it
+    pub fn acquire(path: &Path) -> Result<SaveDBLock, std::io::Error> {
+        let mut name = path.file_name().unwrap().to_os_string();
+        name.push(".lock");
+        let lock = path.with_file_name(name);
+        let started = Instant::now();
+        loop {
+            match OpenOptions::new().write(true).create_new(true).open(&lock) {
+                Ok(mut file) => {
+                    write!(file, "{} {}", gitrepo::machine_name(), std::process::id())?;
+                    return Ok(SaveDBLock { path: lock });
+                }
+                Err(ref err) if err.kind() == std::io::ErrorKind::AlreadyExists => {}
+                Err(err) => return Err(err),
+            }
+            let age = fs::metadata(&lock)
+                .and_then(|x| x.modified())
+                .ok()
+                .and_then(|x| x.elapsed().ok());
+            let holder = fs::read_to_string(&lock).unwrap_or_default();
+            let stale = age.map(|x| x > SaveDBLock::STALE).unwrap_or(false);
+            if stale || SaveDBLock::abandoned(&holder) {
+                warn!("Removing stale savedb lock {}", lock.display());
+                let _ = fs::remove_file(&lock);
+            } else if started.elapsed() > SaveDBLock::WAIT {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::WouldBlock,
+                    format!(
+                        "{} is locked by {}. Delete the lock file if no wyvern is running there.",
+                        path.display(),
+                        holder
+                    ),
+                ));
+            } else {
+                thread::sleep(Duration::from_millis(100));
+            }
+        }
+    }
+    /// Whether a lock was left behind by a wyvern on this machine that isn't running anymore
+    fn abandoned(holder: &str) -> bool {
+        let mut parts = holder.rsplitn(2, ' ');
+        let pid = parts.next().and_then(|x| x.parse::<libc::pid_t>().ok());
+        match (parts.next(), pid) {
+            (Some(machine), Some(pid)) if pid > 0 && machine == gitrepo::machine_name() => {
+                // Signal 0 only checks whether the process exists
+                let signalled = unsafe { libc::kill(pid, 0) };
+                signalled != 0
+                    && std::io::Error::last_os_error().raw_os_error() == Some(libc::ESRCH)
+            }
+            _ => false,
+        }
+    }
+}
+impl Drop for SaveDBLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
 impl SaveDB {
+    /// Current format version
+    pub const VERSION: u64 = 1;
+    /// Migrations from each older format version to the next, starting with 0
+    const MIGRATIONS: &'static [Migration] = &[from_unversioned];
+    /// Loads a savedb, migrating it from older format versions, or creates an empty one if there isn't one yet
     pub fn load<N>(path: N) -> Result<SaveDB, std::io::Error>
     where
         N: Into<PathBuf>,
     {
         let path = path.into();
-        let mut unparsed = String::new();
-        let file = fs::File::open(path.clone());
-        if file.is_ok() {
-            file.unwrap().read_to_string(&mut unparsed)?;
-            Ok(serde_json::from_str(&unparsed).unwrap())
-        } else {
-            let default = SaveDB::default();
-            default.store(path)?;
-            Ok(default)
+        match fs::read_to_string(&path) {
+            Ok(unparsed) => SaveDB::parse(&unparsed).map_err(|err| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("{} is invalid: {}", path.display(), err),
+                )
+            }),
+            Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => {
+                let default = SaveDB::default();
+                default.store(path)?;
+                Ok(default)
+            }
+            Err(err) => Err(err),
         }
     }
+    fn parse(unparsed: &str) -> Result<SaveDB, String> {
+        let mut savedb: serde_json::Value =
+            serde_json::from_str(unparsed).map_err(|err| err.to_string())?;
+        let version = match savedb.get("version") {
+            Some(version) => version
+                .as_u64()
+                .ok_or_else(|| "its version is not a number".to_string())?,
+            None => 0,
+        };
+        if version > SaveDB::VERSION {
+            return Err(format!(
+                "it has format version {}, which only newer versions of wyvern can read",
+                version
+            ));
+        }
+        for migration in &SaveDB::MIGRATIONS[version as usize..] {
+            migration(&mut savedb)?;
+        }
+        serde_json::from_value(savedb).map_err(|err| err.to_string())
+    }
+    /// Writes a savedb, replacing the old one only once it's fully written
+    fn write(&self, path: &Path) -> Result<(), std::io::Error> {
+        let to_write = serde_json::to_string_pretty(&self).unwrap();
+        let mut name = path.file_name().unwrap().to_os_string();
+        name.push(".wyvern-tmp");
+        let temp = path.with_file_name(name);
+        let mut file = File::create(&temp)?;
+        file.write_all(to_write.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&temp, path)
+    }
     pub fn store<N>(&self, path: N) -> Result<&SaveDB, std::io::Error>
     where
         N: Into<PathBuf>,
     {
-        let to_write = serde_json::to_string(&self).unwrap();
-        OpenOptions::new()
-            .create(true)
-            .write(true)
-            .truncate(true)
-            .open(path.into())?
+        let path = path.into();
+        let _lock = SaveDBLock::acquire(&path)?;
+        self.write(&path)?;
+        Ok(self)
+    }
+    /// Changes the savedb at `path` while it's locked, so that changes other machines made since it was loaded aren't lost
+    pub fn update<N, F>(path: N, change: F) -> Result<SaveDB, std::io::Error>
+    where
+        N: Into<PathBuf>,
+        F: FnOnce(&mut SaveDB),
+    {
+        let path = path.into();
+        let _lock = SaveDBLock::acquire(&path)?;
+        let mut savedb = if path.exists() {
+            SaveDB::load(&path)?
+        } else {
+            SaveDB::default()
+        };
+        change(&mut savedb);
+        savedb.write(&path)?;
+        Ok(savedb)
+    }
+}
+/// How to start an installed windows game, recorded in its install directory at install time
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LaunchConfig {
+    pub name: String,
+    pub game_id: Option<String>,
+    pub wine: String,
+    /// Dedicated wine prefix, or None to use wine's default one
+    pub prefix: Option<PathBuf>,
+    /// Main executable, relative to the install directory
+    pub exe: PathBuf,
+    pub arguments: Option<String>,
+    /// Directory to run the game from, relative to the install directory
+    pub working_dir: Option<PathBuf>,
+}
+impl LaunchConfig {
+    pub const FILE_NAME: &'static str = "wyvern-launch.json";
+    pub fn load(game_dir: impl Into<PathBuf>) -> Result<LaunchConfig, std::io::Error> {
+        let mut unparsed = String::new();
+        File::open(game_dir.into().join(LaunchConfig::FILE_NAME))?
+            .read_to_string(&mut unparsed)?;
+        serde_json::from_str(&unparsed)
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))
+    }
+    pub fn store(&self, game_dir: impl Into<PathBuf>) -> Result<&LaunchConfig, std::io::Error> {
+        let to_write = serde_json::to_string_pretty(&self).unwrap();
+        File::create(game_dir.into().join(LaunchConfig::FILE_NAME))?
             .write_all(to_write.as_bytes())?;
         Ok(self)
     }
 }
+/// A game wyvern has installed, and how the user wants it run
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct InstalledGame {
+    pub name: String,
+    pub path: PathBuf,
+    pub windows: bool,
+    /// Extra environment variables, as KEY=VALUE
+    #[serde(default)]
+    pub env: Vec<String>,
+    /// Commands the game is run through, outermost first
+    #[serde(default)]
+    pub wrappers: Vec<String>,
+}
+/// Every game wyvern has installed, so they can be found by name
+#[derive(Serialize, Deserialize, Default)]
+pub struct InstallRegistry {
+    pub games: Vec<InstalledGame>,
+}
+impl InstallRegistry {
+    pub fn path() -> Result<PathBuf, std::io::Error> {
+        confy::get_configuration_file_path("wyvern", "wyvern")
+            .map(|x| x.with_file_name("installs.json"))
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::NotFound, err))
+    }
+    pub fn load() -> Result<InstallRegistry, std::io::Error> {
+        match fs::read_to_string(InstallRegistry::path()?) {
+            Ok(unparsed) => serde_json::from_str(&unparsed)
+                .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err)),
+            Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => {
+                Ok(InstallRegistry::default())
+            }
+            Err(err) => Err(err),
+        }
+    }
+    pub fn store(&self) -> Result<&InstallRegistry, std::io::Error> {
+        let path = InstallRegistry::path()?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(&self).unwrap())?;
+        Ok(self)
+    }
+    /// Adds a game, replacing whatever was installed at the same path before
+    pub fn add(&mut self, game: InstalledGame) {
+        if let Some(existing) = self.games.iter_mut().find(|x| x.path == game.path) {
+            existing.name = game.name;
+            existing.windows = game.windows;
+        } else {
+            self.games.push(game);
+        }
+    }
+    /// Finds a game by install path, exact name, or a name fragment that only one game matches
+    pub fn find(&self, game: &str) -> Option<&InstalledGame> {
+        let lower = game.to_lowercase();
+        let path = Path::new(game).canonicalize().ok();
+        if let Some(found) = self
+            .games
+            .iter()
+            .find(|x| Some(&x.path) == path.as_ref() || x.name.to_lowercase() == lower)
+        {
+            return Some(found);
+        }
+        let mut matches = self
+            .games
+            .iter()
+            .filter(|x| x.name.to_lowercase().contains(&lower));
+        match (matches.next(), matches.next()) {
+            (Some(found), None) => Some(found),
+            _ => None,
+        }
+    }
+}
 pub struct GameInfo {
     pub version: String,
     pub name: String,
@@ -137,3 +475,88 @@ impl Game {
         }
     }
 }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn migrates_unversioned_savedbs() {
+        let savedb =
+            SaveDB::parse(r#"{"saves": {"1": {"identifier": {"GOG": 1}, "path": "~/saves"}}}"#)
+                .unwrap();
+        assert_eq!(savedb.version, SaveDB::VERSION);
+        assert_eq!(savedb.saves["1"].path, "~/saves");
+        assert!(SaveDB::parse("{}").unwrap().saves.is_empty());
+    }
+    #[test]
+    fn reads_current_savedbs() {
+        let unparsed = serde_json::to_string(&SaveDB::default()).unwrap();
+        assert_eq!(SaveDB::parse(&unparsed).unwrap().version, SaveDB::VERSION);
+    }
+    #[test]
+    fn rejects_newer_savedbs() {
+        let unparsed = format!(r#"{{"version": {}, "saves": {{}}}}"#, SaveDB::VERSION + 1);
+        let err = SaveDB::parse(&unparsed).err().unwrap();
+        assert!(err.contains("newer versions of wyvern"));
+        assert!(SaveDB::parse(r#"{"version": "1", "saves": {}}"#).is_err());
+    }
+    #[test]
+    fn rejects_invalid_savedbs() {
+        assert!(SaveDB::parse("").is_err());
+        assert!(SaveDB::parse(r#"{"saves": "#).is_err());
+        assert!(SaveDB::parse("[]").is_err());
+        assert!(SaveDB::parse(r#"{"version": 1, "saves": []}"#).is_err());
+    }
+    /// The pid of a process that has exited
+    fn exited_pid() -> u32 {
+        let mut child = std::process::Command::new("true").spawn().unwrap();
+        child.wait().unwrap();
+        child.id()
+    }
+    #[test]
+    fn finds_abandoned_locks() {
+        let machine = gitrepo::machine_name();
+        let elsewhere = format!("{}-elsewhere", machine);
+        let abandoned =
+            |machine: &str, pid: u32| SaveDBLock::abandoned(&format!("{} {}", machine, pid));
+        assert!(abandoned(&machine, exited_pid()));
+        assert!(!abandoned(&machine, std::process::id()));
+        assert!(!abandoned(&elsewhere, exited_pid()));
+        assert!(!abandoned(&machine, 0));
+        assert!(!SaveDBLock::abandoned(""));
+    }
+    #[test]
+    fn takes_over_abandoned_locks() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("savedb.json");
+        let lock = dir.path().join("savedb.json.lock");
+        let holder = format!("{} {}", gitrepo::machine_name(), exited_pid());
+        fs::write(&lock, holder).unwrap();
+        let started = Instant::now();
+        let acquired = SaveDBLock::acquire(&path).unwrap();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        let holder = fs::read_to_string(&lock).unwrap();
+        assert!(holder.ends_with(&format!(" {}", std::process::id())));
+        drop(acquired);
+        assert!(!lock.exists());
+    }
+    #[test]
+    fn resolves_the_wine_of_accounts() {
+        let mut config = Config {
+            wine: Some("wine-staging".to_string()),
+            ..Config::default()
+        };
+        config
+            .profiles
+            .insert("work".to_string(), Account::default());
+        let own = Account {
+            wine: Some("wine64".to_string()),
+            ..Account::default()
+        };
+        config.profiles.insert("play".to_string(), own);
+        let wine = |profile| config.account(profile).unwrap().wine;
+        assert_eq!(wine(None), Some("wine-staging".to_string()));
+        assert_eq!(wine(Some("work")), Some("wine-staging".to_string()));
+        assert_eq!(wine(Some("play")), Some("wine64".to_string()));
+        assert!(config.account(Some("missing")).is_err());
+    }
+}
@@ -0,0 +1,71 @@
+/// Encryption with keys derived from a passphrase, shared by encrypted save archives and the encrypted token store
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use std::io;
+pub const SALT_LEN: usize = 16;
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 24;
+/// A key derived from a passphrase and a salt
+#[derive(Clone)]
+pub struct Key {
+    pub salt: [u8; SALT_LEN],
+    key: [u8; KEY_LEN],
+}
+impl Key {
+    /// Derives a key with a new random salt
+    pub fn generate(passphrase: &str) -> Result<Key, io::Error> {
+        let mut salt = [0; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        Key::derive(passphrase, &salt)
+    }
+    pub fn derive(passphrase: &str, salt: &[u8]) -> Result<Key, io::Error> {
+        let mut key = Key {
+            salt: [0; SALT_LEN],
+            key: [0; KEY_LEN],
+        };
+        if salt.len() != SALT_LEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid salt"));
+        }
+        key.salt.copy_from_slice(salt);
+        argon2::Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), salt, &mut key.key)
+            .map_err(|err| io::Error::other(err.to_string()))?;
+        Ok(key)
+    }
+    /// Writes the salt and the key, so that it can be used again without deriving it
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.salt.iter().chain(self.key.iter()).cloned().collect()
+    }
+    pub fn from_bytes(bytes: &[u8]) -> Option<Key> {
+        if bytes.len() != SALT_LEN + KEY_LEN {
+            return None;
+        }
+        let mut key = Key {
+            salt: [0; SALT_LEN],
+            key: [0; KEY_LEN],
+        };
+        key.salt.copy_from_slice(&bytes[..SALT_LEN]);
+        key.key.copy_from_slice(&bytes[SALT_LEN..]);
+        Some(key)
+    }
+    /// Encrypts data. The output is the nonce and then the ciphertext.
+    pub fn encrypt(&self, data: &[u8]) -> Result<Vec<u8>, io::Error> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = XChaCha20Poly1305::new(&self.key.into())
+            .encrypt(&nonce, data)
+            .map_err(|_| io::Error::other("Could not encrypt"))?;
+        let mut encrypted = nonce.to_vec();
+        encrypted.extend(ciphertext);
+        Ok(encrypted)
+    }
+    /// Decrypts what encrypt wrote. Returns None if the data was encrypted with another key or changed since.
+    pub fn decrypt(&self, data: &[u8]) -> Option<Vec<u8>> {
+        if data.len() < NONCE_LEN {
+            return None;
+        }
+        XChaCha20Poly1305::new(&self.key.into())
+            .decrypt(XNonce::from_slice(&data[..NONCE_LEN]), &data[NONCE_LEN..])
+            .ok()
+    }
+}
@@ -0,0 +1,356 @@
+/// Built-in file syncing for save files, so that syncing works without rsync. rsync can still be used instead by setting sync_backend = "rsync" in the config.
+use config::*;
+use hashing;
+use std::fmt;
+use std::fs::{self, File};
+use std::io;
+use std::os::unix::fs::{symlink, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use walkdir::WalkDir;
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    #[default]
+    Builtin,
+    Rsync,
+}
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Options {
+    /// Delete files at the destination that aren't at the source
+    pub delete: bool,
+    /// Compare file contents instead of sizes and modification times
+    pub checksum: bool,
+    pub backend: Backend,
+}
+impl Options {
+    /// Options with the backend picked in the config
+    pub fn configured(delete: bool, checksum: bool) -> Options {
+        Options {
+            delete,
+            checksum,
+            backend: confy::load::<Config>("wyvern", "wyvern")
+                .map(|x| x.sync_backend)
+                .unwrap_or_default(),
+        }
+    }
+}
+/// What a sync did
+#[derive(Default, Debug)]
+pub struct Summary {
+    pub copied: usize,
+    pub skipped: usize,
+    pub deleted: usize,
+    pub bytes: u64,
+}
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} copied ({} bytes), {} unchanged, {} deleted",
+            self.copied, self.bytes, self.skipped, self.deleted
+        )
+    }
+}
+/// Makes `to` a copy of `from`. If `from` is a directory, `to` becomes a directory with the same contents; if it's a file, `to` becomes the same file.
+pub fn sync(from: &Path, to: &Path, options: &Options) -> Result<Summary, io::Error> {
+    if fs::symlink_metadata(from).is_err() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} doesn't exist", from.display()),
+        ));
+    }
+    match options.backend {
+        Backend::Builtin => sync_builtin(from, to, options),
+        Backend::Rsync => sync_rsync(from, to, options),
+    }
+}
+fn sync_builtin(from: &Path, to: &Path, options: &Options) -> Result<Summary, io::Error> {
+    let mut summary = Summary::default();
+    if !from.is_dir() {
+        if let Some(parent) = to.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        sync_entry(from, to, options, &mut summary)?;
+        return Ok(summary);
+    }
+    fs::create_dir_all(to)?;
+    for entry in WalkDir::new(from).min_depth(1).sort_by_file_name() {
+        let entry = entry.map_err(io::Error::from)?;
+        let relative = entry.path().strip_prefix(from).unwrap();
+        sync_entry(entry.path(), &to.join(relative), options, &mut summary)?;
+    }
+    if options.delete {
+        delete_extra(from, to, &mut summary)?;
+    }
+    // Directory permissions go last, in case any of them are read-only
+    for entry in WalkDir::new(from).contents_first(true) {
+        let entry = entry.map_err(io::Error::from)?;
+        if entry.file_type().is_dir() {
+            let relative = entry.path().strip_prefix(from).unwrap();
+            fs::set_permissions(
+                to.join(relative),
+                entry.metadata().map_err(io::Error::from)?.permissions(),
+            )?;
+        }
+    }
+    Ok(summary)
+}
+/// Copies one file, directory or symlink, unless the destination is already the same
+fn sync_entry(
+    from: &Path,
+    to: &Path,
+    options: &Options,
+    summary: &mut Summary,
+) -> Result<(), io::Error> {
+    let meta = fs::symlink_metadata(from)?;
+    let existing = fs::symlink_metadata(to).ok();
+    if meta.is_dir() {
+        match existing {
+            Some(ref existing) if existing.is_dir() => {}
+            Some(_) => {
+                fs::remove_file(to)?;
+                fs::create_dir(to)?;
+            }
+            None => fs::create_dir(to)?,
+        }
+        return Ok(());
+    }
+    if let Some(ref existing) = existing {
+        if existing.is_dir() {
+            fs::remove_dir_all(to)?;
+        } else if unchanged(from, &meta, to, existing, options)? {
+            summary.skipped += 1;
+            return Ok(());
+        }
+    }
+    info!("Copying {} to {}", from.display(), to.display());
+    if meta.file_type().is_symlink() {
+        if fs::symlink_metadata(to).is_ok() {
+            fs::remove_file(to)?;
+        }
+        symlink(fs::read_link(from)?, to)?;
+    } else {
+        // Copy next to the destination first so that an interrupted sync never leaves a half-written save behind
+        let temp = temp_path(to);
+        fs::copy(from, &temp)?;
+        File::open(&temp)?.set_modified(meta.modified()?)?;
+        fs::set_permissions(&temp, fs::Permissions::from_mode(meta.permissions().mode()))?;
+        fs::rename(&temp, to)?;
+        summary.bytes += meta.len();
+    }
+    summary.copied += 1;
+    Ok(())
+}
+/// Where a file is written before it's renamed into place
+pub fn temp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap().to_os_string();
+    name.push(".wyvern-tmp");
+    path.with_file_name(name)
+}
+fn unchanged(
+    from: &Path,
+    from_meta: &fs::Metadata,
+    to: &Path,
+    to_meta: &fs::Metadata,
+    options: &Options,
+) -> Result<bool, io::Error> {
+    if from_meta.file_type().is_symlink() || to_meta.file_type().is_symlink() {
+        return Ok(from_meta.file_type().is_symlink()
+            && to_meta.file_type().is_symlink()
+            && fs::read_link(from)? == fs::read_link(to)?);
+    }
+    if from_meta.len() != to_meta.len() {
+        return Ok(false);
+    }
+    if options.checksum {
+        Ok(hashing::sha1_file(from)? == hashing::sha1_file(to)?)
+    } else {
+        Ok(from_meta.modified()? == to_meta.modified()?)
+    }
+}
+/// Removes everything under `to` that has no counterpart under `from`
+fn delete_extra(from: &Path, to: &Path, summary: &mut Summary) -> Result<(), io::Error> {
+    let mut walker = WalkDir::new(to).min_depth(1).into_iter();
+    while let Some(entry) = walker.next() {
+        let entry = entry.map_err(io::Error::from)?;
+        let relative = entry.path().strip_prefix(to).unwrap();
+        if fs::symlink_metadata(from.join(relative)).is_ok() {
+            continue;
+        }
+        info!("Deleting {}", entry.path().display());
+        if entry.file_type().is_dir() {
+            walker.skip_current_dir();
+            summary.deleted += WalkDir::new(entry.path())
+                .into_iter()
+                .filter_map(|x| x.ok())
+                .filter(|x| !x.file_type().is_dir())
+                .count();
+            fs::remove_dir_all(entry.path())?;
+        } else {
+            summary.deleted += 1;
+            fs::remove_file(entry.path())?;
+        }
+    }
+    Ok(())
+}
+fn sync_rsync(from: &Path, to: &Path, options: &Options) -> Result<Summary, io::Error> {
+    let (from_arg, to_arg) = if from.is_dir() {
+        fs::create_dir_all(to)?;
+        (format!("{}/", from.display()), format!("{}/", to.display()))
+    } else {
+        if let Some(parent) = to.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        (from.display().to_string(), to.display().to_string())
+    };
+    let mut command = Command::new("rsync");
+    command.arg("-a").arg("--out-format=%o %n");
+    if options.delete {
+        command.arg("--delete");
+    }
+    if options.checksum {
+        command.arg("--checksum");
+    }
+    info!("Running {:?}", command);
+    let output = command.arg(from_arg).arg(to_arg).output().map_err(|err| {
+        io::Error::new(
+            err.kind(),
+            format!("Could not run rsync. Is it installed? {}", err),
+        )
+    })?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "rsync failed with {}. Stderr: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr)
+        )));
+    }
+    let mut summary = Summary::default();
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        if line.starts_with("del.") {
+            summary.deleted += 1;
+        } else if !line.ends_with('/') {
+            summary.copied += 1;
+        }
+    }
+    Ok(summary)
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+    fn write(path: &Path, contents: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+    fn read(path: &Path) -> String {
+        fs::read_to_string(path).unwrap()
+    }
+    fn mode(path: &Path) -> u32 {
+        fs::metadata(path).unwrap().permissions().mode() & 0o777
+    }
+    fn delete() -> Options {
+        Options {
+            delete: true,
+            ..Default::default()
+        }
+    }
+    #[test]
+    fn copies_times_and_permissions() {
+        let dir = tempfile::tempdir().unwrap();
+        let (from, to) = (dir.path().join("from"), dir.path().join("to"));
+        write(&from.join("slot1.sav"), "one");
+        write(&from.join("profiles/me.cfg"), "me");
+        let modified = SystemTime::now() - Duration::from_secs(3600);
+        File::open(from.join("slot1.sav"))
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        fs::set_permissions(from.join("slot1.sav"), fs::Permissions::from_mode(0o600)).unwrap();
+        fs::set_permissions(from.join("profiles"), fs::Permissions::from_mode(0o700)).unwrap();
+        let summary = sync_builtin(&from, &to, &Options::default()).unwrap();
+        assert_eq!((summary.copied, summary.bytes), (2, 5));
+        assert_eq!(read(&to.join("slot1.sav")), "one");
+        assert_eq!(read(&to.join("profiles/me.cfg")), "me");
+        let copied = fs::metadata(to.join("slot1.sav")).unwrap();
+        assert_eq!(copied.modified().unwrap(), modified);
+        assert_eq!(mode(&to.join("slot1.sav")), 0o600);
+        assert_eq!(mode(&to.join("profiles")), 0o700);
+        assert!(!temp_path(&to.join("slot1.sav")).exists());
+    }
+    #[test]
+    fn skips_unchanged_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let (from, to) = (dir.path().join("from"), dir.path().join("to"));
+        write(&from.join("slot1.sav"), "one");
+        write(&from.join("slot2.sav"), "two");
+        sync_builtin(&from, &to, &Options::default()).unwrap();
+        let summary = sync_builtin(&from, &to, &Options::default()).unwrap();
+        assert_eq!((summary.copied, summary.skipped), (0, 2));
+        write(&from.join("slot2.sav"), "changed");
+        let summary = sync_builtin(&from, &to, &Options::default()).unwrap();
+        assert_eq!((summary.copied, summary.skipped), (1, 1));
+        assert_eq!(read(&to.join("slot2.sav")), "changed");
+        // Same size and time, different contents: only a checksum notices
+        write(&to.join("slot1.sav"), "eno");
+        let modified = fs::metadata(from.join("slot1.sav"))
+            .unwrap()
+            .modified()
+            .unwrap();
+        File::open(to.join("slot1.sav"))
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        let summary = sync_builtin(&from, &to, &Options::default()).unwrap();
+        assert_eq!(summary.copied, 0);
+        let checksum = Options {
+            checksum: true,
+            ..Default::default()
+        };
+        let summary = sync_builtin(&from, &to, &checksum).unwrap();
+        assert_eq!(summary.copied, 1);
+        assert_eq!(read(&to.join("slot1.sav")), "one");
+    }
+    #[test]
+    fn deletes_extra_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let (from, to) = (dir.path().join("from"), dir.path().join("to"));
+        write(&from.join("slot1.sav"), "one");
+        write(&to.join("old.sav"), "old");
+        write(&to.join("old/a.sav"), "a");
+        write(&to.join("old/b.sav"), "b");
+        let summary = sync_builtin(&from, &to, &Options::default()).unwrap();
+        assert_eq!(summary.deleted, 0);
+        assert!(to.join("old.sav").exists());
+        let summary = sync_builtin(&from, &to, &delete()).unwrap();
+        assert_eq!(summary.deleted, 3);
+        assert!(!to.join("old.sav").exists());
+        assert!(!to.join("old").exists());
+        assert_eq!(read(&to.join("slot1.sav")), "one");
+    }
+    #[test]
+    fn replaces_files_and_directories() {
+        let dir = tempfile::tempdir().unwrap();
+        let (from, to) = (dir.path().join("from"), dir.path().join("to"));
+        write(&from.join("profiles/me.cfg"), "me");
+        write(&from.join("slot1"), "one");
+        write(&to.join("profiles"), "was a file");
+        write(&to.join("slot1/old.sav"), "was a directory");
+        sync_builtin(&from, &to, &delete()).unwrap();
+        assert_eq!(read(&to.join("profiles/me.cfg")), "me");
+        assert_eq!(read(&to.join("slot1")), "one");
+    }
+    #[test]
+    fn syncs_single_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let from = dir.path().join("game.sav");
+        let to = dir.path().join("sync/game.sav");
+        write(&from, "saved");
+        let summary = sync_builtin(&from, &to, &delete()).unwrap();
+        assert_eq!(summary.copied, 1);
+        assert_eq!(read(&to), "saved");
+        let summary = sync_builtin(&from, &to, &delete()).unwrap();
+        assert_eq!(summary.skipped, 1);
+    }
+}
@@ -49,11 +49,8 @@ pub mod update {
                         .progress_chars("#>-"),
                 );
                     data.files.par_iter().for_each(|file| {
-                        if !file.filename.contains("meta") && !file.filename.contains("scripts") {
-                            let path = game_info_path
-                                .parent()
-                                .unwrap()
-                                .join(&file.filename.replace("/noarch", "").replace("data/", ""));
+                        if let Some(relative) = mojosetup::game_path(&file.filename) {
+                            let path = game_info_path.parent().unwrap().join(relative);
                             let is_dir = path.extension().is_none()
                                 || file.filename.clone().pop().unwrap() == '/';
                             if path.is_file() {
@@ -172,7 +169,7 @@ pub mod download {
         } else {
             if !options.windows_auto && !options.windows_force {
                 info!("Asking user about downloading windows version");
-                if Confirm::new().with_prompt("This game does not support linux! Would you like to download the windows version to run under wine?").interact().unwrap() {
+                if prompt::confirm("This game does not support linux! Would you like to download the windows version to run under wine?", false) {
                 println!("Downloading windows files. Note: wyvern does not support automatic installation from windows games");
                 info!("Downloading windows downloads");
                 let name;
@@ -182,6 +179,7 @@ pub mod download {
                 } else {
                     name = download(gog, details.downloads.windows.unwrap(), options).unwrap();
                 }
+                check_downloaded_parts(&name)?;
                 return Ok((name, true));
 
             } else {
@@ -200,10 +198,22 @@ pub mod download {
                 } else {
                     name = download(gog, details.downloads.windows.unwrap(), options).unwrap();
                 }
+                check_downloaded_parts(&name)?;
                 return Ok((name, true));
             }
         }
     }
+    /// Checks that windows installers aren't missing .bin parts after downloading, so that an incomplete set isn't installed
+    fn check_downloaded_parts(names: &[String]) -> Result<(), Error> {
+        for name in names
+            .iter()
+            .filter(|x| !is_part(x) && Path::new(x).is_file())
+        {
+            check_parts(Path::new(name))
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        }
+        Ok(())
+    }
     pub fn download(
         gog: &Gog,
         downloads: Vec<gog::gog::Download>,
@@ -216,6 +226,8 @@ pub mod download {
         }
         info!("Getting responses to requests");
         let count = downloads.len();
+        // Original name and chosen path of the last installer exe, which any .bin parts after it are named after
+        let mut installer_set: Option<(String, PathBuf)> = None;
         for idx in 0..count {
             let ref tok = gog.token.borrow();
             if tok.is_expired() {
@@ -252,9 +264,10 @@ pub mod download {
                         .to_string();
                 let final_name_path = PathBuf::from(&final_name);
                 if options.original {
-                    name = final_name;
+                    name = final_name.clone();
                 }
-                if let Some(output) = options.output.clone() {
+                if let Some(output) = options.output.as_ref() {
+                    let output = paths::expand(&output.to_string_lossy());
                     if output.is_dir() {
                         name = output
                             .join(PathBuf::from(&name))
@@ -270,19 +283,34 @@ pub mod download {
                         name = name + "." + &extension.to_string_lossy().to_string();
                     }
                 }
+                // Installers find their .bin parts by the exe's name, so parts have to be saved next to the exe and named after it
+                if let Some((ref exe_stem, ref exe_path)) = installer_set {
+                    if is_part(&final_name) && final_name.starts_with(exe_stem.as_str()) {
+                        let stem = exe_path.file_stem().unwrap().to_string_lossy().to_string();
+                        name = exe_path
+                            .with_file_name(stem + &final_name[exe_stem.len()..])
+                            .to_str()
+                            .unwrap()
+                            .to_string();
+                    }
+                }
+                if !is_part(&final_name) {
+                    let stem = final_name_path.file_stem().unwrap().to_string_lossy().to_string();
+                    installer_set = Some((stem, PathBuf::from(&name)));
+                }
                 let _i = 1;
                 let name_path = PathBuf::from(&name);
                 let filename = name_path.file_name().unwrap().to_str().unwrap().to_string();
+                name = name_path.to_str().unwrap().to_string();
+                names[idx] = name.clone();
                 if name_path.exists() {
                     error!("A file named {} already exists. Skipping this file.", filename);
                     continue;
                 }
-                name = name_path.to_str().unwrap().to_string();
-                names[idx] = name.clone();
                 let temp_name = name.clone() + ".tmp";
                 if options.resume {
                     if let Ok(meta) = fs::metadata(&temp_name) {
-                        if meta.len() >= total_size {
+                        if meta.len() < total_size {
                             println!("Resuming {}, {} of {}", name, idx + 1, count);
                             pb.set_position(meta.len());
                             let fd = OpenOptions::new().append(true).open(&temp_name)?;
@@ -299,6 +327,7 @@ pub mod download {
                             )?;
                             let fd_ref = result.get_mut();
                             fd_ref.pb.take().unwrap().finish();
+                            fs::rename(&temp_name, &name)?;
                             continue;
                         } else {
                             error!("This file is larger than or equal to the total size of the file. Not downloading anything.");
@@ -341,13 +370,11 @@ pub mod install {
         shortcut_opts: &ShortcutOptions,
         windows: bool,
         external_zip: bool,
+        wine_opts: &WineOptions,
     ) {
-        for name in names
-            .iter()
-            .filter(|x| if windows { x.contains("exe") } else { true })
-        {
+        for installer in names.iter().filter(|x| !windows || !is_part(x)) {
             install(
-                name.as_str(),
+                installer.as_str(),
                 path.clone(),
                 name.clone(),
                 &ShortcutOptions {
@@ -357,10 +384,61 @@ pub mod install {
                 },
                 windows,
                 external_zip,
+                wine_opts,
             );
         }
         shortcuts(&name, path.as_path(), shortcut_opts);
     }
+    /// Whether a file is one of the .bin parts of a multi-part windows installer
+    pub fn is_part(name: &str) -> bool {
+        Path::new(name)
+            .extension()
+            .map(|x| x.eq_ignore_ascii_case("bin"))
+            .unwrap_or(false)
+    }
+    /// Checks that all the .bin parts a windows installer needs are next to it and complete
+    pub fn check_parts(installer: &Path) -> Result<(), String> {
+        match inno::Installer::open(installer) {
+            Ok(setup) => setup.check_parts().map_err(|err| err.to_string()),
+            Err(inno::InnoError::Unsupported(_)) => check_numbered_parts(installer),
+            Err(err) => Err(err.to_string()),
+        }
+    }
+    /// For installers wyvern can't read, the most that can be checked is that there are no gaps in the numbered parts
+    fn check_numbered_parts(installer: &Path) -> Result<(), String> {
+        let stem = installer
+            .file_stem()
+            .map(|x| x.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let dir = match installer.parent() {
+            Some(dir) if dir != Path::new("") => dir,
+            _ => Path::new("."),
+        };
+        let prefix = format!("{}-", stem);
+        let mut numbers: Vec<u32> = fs::read_dir(dir)
+            .map(|entries| {
+                entries
+                    .filter_map(|x| x.ok())
+                    .filter_map(|x| {
+                        let name = x.file_name().to_string_lossy().to_string();
+                        name.strip_prefix(prefix.as_str())?
+                            .strip_suffix(".bin")?
+                            .parse()
+                            .ok()
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        numbers.sort_unstable();
+        let last = numbers.last().cloned().unwrap_or(0);
+        match (1..=last).find(|x| !numbers.contains(x)) {
+            Some(missing) => Err(format!(
+                "Installer part {}{}.bin ({} of at least {}) is missing",
+                prefix, missing, missing, last
+            )),
+            None => Ok(()),
+        }
+    }
     pub fn install(
         installer: impl Into<String>,
         path: PathBuf,
@@ -368,16 +446,41 @@ pub mod install {
         shortcut_opts: &ShortcutOptions,
         windows: bool,
         external_zip: bool,
+        wine_opts: &WineOptions,
     ) {
         info!("Starting installer extraction process");
+        let installer: String = installer.into();
         if windows {
+            info!("Checking installer parts");
+            if let Err(err) = check_parts(Path::new(&installer)) {
+                error!("{}", err);
+                std::process::exit(64);
+            }
+            info!("Extracting windows game");
+            match inno::Installer::open(&installer).and_then(|x| x.extract(&path)) {
+                Ok(()) => {
+                    info!("Extracted windows game without innoextract");
+                    wine::setup(&path, &name, wine_opts.wine.clone(), wine_opts.prefix);
+                    launch::register(&path, &name, true);
+                    return;
+                }
+                Err(inno::InnoError::Unsupported(reason)) => {
+                    warn!("Can't extract this installer without innoextract: {}", reason);
+                }
+                Err(inno::InnoError::Io(err)) => {
+                    warn!(
+                        "Could not extract installer without innoextract. Error: {}",
+                        err
+                    );
+                }
+            }
             info!("Extracting windows game using innoextract");
             let output = Command::new("innoextract")
                 .arg("--exclude-temp")
                 .arg("--gog")
                 .arg("--output-dir")
                 .arg(path.to_str().expect("Couldn't convert path to string"))
-                .arg(installer.into())
+                .arg(&installer)
                 .output();
             if let Ok(output) = output {
                 if output.status.success() {
@@ -388,6 +491,8 @@ pub mod install {
                         fs::remove_dir_all(&path).expect("Couldn't remove old folder");
                         fs::rename("tmp", &path).expect("Couldn't rename tmp folder");
                     }
+                    wine::setup(&path, &name, wine_opts.wine.clone(), wine_opts.prefix);
+                    launch::register(&path, &name, true);
                     return;
                 } else {
                     error!("Could not run innoextract. Are you sure it's installed and in $PATH?");
@@ -419,21 +524,13 @@ pub mod install {
                     if output.status.success() {
                         info!("Unzip command succeeded. Beginning path processing.");
                         for entry in WalkDir::new("tmp").into_iter().filter_map(|e| e.ok()) {
-                            let new_path = path
-                                .join(
-                                    entry
-                                        .path()
-                                        .strip_prefix(Path::new("tmp"))
-                                        .expect("Couldn't strip path")
-                                        .to_str()
-                                        .unwrap()
-                                        .replace("/noarch", "")
-                                        .replace("data/", "")
-                                        .to_owned(),
-                                )
-                                .to_path_buf();
-                            let str_new = format!("{}", new_path.clone().to_str().unwrap());
-                            if !str_new.contains("meta") && !str_new.contains("scripts") {
+                            let relative = entry
+                                .path()
+                                .strip_prefix(Path::new("tmp"))
+                                .expect("Couldn't strip path");
+                            if let Some(mapped) = mojosetup::game_path(relative) {
+                                let new_path = path.join(mapped);
+                                let str_new = format!("{}", new_path.clone().to_str().unwrap());
                                 if str_new.ends_with("/") {
                                     info!("Creating dir");
                                     fs::create_dir_all(new_path)
@@ -456,6 +553,7 @@ pub mod install {
                             }
                         }
                         fs::remove_dir_all("tmp").expect("Could not remove temp directory");
+                        launch::register(&path, &name, false);
                     } else {
                         error!(
                             "Unzip command failed.\n Stdout: {:?}\n Stderr: {:?}",
@@ -464,7 +562,7 @@ pub mod install {
                     }
                 }
             } else {
-                if let Ok(mut installer) = File::open(installer.into()) {
+                if let Ok(mut installer) = File::open(&installer) {
                     extract(
                         &mut installer,
                         "/tmp",
@@ -497,16 +595,9 @@ pub mod install {
                         .unwrap();
                         info!("Getting file from archive");
                         let mut file = archive.by_index(i).unwrap();
-                        let filtered_path = file
-                            .mangled_name()
-                            .to_str()
-                            .unwrap()
-                            .replace("/noarch", "")
-                            .replace("data/", "")
-                            .to_owned();
                         //Extract only files for the game itself
-                        if !filtered_path.contains("meta") && !filtered_path.contains("scripts") {
-                            let outpath = path.clone().join(PathBuf::from(filtered_path));
+                        if let Some(relative) = mojosetup::game_path(file.mangled_name()) {
+                            let outpath = path.clone().join(relative);
                             if (&*file.name()).ends_with('/') {
                                 info!("Creating dir");
                                 fs::create_dir_all(&outpath).unwrap();
@@ -527,12 +618,13 @@ pub mod install {
                                     .unwrap();
                             }
                         } else {
-                            info!("File {} not being extracted", filtered_path);
+                            info!("File {} not being extracted", file.name());
                         }
                         pb.inc(1);
                     });
                     pb.finish_with_message("Game installed!");
                     shortcuts(&name, path.as_path(), shortcut_opts);
+                    launch::register(&path, &name, false);
                 } else {
                     error!("Could not open installer file");
                     return;
@@ -561,3 +653,75 @@ pub mod install {
         }
     }
 }
+pub mod mojosetup {
+    use std::path::{Component, Path, PathBuf};
+    /// Directory inside a mojosetup installer's archive that holds the game itself
+    const GAME_DATA: &str = "data/noarch";
+    /// Maps a path inside a mojosetup installer's archive to where it belongs in the install directory.
+    /// The installer's own files (meta/, scripts/) and anything that would escape the install directory map to None.
+    pub fn game_path(archive_path: impl AsRef<Path>) -> Option<PathBuf> {
+        let relative = archive_path.as_ref().strip_prefix(GAME_DATA).ok()?;
+        let mut mapped = PathBuf::new();
+        for component in relative.components() {
+            match component {
+                Component::Normal(part) => mapped.push(part),
+                Component::CurDir => {}
+                _ => return None,
+            }
+        }
+        if mapped.as_os_str().is_empty() {
+            None
+        } else {
+            Some(mapped)
+        }
+    }
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        fn mapped(path: &str) -> Option<String> {
+            game_path(path).map(|x| x.display().to_string())
+        }
+        #[test]
+        fn strips_noarch_prefix() {
+            assert_eq!(mapped("data/noarch/start.sh"), Some("start.sh".to_string()));
+            assert_eq!(
+                mapped("data/noarch/game/Hollow Knight"),
+                Some("game/Hollow Knight".to_string())
+            );
+            assert_eq!(mapped("data/noarch"), None);
+            assert_eq!(mapped("data/noarch/"), None);
+        }
+        #[test]
+        fn keeps_game_files_named_like_installer_files() {
+            assert_eq!(
+                mapped("data/noarch/game/metadata.pak"),
+                Some("game/metadata.pak".to_string())
+            );
+            assert_eq!(
+                mapped("data/noarch/game/Scripts/init.lua"),
+                Some("game/Scripts/init.lua".to_string())
+            );
+            assert_eq!(
+                mapped("data/noarch/game/scripts/meta/x.json"),
+                Some("game/scripts/meta/x.json".to_string())
+            );
+            assert_eq!(
+                mapped("data/noarch/game/data/levels/1.dat"),
+                Some("game/data/levels/1.dat".to_string())
+            );
+        }
+        #[test]
+        fn skips_installer_files() {
+            assert_eq!(mapped("meta/metadata.pak"), None);
+            assert_eq!(mapped("scripts/mojosetup_init.lua"), None);
+            assert_eq!(mapped("data/x86_64/lib.so"), None);
+        }
+        #[test]
+        fn rejects_escaping_paths() {
+            assert_eq!(mapped("data/noarch/../../etc/passwd"), None);
+            assert_eq!(mapped("data/noarch/game/../../../x"), None);
+            assert_eq!(mapped("/data/noarch/start.sh"), None);
+            assert_eq!(mapped("/etc/passwd"), None);
+        }
+    }
+}
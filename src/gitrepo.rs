@@ -0,0 +1,148 @@
+/// Sync folders kept as git repositories, so that every push is a commit and machines merge each other's changes through a shared remote
+use std::ffi::CStr;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::SystemTime;
+/// Branch that unpushed commits are moved to when they conflict with the remote
+const UNPUSHED_BRANCH: &str = "wyvern-unpushed";
+pub struct Repo {
+    path: PathBuf,
+    branch: String,
+}
+/// This machine's hostname, to tell in the history where each push came from
+pub fn machine_name() -> String {
+    let mut buf = [0 as libc::c_char; 256];
+    let named = unsafe { libc::gethostname(buf.as_mut_ptr(), buf.len()) } == 0;
+    if named {
+        let name = unsafe { CStr::from_ptr(buf.as_ptr()) };
+        if let Ok(name) = name.to_str() {
+            return name.to_string();
+        }
+    }
+    "unknown".to_string()
+}
+impl Repo {
+    fn git(&self, args: &[&str]) -> Command {
+        let mut command = Command::new("git");
+        command.arg("-C").arg(&self.path).args(args);
+        command
+    }
+    /// Runs git, failing if it does. Returns what it printed.
+    fn run(&self, args: &[&str]) -> Result<String, io::Error> {
+        let mut command = self.git(args);
+        info!("Running {:?}", command);
+        let output = command.output().map_err(|err| {
+            io::Error::new(
+                err.kind(),
+                format!("Could not run git. Is it installed? {}", err),
+            )
+        })?;
+        if !output.status.success() {
+            return Err(io::Error::other(format!(
+                "git {} failed with {}. Stderr: {}",
+                args.join(" "),
+                output.status,
+                String::from_utf8_lossy(&output.stderr)
+            )));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    }
+    /// Runs git and returns whether it succeeded
+    fn check(&self, args: &[&str]) -> Result<bool, io::Error> {
+        let output = self.git(args).output()?;
+        Ok(output.status.success())
+    }
+    fn remote_branch(&self) -> String {
+        format!("origin/{}", self.branch)
+    }
+    fn has_remote_branch(&self) -> Result<bool, io::Error> {
+        let reference = format!("refs/remotes/{}", self.remote_branch());
+        self.check(&["rev-parse", "--verify", "-q", &reference])
+    }
+    fn has_commits(&self) -> Result<bool, io::Error> {
+        self.check(&["rev-parse", "--verify", "-q", "HEAD"])
+    }
+    /// Opens the working tree at `path`, setting it up to sync with the remote at `url` if it's new, and pulls from the remote
+    pub fn open(path: &Path, url: &str, branch: &str) -> Result<Repo, io::Error> {
+        let repo = Repo {
+            path: path.to_path_buf(),
+            branch: branch.to_string(),
+        };
+        if !path.join(".git").exists() {
+            fs::create_dir_all(path)?;
+            repo.run(&["init", "-q"])?;
+            repo.run(&["remote", "add", "origin", url])?;
+            let machine = machine_name();
+            repo.run(&["config", "user.name", &format!("wyvern on {}", machine)])?;
+            repo.run(&["config", "user.email", &format!("wyvern@{}", machine)])?;
+            repo.run(&["checkout", "-q", "-B", branch])?;
+        } else {
+            repo.run(&["remote", "set-url", "origin", url])?;
+        }
+        repo.pull()?;
+        Ok(repo)
+    }
+    /// Fetches the remote and merges it in, throwing away anything a failed command left uncommitted
+    pub fn pull(&self) -> Result<(), io::Error> {
+        if self.has_commits()? {
+            self.run(&["reset", "-q", "--hard"])?;
+        }
+        self.run(&["clean", "-q", "-f", "-d"])?;
+        self.run(&["fetch", "-q", "origin"])?;
+        if !self.has_remote_branch()? {
+            return Ok(());
+        }
+        let remote = self.remote_branch();
+        if !self.has_commits()? {
+            self.run(&["reset", "-q", "--hard", &remote])?;
+        } else if !self.check(&["merge", "-q", "--no-edit", &remote])? {
+            // Local commits only exist when pushing them failed, and the saves they came from are still around to push again.
+            // They're kept on a branch anyway, for anything else they changed.
+            let _ = self.check(&["merge", "--abort"]);
+            self.run(&["branch", "-q", "-f", UNPUSHED_BRANCH])?;
+            warn!(
+                "Could not merge synced saves with unpushed local changes. Moved them to the {} branch of {}.",
+                UNPUSHED_BRANCH,
+                self.path.display()
+            );
+            self.run(&["reset", "-q", "--hard", &remote])?;
+        }
+        Ok(())
+    }
+    /// Commits everything in the working tree and pushes it. Returns how many files were added or changed, and how many deleted.
+    pub fn commit_and_push(&self, description: &str) -> Result<(usize, usize), io::Error> {
+        self.run(&["add", "-A"])?;
+        let staged = self.run(&["diff", "--cached", "--name-status"])?;
+        let deleted = staged.lines().filter(|x| x.starts_with('D')).count();
+        let changed = staged.lines().count() - deleted;
+        if changed + deleted > 0 {
+            let message = format!(
+                "{}\n\nMachine: {}\nTime: {}",
+                description,
+                machine_name(),
+                humantime::format_rfc3339_seconds(SystemTime::now())
+            );
+            self.run(&["commit", "-q", "-m", &message])?;
+        }
+        if !self.has_commits()? {
+            return Ok((0, 0));
+        }
+        let branch = format!("HEAD:refs/heads/{}", self.branch);
+        if self.check(&["push", "-q", "origin", &branch])? {
+            return Ok((changed, deleted));
+        }
+        // Another machine pushed in the meantime
+        self.run(&["fetch", "-q", "origin"])?;
+        if !self.check(&["merge", "-q", "--no-edit", &self.remote_branch()])? {
+            // The commit stays unpushed. Since the sync state of its saves isn't stored either, the next sync sees them as conflicts to resolve.
+            let _ = self.check(&["merge", "--abort"]);
+            return Err(io::Error::other(
+                "Another machine pushed conflicting saves at the same time. Sync again to resolve them.",
+            ));
+        }
+        self.run(&["push", "-q", "origin", &branch])?;
+        Ok((changed, deleted))
+    }
+}
@@ -0,0 +1,59 @@
+/// SHA-1 hashes of save files and hex encoding, shared by syncing, snapshots, remote sync locations and the token store
+use sha1::{Digest, Sha1};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|x| format!("{:02x}", x)).collect()
+}
+/// Reads what to_hex wrote. Returns None if it isn't valid hex.
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.bytes().all(|x| x.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|x| u8::from_str_radix(hex.get(x..x + 2)?, 16).ok())
+        .collect()
+}
+/// The SHA-1 hash of some data, in hex
+pub fn sha1(data: &[u8]) -> String {
+    to_hex(&Sha1::digest(data))
+}
+/// The SHA-1 hash of a file's contents, in hex, without reading it into memory at once
+pub fn sha1_file(path: &Path) -> Result<String, io::Error> {
+    let mut hasher = Sha1::new();
+    let mut file = File::open(path)?;
+    let mut buf = vec![0; 1 << 16];
+    loop {
+        let read = file.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+    }
+    Ok(to_hex(&hasher.finalize()))
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn round_trips_hex() {
+        assert_eq!(to_hex(&[0, 15, 16, 255]), "000f10ff");
+        assert_eq!(from_hex("000f10ff"), Some(vec![0, 15, 16, 255]));
+        assert_eq!(from_hex("000F10FF"), Some(vec![0, 15, 16, 255]));
+        assert_eq!(from_hex(""), Some(vec![]));
+    }
+    #[test]
+    fn rejects_invalid_hex() {
+        assert_eq!(from_hex("abc"), None);
+        assert_eq!(from_hex("zz"), None);
+        assert_eq!(from_hex("+1"), None);
+        assert_eq!(from_hex("é0"), None);
+    }
+    #[test]
+    fn hashes_with_sha1() {
+        assert_eq!(sha1(b""), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        assert_eq!(sha1(b"abc"), "a9993e364706816aba3e25717850c26c9cd0d89d");
+    }
+}
@@ -0,0 +1,1049 @@
+/// Built-in extraction of the Inno Setup installers GOG uses for windows games, so installing them doesn't require innoextract.
+/// Only the layouts GOG ships (Inno Setup 5.5 to 6.2, unicode builds) are handled. Anything else is reported as unsupported so that the caller can fall back to innoextract.
+use crc::{Crc, CRC_32_ISO_HDLC};
+use flate2::read::ZlibDecoder;
+use indicatif::{ProgressBar, ProgressStyle};
+use lzma_rust2::{Lzma2Reader, LzmaReader};
+use sha1::{Digest, Sha1};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+const CRC32: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+/// Magic at the start of the setup loader's offset table, stored as a resource in the installer exe
+const LOADER_MAGIC: &[u8] = b"rDlPtS\xcd\xe6\xd7\x7b\x0b\x2a";
+const LOADER_TABLE_LEN: usize = 44;
+/// How far into the exe to look for the offset table. The loader itself is small; the game data comes after it.
+const LOADER_SEARCH_LEN: u64 = 16 << 20;
+const SETUP_ID_LEN: usize = 64;
+const SLICE_MAGIC: &[u8] = b"idska32\x1a";
+const SLICE_HEADER_LEN: u64 = 12;
+const CHUNK_MAGIC: &[u8] = b"zlb\x1a";
+const BLOCK_CHUNK_LEN: usize = 4096;
+/// Setup header blocks are a few megabytes at most, so anything claiming to be much larger isn't one
+const MAX_BLOCK_LEN: usize = 64 << 20;
+/// Size of a MinVersion/OnlyBelowVersion pair, which most entries carry
+const VERSION_RANGE_LEN: usize = 20;
+const DATA_ENTRY_LEN: usize = 74;
+/// Files are written, checksummed and (if needed) un-filtered in blocks of this size, the same as setup.exe does
+const FILE_BLOCK_LEN: usize = 0x10000;
+const FLAG_CALL_OPTIMIZED: u16 = 1 << 4;
+const FLAG_CHUNK_ENCRYPTED: u16 = 1 << 6;
+const FLAG_CHUNK_COMPRESSED: u16 = 1 << 7;
+const FILE_TYPE_USER: u8 = 0;
+const NO_LOCATION: u32 = u32::MAX;
+#[derive(Debug)]
+pub enum InnoError {
+    /// The installer isn't in a format the built-in extractor understands
+    Unsupported(String),
+    Io(io::Error),
+}
+impl fmt::Display for InnoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InnoError::Unsupported(reason) => write!(f, "unsupported installer: {}", reason),
+            InnoError::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+impl From<io::Error> for InnoError {
+    fn from(err: io::Error) -> InnoError {
+        InnoError::Io(err)
+    }
+}
+fn unsupported<T>(reason: impl Into<String>) -> Result<T, InnoError> {
+    Err(InnoError::Unsupported(reason.into()))
+}
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+struct Version(u32, u32, u32);
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.0, self.1, self.2)
+    }
+}
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Compression {
+    Stored,
+    Zlib,
+    Lzma1,
+    Lzma2,
+}
+/// Everything read out of an installer that's needed to extract it
+pub struct Installer {
+    exe: PathBuf,
+    data_offset: u64,
+    slices_per_disk: u32,
+    compression: Compression,
+    files: Vec<FileEntry>,
+    data: Vec<DataEntry>,
+}
+struct FileEntry {
+    destination: String,
+    languages: String,
+    location: u32,
+}
+struct DataEntry {
+    first_slice: u32,
+    last_slice: u32,
+    chunk_offset: u32,
+    file_offset: u64,
+    file_size: u64,
+    chunk_size: u64,
+    sha1: [u8; 20],
+    flags: u16,
+}
+impl Installer {
+    /// Reads the setup headers of an installer, checking that every part of it is something the built-in extractor handles
+    pub fn open(exe: impl Into<PathBuf>) -> Result<Installer, InnoError> {
+        let exe = exe.into();
+        let mut fd = File::open(&exe)?;
+        let (header_offset, data_offset) = find_offsets(&mut fd)?;
+        fd.seek(SeekFrom::Start(header_offset))?;
+        let mut id = [0; SETUP_ID_LEN];
+        fd.read_exact(&mut id)?;
+        let version = parse_version(&id)?;
+        info!("Installer was built with Inno Setup {}", version);
+        let mut fd = BufReader::new(fd);
+        let headers = read_block(&mut fd, None)?;
+        let mut fields = Fields::new(&headers);
+        let header = parse_header(&mut fields, version)?;
+        for &(count, strings, fixed) in &[
+            (header.languages, 10, 21),
+            (header.messages, 2, 4),
+            (header.permissions, 1, 0),
+            (header.types, 4, VERSION_RANGE_LEN + 10),
+            (header.components, 5, VERSION_RANGE_LEN + 22),
+            (header.tasks, 6, VERSION_RANGE_LEN + 6),
+            (header.directories, 7, VERSION_RANGE_LEN + 7),
+        ] {
+            for _ in 0..count {
+                fields.skip_strings(strings)?;
+                fields.skip(fixed)?;
+            }
+        }
+        let mut files = vec![];
+        for _ in 0..header.files {
+            if let Some(file) = parse_file_entry(&mut fields, header.data_entries)? {
+                files.push(file);
+            }
+        }
+        let locations = read_block(
+            &mut fd,
+            Some(header.data_entries as u64 * DATA_ENTRY_LEN as u64),
+        )?;
+        if locations.len() != header.data_entries as usize * DATA_ENTRY_LEN {
+            return unsupported("file location table has an unexpected size");
+        }
+        let mut fields = Fields::new(&locations);
+        let mut data = vec![];
+        for _ in 0..header.data_entries {
+            let entry = parse_data_entry(&mut fields)?;
+            if entry.flags & FLAG_CHUNK_ENCRYPTED != 0 {
+                return unsupported("installer data is encrypted");
+            }
+            data.push(entry);
+        }
+        Ok(Installer {
+            exe,
+            data_offset,
+            slices_per_disk: header.slices_per_disk.max(1),
+            compression: header.compression,
+            files,
+            data,
+        })
+    }
+    /// The .bin parts the installer's data is spread over, in order. Empty when the data is inside the exe.
+    pub fn parts(&self) -> Vec<PathBuf> {
+        if self.data_offset != 0 {
+            return vec![];
+        }
+        let slices = self.data.iter().map(|x| x.last_slice + 1).max().unwrap_or(0);
+        (0..slices).map(|x| self.slice_path(x)).collect()
+    }
+    /// Checks that every .bin part of the installer is next to it and completely downloaded
+    pub fn check_parts(&self) -> Result<(), InnoError> {
+        let parts = self.parts();
+        for (slice, path) in parts.iter().enumerate() {
+            let describe = format!(
+                "{} ({} of {})",
+                path.file_name().unwrap().to_string_lossy(),
+                slice + 1,
+                parts.len()
+            );
+            if !path.is_file() {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("Installer part {} is missing", describe),
+                )
+                .into());
+            }
+            match SliceReader::open(self, slice as u32) {
+                Ok(_) => {}
+                Err(InnoError::Io(ref err)) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        format!("Installer part {} is incomplete", describe),
+                    )
+                    .into());
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+    fn slice_path(&self, slice: u32) -> PathBuf {
+        let stem = self
+            .exe
+            .file_stem()
+            .map(|x| x.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let name = if self.slices_per_disk == 1 {
+            format!("{}-{}.bin", stem, slice + 1)
+        } else {
+            format!(
+                "{}-{}{}.bin",
+                stem,
+                slice / self.slices_per_disk + 1,
+                (b'a' + (slice % self.slices_per_disk) as u8) as char
+            )
+        };
+        self.exe.with_file_name(name)
+    }
+    /// Extracts the game's files (everything installed into {app}) to path
+    pub fn extract(&self, path: &Path) -> Result<(), InnoError> {
+        let mut targets: HashMap<u32, Vec<PathBuf>> = HashMap::new();
+        let mut picked: HashMap<PathBuf, &FileEntry> = HashMap::new();
+        for file in self.files.iter() {
+            if file.location == NO_LOCATION {
+                continue;
+            }
+            if let Some(relative) = app_path(&file.destination) {
+                let replace = match picked.get(&relative) {
+                    Some(current) => !prefers_language(current) && prefers_language(file),
+                    None => true,
+                };
+                if replace {
+                    picked.insert(relative, file);
+                }
+            } else {
+                info!("Not extracting {}", file.destination);
+            }
+        }
+        for (relative, file) in picked {
+            targets
+                .entry(file.location)
+                .or_default()
+                .push(path.join(relative));
+        }
+        // Group the wanted files by the compressed chunk they live in, in the order they appear on disk
+        let mut chunks: HashMap<(u32, u32), Vec<u32>> = HashMap::new();
+        for location in targets.keys() {
+            let entry = &self.data[*location as usize];
+            chunks
+                .entry((entry.first_slice, entry.chunk_offset))
+                .or_default()
+                .push(*location);
+        }
+        let mut order: Vec<(u32, u32)> = chunks.keys().cloned().collect();
+        order.sort();
+        for key in order.iter() {
+            self.open_chunk(&self.data[chunks[key][0] as usize])?;
+        }
+        let pb = ProgressBar::new(targets.len() as u64);
+        pb.set_style(
+            ProgressStyle::default_bar()
+                .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len}")
+                .unwrap()
+                .progress_chars("#>-"),
+        );
+        for key in order {
+            let mut locations = chunks.remove(&key).unwrap();
+            locations.sort_by_key(|x| self.data[*x as usize].file_offset);
+            let first = &self.data[locations[0] as usize];
+            let last = &self.data[locations[locations.len() - 1] as usize];
+            let size = last.file_offset + last.file_size;
+            let mut chunk = self.decompressor(first, size)?;
+            let mut position = 0;
+            for location in locations {
+                let entry = &self.data[location as usize];
+                if entry.file_offset < position {
+                    return unsupported("files in a chunk overlap");
+                }
+                io::copy(
+                    &mut chunk.by_ref().take(entry.file_offset - position),
+                    &mut io::sink(),
+                )?;
+                let outputs = &targets[&location];
+                self.extract_file(&mut chunk, entry, &outputs[0])?;
+                for output in outputs.iter().skip(1) {
+                    fs::create_dir_all(output.parent().unwrap())?;
+                    fs::copy(&outputs[0], output)?;
+                }
+                position = entry.file_offset + entry.file_size;
+                pb.inc(1);
+            }
+        }
+        pb.finish_with_message("Game installed!");
+        Ok(())
+    }
+    fn extract_file(
+        &self,
+        chunk: &mut dyn Read,
+        entry: &DataEntry,
+        output: &Path,
+    ) -> Result<(), InnoError> {
+        info!("Extracting {:?}", output);
+        fs::create_dir_all(output.parent().unwrap())?;
+        let mut fd = io::BufWriter::new(File::create(output)?);
+        let mut hasher = Sha1::new();
+        let mut buffer = vec![0; FILE_BLOCK_LEN];
+        let mut remaining = entry.file_size;
+        let mut offset: u32 = 0;
+        while remaining > 0 {
+            let len = remaining.min(FILE_BLOCK_LEN as u64) as usize;
+            let block = &mut buffer[..len];
+            chunk.read_exact(block)?;
+            if entry.flags & FLAG_CALL_OPTIMIZED != 0 {
+                decode_calls(block, offset);
+                offset = offset.wrapping_add(len as u32);
+            }
+            hasher.update(&block[..]);
+            io::Write::write_all(&mut fd, block)?;
+            remaining -= len as u64;
+        }
+        io::Write::flush(&mut fd)?;
+        if hasher.finalize()[..] != entry.sha1[..] {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Checksum mismatch for extracted file {:?}", output),
+            )
+            .into());
+        }
+        Ok(())
+    }
+    /// Opens the raw data of the chunk an entry is stored in, positioned just after the chunk's magic
+    fn open_chunk(&self, entry: &DataEntry) -> Result<SliceReader<'_>, InnoError> {
+        let mut reader = if self.data_offset != 0 {
+            let mut fd = File::open(&self.exe)?;
+            let start = self.data_offset + entry.chunk_offset as u64;
+            let end = fd.metadata()?.len();
+            fd.seek(SeekFrom::Start(start))?;
+            SliceReader {
+                installer: self,
+                slice: None,
+                fd: BufReader::new(fd),
+                slice_remaining: end.saturating_sub(start),
+            }
+        } else {
+            let mut reader = SliceReader::open(self, entry.first_slice)?;
+            if (entry.chunk_offset as u64) < SLICE_HEADER_LEN {
+                return unsupported("chunk starts inside the slice header");
+            }
+            let skip = entry.chunk_offset as u64 - SLICE_HEADER_LEN;
+            if skip > reader.slice_remaining {
+                return unsupported("chunk starts past the end of its slice");
+            }
+            reader.fd.seek(SeekFrom::Start(entry.chunk_offset as u64))?;
+            reader.slice_remaining -= skip;
+            reader
+        };
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if magic != CHUNK_MAGIC {
+            return unsupported("data chunk has an unknown signature");
+        }
+        Ok(reader)
+    }
+    /// Returns a reader over the first size bytes of the decompressed chunk an entry is stored in
+    fn decompressor<'a>(
+        &'a self,
+        entry: &DataEntry,
+        size: u64,
+    ) -> Result<Box<dyn Read + 'a>, InnoError> {
+        let mut raw = self.open_chunk(entry)?.take(entry.chunk_size);
+        let compression = if entry.flags & FLAG_CHUNK_COMPRESSED != 0 {
+            self.compression
+        } else {
+            Compression::Stored
+        };
+        Ok(match compression {
+            Compression::Stored => Box::new(raw),
+            Compression::Zlib => Box::new(ZlibDecoder::new(raw).take(size)),
+            Compression::Lzma1 => {
+                let mut props = [0; 5];
+                raw.read_exact(&mut props)?;
+                let dict_size = u32::from_le_bytes([props[1], props[2], props[3], props[4]]);
+                Box::new(LzmaReader::new_with_props(
+                    raw, size, props[0], dict_size, None,
+                )?)
+            }
+            Compression::Lzma2 => {
+                let mut props = [0; 1];
+                raw.read_exact(&mut props)?;
+                if props[0] > 40 {
+                    return unsupported("invalid LZMA2 dictionary size");
+                }
+                let dict_size = if props[0] == 40 {
+                    u32::MAX
+                } else {
+                    (2 | (props[0] as u32 & 1)) << (props[0] / 2 + 11)
+                };
+                Box::new(Lzma2Reader::new(raw, dict_size, None).take(size))
+            }
+        })
+    }
+}
+/// Reads chunk data out of the exe or the .bin slices, moving on to the next slice when one runs out
+struct SliceReader<'a> {
+    installer: &'a Installer,
+    slice: Option<u32>,
+    fd: BufReader<File>,
+    slice_remaining: u64,
+}
+impl<'a> SliceReader<'a> {
+    fn open(installer: &'a Installer, slice: u32) -> Result<SliceReader<'a>, InnoError> {
+        let path = installer.slice_path(slice);
+        let mut fd = File::open(&path).map_err(|err| {
+            io::Error::new(
+                err.kind(),
+                format!("Couldn't open installer part {:?}: {}", path, err),
+            )
+        })?;
+        let len = fd.metadata()?.len();
+        let mut header = [0; SLICE_HEADER_LEN as usize];
+        fd.read_exact(&mut header)?;
+        if &header[..8] != SLICE_MAGIC {
+            return unsupported(format!("{:?} isn't an Inno Setup slice", path));
+        }
+        let size = u32::from_le_bytes([header[8], header[9], header[10], header[11]]) as u64;
+        if size > len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("Installer part {:?} is incomplete", path),
+            )
+            .into());
+        }
+        Ok(SliceReader {
+            installer,
+            slice: Some(slice),
+            fd: BufReader::new(fd),
+            slice_remaining: size.saturating_sub(SLICE_HEADER_LEN),
+        })
+    }
+}
+impl<'a> Read for SliceReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.slice_remaining == 0 {
+            if let Some(slice) = self.slice {
+                *self = match SliceReader::open(self.installer, slice + 1) {
+                    Ok(next) => next,
+                    Err(InnoError::Io(err)) => return Err(err),
+                    Err(err) => return Err(io::Error::new(io::ErrorKind::InvalidData, err.to_string())),
+                };
+            } else {
+                return Ok(0);
+            }
+        }
+        let len = (buf.len() as u64).min(self.slice_remaining) as usize;
+        let read = self.fd.read(&mut buf[..len])?;
+        if read == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.slice_remaining -= read as u64;
+        Ok(read)
+    }
+}
+/// Finds the setup loader's offset table, returning where the setup headers and the embedded data (0 if it's in .bin files) start
+fn find_offsets(fd: &mut File) -> Result<(u64, u64), InnoError> {
+    let mut head = vec![];
+    fd.take(LOADER_SEARCH_LEN).read_to_end(&mut head)?;
+    // The loader's own code contains the magic too, so check every match until one has a valid checksum
+    let mut start = 0;
+    while let Some(found) = find(&head[start..], LOADER_MAGIC) {
+        let pos = start + found;
+        start = pos + 1;
+        let table = match head.get(pos..pos + LOADER_TABLE_LEN) {
+            Some(table) => table,
+            None => break,
+        };
+        let field = |i: usize| u32::from_le_bytes([table[i], table[i + 1], table[i + 2], table[i + 3]]);
+        if CRC32.checksum(&table[..40]) != field(40) {
+            continue;
+        }
+        if field(12) != 1 {
+            return unsupported(format!("setup loader table revision {}", field(12)));
+        }
+        return Ok((field(32) as u64, field(36) as u64));
+    }
+    unsupported("no Inno Setup loader found")
+}
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|x| x == needle)
+}
+fn parse_version(id: &[u8]) -> Result<Version, InnoError> {
+    let id = String::from_utf8_lossy(id);
+    let id = id.trim_end_matches('\0');
+    if !id.starts_with("Inno Setup Setup Data (") {
+        return unsupported(format!("unknown setup data \"{}\"", id));
+    }
+    let numbers: Vec<u32> = id["Inno Setup Setup Data (".len()..]
+        .split(')')
+        .next()
+        .unwrap()
+        .split('.')
+        .filter_map(|x| x.parse().ok())
+        .collect();
+    if numbers.len() != 3 {
+        return unsupported(format!("unknown setup data \"{}\"", id));
+    }
+    let version = Version(numbers[0], numbers[1], numbers[2]);
+    if !id.ends_with("(u)") {
+        return unsupported(format!("non-unicode Inno Setup {}", version));
+    }
+    if version < Version(5, 5, 0) || version >= Version(6, 3, 0) {
+        return unsupported(format!("Inno Setup {}", version));
+    }
+    Ok(version)
+}
+/// Reads one of the compressed blocks the setup headers are stored in.
+/// When the decompressed size isn't known the block is read until its data runs out.
+fn read_block(fd: &mut impl Read, size: Option<u64>) -> Result<Vec<u8>, InnoError> {
+    let mut header = [0; 9];
+    fd.read_exact(&mut header)?;
+    let checksum = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+    if CRC32.checksum(&header[4..]) != checksum {
+        return unsupported("setup header block has a bad checksum");
+    }
+    let stored = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+    let compressed = header[8] != 0;
+    if stored > MAX_BLOCK_LEN {
+        return unsupported(format!("setup header block claims to be {} bytes", stored));
+    }
+    let mut raw = vec![0; stored];
+    fd.read_exact(&mut raw)?;
+    // The block is split into 4KiB pieces, each with its own checksum
+    let mut data = Vec::with_capacity(stored);
+    for piece in raw.chunks(4 + BLOCK_CHUNK_LEN) {
+        if piece.len() < 4 {
+            return unsupported("setup header block is truncated");
+        }
+        if CRC32.checksum(&piece[4..]) != u32::from_le_bytes([piece[0], piece[1], piece[2], piece[3]]) {
+            return unsupported("setup header block has a bad checksum");
+        }
+        data.extend_from_slice(&piece[4..]);
+    }
+    if !compressed {
+        return Ok(data);
+    }
+    if data.len() < 5 {
+        return unsupported("setup header block is truncated");
+    }
+    let dict_size = u32::from_le_bytes([data[1], data[2], data[3], data[4]]);
+    let mut reader =
+        LzmaReader::new_with_props(&data[5..], size.unwrap_or(u64::MAX), data[0], dict_size, None)?;
+    let mut decompressed = vec![];
+    let mut buffer = [0; BLOCK_CHUNK_LEN];
+    loop {
+        match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => decompressed.extend_from_slice(&buffer[..read]),
+            // The headers have no end marker, so running out of input is how the end shows up
+            Err(ref err)
+                if size.is_none()
+                    && !decompressed.is_empty()
+                    && err.kind() == io::ErrorKind::UnexpectedEof =>
+            {
+                break
+            }
+            Err(err) => return Err(err.into()),
+        }
+    }
+    Ok(decompressed)
+}
+/// Sequential reader over decompressed setup headers. Running out of data means the layout wasn't what was expected.
+struct Fields<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+impl<'a> Fields<'a> {
+    fn new(data: &'a [u8]) -> Fields<'a> {
+        Fields { data, pos: 0 }
+    }
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], InnoError> {
+        if self.data.len() - self.pos < len {
+            return unsupported("setup headers ended early");
+        }
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+    fn skip(&mut self, len: usize) -> Result<(), InnoError> {
+        self.bytes(len).map(|_| ())
+    }
+    fn u8(&mut self) -> Result<u8, InnoError> {
+        Ok(self.bytes(1)?[0])
+    }
+    fn u16(&mut self) -> Result<u16, InnoError> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+    fn u32(&mut self) -> Result<u32, InnoError> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+    fn u64(&mut self) -> Result<u64, InnoError> {
+        Ok(self.u32()? as u64 | (self.u32()? as u64) << 32)
+    }
+    fn string(&mut self) -> Result<&'a [u8], InnoError> {
+        let len = self.u32()? as usize;
+        self.bytes(len)
+    }
+    /// Reads a UTF-16 string, which is how unicode installers store text
+    fn wide_string(&mut self) -> Result<String, InnoError> {
+        let bytes = self.string()?;
+        if bytes.len() % 2 != 0 {
+            return unsupported("malformed string in setup headers");
+        }
+        let units: Vec<u16> = bytes
+            .chunks(2)
+            .map(|x| u16::from_le_bytes([x[0], x[1]]))
+            .collect();
+        Ok(String::from_utf16_lossy(&units))
+    }
+    fn skip_strings(&mut self, count: usize) -> Result<(), InnoError> {
+        for _ in 0..count {
+            self.string()?;
+        }
+        Ok(())
+    }
+}
+struct Header {
+    languages: u32,
+    messages: u32,
+    permissions: u32,
+    types: u32,
+    components: u32,
+    tasks: u32,
+    directories: u32,
+    files: u32,
+    data_entries: u32,
+    slices_per_disk: u32,
+    compression: Compression,
+}
+fn parse_header(fields: &mut Fields, version: Version) -> Result<Header, InnoError> {
+    // AppName through Uninstallable
+    let mut strings = 26;
+    if version >= Version(5, 5, 0) {
+        strings += 1; // CloseApplicationsFilter
+    }
+    if version >= Version(5, 5, 6) {
+        strings += 1; // SetupMutex
+    }
+    if version >= Version(5, 6, 1) {
+        strings += 2; // ChangesEnvironment, ChangesAssociations
+    }
+    // LicenseText, InfoBeforeText, InfoAfterText, CompiledCodeText
+    strings += 4;
+    fields.skip_strings(strings)?;
+    let mut counts = [0; 16];
+    for count in counts.iter_mut() {
+        *count = fields.u32()?;
+    }
+    fields.skip(VERSION_RANGE_LEN)?;
+    fields.skip(8)?; // BackColor, BackColor2
+    if version < Version(5, 5, 7) {
+        fields.skip(4)?; // WizardImageBackColor
+    }
+    if version >= Version(6, 0, 0) {
+        fields.skip(9)?; // WizardStyle, WizardSizePercentX, WizardSizePercentY
+    }
+    if version >= Version(5, 5, 7) {
+        fields.skip(1)?; // WizardImageAlphaFormat
+    }
+    fields.skip(20 + 8 + 8)?; // PasswordHash, PasswordSalt, ExtraDiskSpaceRequired
+    let slices_per_disk = fields.u32()?;
+    fields.skip(3)?; // UninstallLogMode, DirExistsWarning, PrivilegesRequired
+    if version >= Version(6, 0, 0) {
+        fields.skip(1)?; // PrivilegesRequiredOverridesAllowed
+    }
+    fields.skip(2)?; // ShowLanguageDialog, LanguageDetectionMethod
+    let compression = match fields.u8()? {
+        0 => Compression::Stored,
+        1 => Compression::Zlib,
+        3 => Compression::Lzma1,
+        4 => Compression::Lzma2,
+        2 => return unsupported("bzip2 compressed data"),
+        other => return unsupported(format!("compression method {}", other)),
+    };
+    // ArchitecturesAllowed, ArchitecturesInstallIn64BitMode, DisableDirPage, DisableProgramGroupPage, UninstallDisplaySize, Options
+    fields.skip(4 + 8 + 6)?;
+    if counts[8] as usize > fields.data.len() {
+        return unsupported("setup header counts are out of range");
+    }
+    Ok(Header {
+        languages: counts[0],
+        messages: counts[1],
+        permissions: counts[2],
+        types: counts[3],
+        components: counts[4],
+        tasks: counts[5],
+        directories: counts[6],
+        files: counts[7],
+        data_entries: counts[8],
+        slices_per_disk,
+        compression,
+    })
+}
+/// Parses a [Files] entry, returning None for entries setup generates itself (like the uninstaller)
+fn parse_file_entry(fields: &mut Fields, data_entries: u32) -> Result<Option<FileEntry>, InnoError> {
+    fields.string()?; // Source
+    let destination = fields.wide_string()?;
+    fields.skip_strings(4)?; // InstallFontName, StrongAssemblyName, Components, Tasks
+    let languages = fields.wide_string()?;
+    fields.skip_strings(3)?; // Check, AfterInstall, BeforeInstall
+    fields.skip(VERSION_RANGE_LEN)?;
+    let location = fields.u32()?;
+    fields.skip(4 + 8)?; // Attribs, ExternalSize
+    fields.u16()?; // PermissionsEntry
+    fields.skip(4)?; // Options
+    let file_type = fields.u8()?;
+    if file_type > 1 || (location != NO_LOCATION && location >= data_entries) {
+        return unsupported("file entries don't match the expected layout");
+    }
+    if file_type != FILE_TYPE_USER {
+        return Ok(None);
+    }
+    Ok(Some(FileEntry {
+        destination,
+        languages,
+        location,
+    }))
+}
+fn parse_data_entry(fields: &mut Fields) -> Result<DataEntry, InnoError> {
+    let first_slice = fields.u32()?;
+    let last_slice = fields.u32()?;
+    let chunk_offset = fields.u32()?;
+    let file_offset = fields.u64()?;
+    let file_size = fields.u64()?;
+    let chunk_size = fields.u64()?;
+    let mut sha1 = [0; 20];
+    sha1.copy_from_slice(fields.bytes(20)?);
+    fields.skip(8 + 8)?; // SourceTimeStamp, FileVersionMS, FileVersionLS
+    let flags = fields.u16()?;
+    Ok(DataEntry {
+        first_slice,
+        last_slice,
+        chunk_offset,
+        file_offset,
+        file_size,
+        chunk_size,
+        sha1,
+        flags,
+    })
+}
+/// Maps an installer destination like {app}\Data\file.pak to a path relative to the install directory
+fn app_path(destination: &str) -> Option<PathBuf> {
+    let destination = destination.replace('\\', "/");
+    if !destination
+        .get(..6)
+        .is_some_and(|x| x.eq_ignore_ascii_case("{app}/"))
+    {
+        return None;
+    }
+    let mut mapped = PathBuf::new();
+    for component in Path::new(&destination[6..]).components() {
+        match component {
+            Component::Normal(part) => mapped.push(part),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    if mapped.as_os_str().is_empty() {
+        None
+    } else {
+        Some(mapped)
+    }
+}
+/// Whether a file entry is one to keep when several install to the same place for different languages
+fn prefers_language(file: &FileEntry) -> bool {
+    file.languages.trim().is_empty()
+        || file
+            .languages
+            .split_whitespace()
+            .any(|x| x.eq_ignore_ascii_case("en") || x.eq_ignore_ascii_case("english"))
+}
+/// Undoes the transform Inno Setup applies to the relative addresses of x86 CALL and JMP instructions in executables to make them compress better.
+/// Works on one block of a file at a time, offset being the block's position in the file.
+fn decode_calls(block: &mut [u8], offset: u32) {
+    if block.len() < 5 {
+        return;
+    }
+    let end = block.len() - 4;
+    let mut i = 0;
+    while i < end {
+        if block[i] == 0xE8 || block[i] == 0xE9 {
+            i += 1;
+            // Real relative addresses will almost always have a high byte of 0x00 or 0xFF
+            if block[i + 3] == 0x00 || block[i + 3] == 0xFF {
+                let addr = offset.wrapping_add(i as u32 + 4) & 0xFF_FFFF;
+                let rel = (block[i] as u32 | (block[i + 1] as u32) << 8 | (block[i + 2] as u32) << 16)
+                    .wrapping_sub(addr);
+                if rel & 0x80_0000 != 0 {
+                    block[i + 3] = !block[i + 3];
+                }
+                block[i] = rel as u8;
+                block[i + 1] = (rel >> 8) as u8;
+                block[i + 2] = (rel >> 16) as u8;
+            }
+            i += 4;
+        } else {
+            i += 1;
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn setup_id(id: &str) -> Vec<u8> {
+        let mut padded = id.as_bytes().to_vec();
+        padded.resize(SETUP_ID_LEN, 0);
+        padded
+    }
+    /// An uncompressed header block holding data, with checksums as setup.exe writes them
+    fn block(data: &[u8]) -> Vec<u8> {
+        let mut raw = vec![];
+        for piece in data.chunks(BLOCK_CHUNK_LEN) {
+            raw.extend_from_slice(&CRC32.checksum(piece).to_le_bytes());
+            raw.extend_from_slice(piece);
+        }
+        let mut header = (raw.len() as u32).to_le_bytes().to_vec();
+        header.push(0);
+        let mut block = CRC32.checksum(&header).to_le_bytes().to_vec();
+        block.extend(header);
+        block.extend(raw);
+        block
+    }
+    fn wide_string(text: &str) -> Vec<u8> {
+        let bytes: Vec<u8> = text.encode_utf16().flat_map(|x| x.to_le_bytes()).collect();
+        let mut string = (bytes.len() as u32).to_le_bytes().to_vec();
+        string.extend(bytes);
+        string
+    }
+    /// A minimal Inno Setup 5.5.7 installer with its data stored uncompressed inside the exe, one data entry per file.
+    /// Files are given as (destination, languages, contents).
+    fn installer(files: &[(&str, &str, &[u8])]) -> Vec<u8> {
+        let empty = 0u32.to_le_bytes();
+        let mut headers = vec![];
+        for _ in 0..32 {
+            headers.extend_from_slice(&empty);
+        }
+        let mut counts = [0u32; 16];
+        counts[7] = files.len() as u32;
+        counts[8] = files.len() as u32;
+        for count in counts.iter() {
+            headers.extend_from_slice(&count.to_le_bytes());
+        }
+        headers.extend(vec![0; VERSION_RANGE_LEN + 8 + 1 + 36]);
+        headers.extend_from_slice(&1u32.to_le_bytes()); // slices per disk
+        headers.extend(vec![0; 3 + 2]);
+        headers.push(0); // stored
+        headers.extend(vec![0; 18]);
+        let mut locations = vec![];
+        let mut data = CHUNK_MAGIC.to_vec();
+        for (location, &(destination, languages, contents)) in files.iter().enumerate() {
+            headers.extend_from_slice(&empty);
+            headers.extend(wide_string(destination));
+            for _ in 0..4 {
+                headers.extend_from_slice(&empty);
+            }
+            headers.extend(wide_string(languages));
+            for _ in 0..3 {
+                headers.extend_from_slice(&empty);
+            }
+            headers.extend(vec![0; VERSION_RANGE_LEN]);
+            headers.extend_from_slice(&(location as u32).to_le_bytes());
+            headers.extend(vec![0; 12 + 2 + 4]);
+            headers.push(FILE_TYPE_USER);
+            locations.extend(vec![0; 12]); // first_slice, last_slice, chunk_offset
+            locations.extend_from_slice(&((data.len() - CHUNK_MAGIC.len()) as u64).to_le_bytes());
+            locations.extend_from_slice(&(contents.len() as u64).to_le_bytes());
+            locations.extend(vec![0; 8]); // chunk_size, filled in below
+            locations.extend_from_slice(&Sha1::digest(contents));
+            locations.extend(vec![0; 16 + 2]);
+            data.extend_from_slice(contents);
+        }
+        let chunk_size = ((data.len() - CHUNK_MAGIC.len()) as u64).to_le_bytes();
+        for entry in locations.chunks_mut(DATA_ENTRY_LEN) {
+            entry[28..36].copy_from_slice(&chunk_size);
+        }
+        // A copy of the magic without a valid table, like the one in the loader's code
+        let mut exe = b"MZ".to_vec();
+        exe.extend(vec![0; 62]);
+        exe.extend_from_slice(LOADER_MAGIC);
+        exe.extend(vec![0; LOADER_TABLE_LEN]);
+        let header_offset = exe.len() + LOADER_TABLE_LEN;
+        let mut setup = setup_id("Inno Setup Setup Data (5.5.7) (u)");
+        setup.extend(block(&headers));
+        setup.extend(block(&locations));
+        let mut table = LOADER_MAGIC.to_vec();
+        table.extend_from_slice(&1u32.to_le_bytes());
+        table.extend(vec![0; 16]);
+        table.extend_from_slice(&(header_offset as u32).to_le_bytes());
+        table.extend_from_slice(&((header_offset + setup.len()) as u32).to_le_bytes());
+        let checksum = CRC32.checksum(&table);
+        table.extend_from_slice(&checksum.to_le_bytes());
+        exe.extend(table);
+        exe.extend(setup);
+        exe.extend(data);
+        exe
+    }
+    fn is_unsupported<T>(result: Result<T, InnoError>) -> bool {
+        matches!(result, Err(InnoError::Unsupported(_)))
+    }
+    #[test]
+    fn parses_supported_versions() {
+        assert_eq!(
+            parse_version(&setup_id("Inno Setup Setup Data (5.5.7) (u)")).unwrap(),
+            Version(5, 5, 7)
+        );
+        assert_eq!(
+            parse_version(&setup_id("Inno Setup Setup Data (6.2.0) (u)")).unwrap(),
+            Version(6, 2, 0)
+        );
+    }
+    #[test]
+    fn rejects_unsupported_versions() {
+        assert!(is_unsupported(parse_version(&setup_id(
+            "Inno Setup Setup Data (5.5.7)"
+        ))));
+        assert!(is_unsupported(parse_version(&setup_id(
+            "Inno Setup Setup Data (5.4.2) (u)"
+        ))));
+        assert!(is_unsupported(parse_version(&setup_id(
+            "Inno Setup Setup Data (6.3.0) (u)"
+        ))));
+        assert!(is_unsupported(parse_version(&setup_id(
+            "Inno Setup Setup Data (6.x) (u)"
+        ))));
+        assert!(is_unsupported(parse_version(&setup_id("Not a setup"))));
+    }
+    #[test]
+    fn reads_uncompressed_blocks() {
+        let data: Vec<u8> = (0..10000).map(|x| x as u8).collect();
+        let mut read = &block(&data)[..];
+        assert_eq!(read_block(&mut read, None).unwrap(), data);
+        assert!(read.is_empty());
+    }
+    #[test]
+    fn rejects_bad_block_checksums() {
+        let data = vec![7; 5000];
+        let mut header_changed = block(&data);
+        header_changed[0] ^= 1;
+        assert!(is_unsupported(read_block(&mut &header_changed[..], None)));
+        let mut data_changed = block(&data);
+        let last = data_changed.len() - 1;
+        data_changed[last] ^= 1;
+        assert!(is_unsupported(read_block(&mut &data_changed[..], None)));
+    }
+    #[test]
+    fn rejects_oversized_and_truncated_blocks() {
+        let mut header = ((MAX_BLOCK_LEN + 1) as u32).to_le_bytes().to_vec();
+        header.push(0);
+        let mut oversized = CRC32.checksum(&header).to_le_bytes().to_vec();
+        oversized.extend(header);
+        assert!(is_unsupported(read_block(&mut &oversized[..], None)));
+        let whole = block(&[1; 100]);
+        match read_block(&mut &whole[..50], None) {
+            Err(InnoError::Io(_)) => {}
+            _ => panic!("truncated block was read"),
+        }
+    }
+    #[test]
+    fn decodes_call_addresses() {
+        let mut forward = [0xE8, 0x05, 0x01, 0x00, 0x00];
+        decode_calls(&mut forward, 0);
+        assert_eq!(forward, [0xE8, 0x00, 0x01, 0x00, 0x00]);
+        let mut backward = [0xE8, 0x02, 0x00, 0x00, 0x00];
+        decode_calls(&mut backward, 0);
+        assert_eq!(backward, [0xE8, 0xFD, 0xFF, 0xFF, 0xFF]);
+        // The position is counted from the start of the file, not the block
+        let mut later = [0xE9, 0x05, 0x01, 0x01, 0x00];
+        decode_calls(&mut later, 0x10000);
+        assert_eq!(later, [0xE9, 0x00, 0x01, 0x00, 0x00]);
+    }
+    #[test]
+    fn leaves_other_bytes_alone() {
+        let mut not_call = [0xE8, 0x01, 0x02, 0x03, 0x42, 0x90];
+        decode_calls(&mut not_call, 0);
+        assert_eq!(not_call, [0xE8, 0x01, 0x02, 0x03, 0x42, 0x90]);
+        let mut short = [0xE8, 0x01, 0x02, 0x03];
+        decode_calls(&mut short, 0);
+        assert_eq!(short, [0xE8, 0x01, 0x02, 0x03]);
+    }
+    #[test]
+    fn maps_app_paths() {
+        assert_eq!(
+            app_path("{app}\\Data\\file.pak"),
+            Some(PathBuf::from("Data/file.pak"))
+        );
+        assert_eq!(app_path("{APP}\\game.exe"), Some(PathBuf::from("game.exe")));
+        assert_eq!(app_path("{app}\\.\\a\\b"), Some(PathBuf::from("a/b")));
+        assert_eq!(app_path("{tmp}\\setup.dll"), None);
+        assert_eq!(app_path("{app}\\"), None);
+        assert_eq!(app_path("{app}\\..\\outside"), None);
+        assert_eq!(app_path("{app}\\Data\\..\\..\\outside"), None);
+        assert_eq!(app_path("C:\\Windows\\x"), None);
+        assert_eq!(app_path("{tmp}é"), None);
+        assert_eq!(app_path("{app}é\\x"), None);
+    }
+    #[test]
+    fn extracts_installers() {
+        let dir = tempfile::tempdir().unwrap();
+        let exe = dir.path().join("setup_game.exe");
+        fs::write(
+            &exe,
+            installer(&[
+                ("{app}\\game.exe", "", b"\x90\x90game"),
+                ("{app}\\Data\\file.pak", "", b"data"),
+                ("{tmp}\\setup.dll", "", b"setup"),
+                ("{app}\\readme.txt", "de", b"Liesmich"),
+                ("{app}\\readme.txt", "en", b"Read me"),
+            ]),
+        )
+        .unwrap();
+        let installer = Installer::open(&exe).unwrap();
+        assert!(installer.parts().is_empty());
+        let game = dir.path().join("game");
+        installer.extract(&game).unwrap();
+        assert_eq!(fs::read(game.join("game.exe")).unwrap(), b"\x90\x90game");
+        assert_eq!(fs::read(game.join("Data/file.pak")).unwrap(), b"data");
+        assert_eq!(fs::read(game.join("readme.txt")).unwrap(), b"Read me");
+        assert!(!game.join("setup.dll").exists());
+        assert_eq!(fs::read_dir(&game).unwrap().count(), 3);
+    }
+    #[test]
+    fn rejects_damaged_installers() {
+        let dir = tempfile::tempdir().unwrap();
+        let exe = dir.path().join("setup_game.exe");
+        let whole = installer(&[("{app}\\game.exe", "", b"game")]);
+        let table = find(&whole, LOADER_MAGIC).unwrap() + LOADER_MAGIC.len() + LOADER_TABLE_LEN;
+        let mut no_loader = whole.clone();
+        no_loader[table + 40] ^= 1;
+        fs::write(&exe, no_loader).unwrap();
+        assert!(is_unsupported(Installer::open(&exe)));
+        let mut bad_headers = whole.clone();
+        bad_headers[table + LOADER_TABLE_LEN + SETUP_ID_LEN + 20] ^= 1;
+        fs::write(&exe, bad_headers).unwrap();
+        assert!(is_unsupported(Installer::open(&exe)));
+        let mut bad_data = whole;
+        let last = bad_data.len() - 1;
+        bad_data[last] ^= 1;
+        fs::write(&exe, bad_data).unwrap();
+        let installer = Installer::open(&exe).unwrap();
+        match installer.extract(&dir.path().join("game")) {
+            Err(InnoError::Io(ref err)) if err.kind() == io::ErrorKind::InvalidData => {}
+            _ => panic!("damaged file was extracted"),
+        }
+    }
+}
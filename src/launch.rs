@@ -0,0 +1,256 @@
+/// Keeps track of installed games and launches them, through start.sh for linux games and wine for windows ones
+use config::*;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+/// Records a freshly installed game in the install registry, so `wyvern run` can find it by name
+pub fn register(path: &Path, name: &str, windows: bool) {
+    let path = match path.canonicalize() {
+        Ok(path) => path,
+        Err(err) => {
+            error!("Could not find install directory to register it. Error: {}", err);
+            return;
+        }
+    };
+    let name = if windows {
+        LaunchConfig::load(&path).ok().map(|x| x.name)
+    } else {
+        fs::read_to_string(path.join("gameinfo"))
+            .ok()
+            .and_then(|x| GameInfo::parse(x).ok())
+            .map(|x| x.name)
+    }
+    .unwrap_or_else(|| name.to_string());
+    let registered = InstallRegistry::load().and_then(|mut registry| {
+        registry.add(InstalledGame {
+            name: name.clone(),
+            path,
+            windows,
+            env: vec![],
+            wrappers: vec![],
+        });
+        registry.store().map(|_| ())
+    });
+    match registered {
+        Ok(()) => info!("Registered {} as installed", name),
+        Err(err) => error!("Could not register installed game. Error: {}", err),
+    }
+}
+/// Where a game's output is logged
+pub fn log_path(name: &str) -> PathBuf {
+    let file_name: String = name
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '_' })
+        .collect();
+    dirs::data_dir()
+        .unwrap_or_else(|| dirs::home_dir().unwrap().join(".local/share"))
+        .join("wyvern/logs")
+        .join(file_name + ".log")
+}
+/// Looks a game up by name or path. Directories wyvern didn't install are still runnable, as long as they have a start.sh or a launch configuration.
+pub fn find_game(game: &str) -> Option<InstalledGame> {
+    let registry = InstallRegistry::load().unwrap_or_else(|err| {
+        warn!("Could not read install registry. Error: {}", err);
+        InstallRegistry::default()
+    });
+    if let Some(found) = registry.find(game) {
+        return Some(found.clone());
+    }
+    let path = Path::new(game).canonicalize().ok()?;
+    let windows = if path.join("start.sh").is_file() {
+        false
+    } else if path.join(LaunchConfig::FILE_NAME).is_file() {
+        true
+    } else {
+        return None;
+    };
+    Some(InstalledGame {
+        name: path.file_name()?.to_string_lossy().to_string(),
+        path,
+        windows,
+        env: vec![],
+        wrappers: vec![],
+    })
+}
+/// Builds the command that starts a game, without any wrappers
+fn game_command(game: &InstalledGame) -> Result<Command, String> {
+    if !game.windows {
+        let start = game.path.join("start.sh");
+        if !start.is_file() {
+            return Err(format!("{} has no start.sh", game.path.display()));
+        }
+        let mut command = Command::new(start);
+        command.current_dir(&game.path);
+        return Ok(command);
+    }
+    let launch = LaunchConfig::load(&game.path).map_err(|err| {
+        format!(
+            "Could not read launch configuration from {}. Was it installed with --windows? Error: {}",
+            game.path.display(),
+            err
+        )
+    })?;
+    let exe = game.path.join(&launch.exe);
+    let mut command = Command::new(&launch.wine);
+    command.arg(&exe);
+    if let Some(arguments) = launch.arguments.as_ref() {
+        command.args(windows_args(arguments));
+    }
+    if let Some(prefix) = launch.prefix.as_ref() {
+        command.env("WINEPREFIX", prefix);
+    }
+    match launch.working_dir.as_ref() {
+        Some(working_dir) => command.current_dir(game.path.join(working_dir)),
+        None => command.current_dir(exe.parent().unwrap_or(&game.path)),
+    };
+    Ok(command)
+}
+/// Splits a windows command line, like the arguments in goggame-*.info files, the way windows programs do.
+/// Quotes group words, and backslashes are only special right before a quote, so that windows paths stay intact.
+fn windows_args(line: &str) -> Vec<String> {
+    let mut args = vec![];
+    let mut arg = String::new();
+    let mut in_arg = false;
+    let mut quoted = false;
+    let mut backslashes = 0;
+    for c in line.chars() {
+        match c {
+            '\\' => {
+                backslashes += 1;
+                in_arg = true;
+                continue;
+            }
+            '"' => {
+                arg.extend(std::iter::repeat_n('\\', backslashes / 2));
+                if backslashes % 2 == 1 {
+                    arg.push('"');
+                } else {
+                    quoted = !quoted;
+                }
+                in_arg = true;
+            }
+            c if c.is_whitespace() && !quoted => {
+                arg.extend(std::iter::repeat_n('\\', backslashes));
+                if in_arg {
+                    args.push(std::mem::take(&mut arg));
+                }
+                in_arg = false;
+            }
+            c => {
+                arg.extend(std::iter::repeat_n('\\', backslashes));
+                arg.push(c);
+                in_arg = true;
+            }
+        }
+        backslashes = 0;
+    }
+    arg.extend(std::iter::repeat_n('\\', backslashes));
+    if in_arg {
+        args.push(arg);
+    }
+    args
+}
+/// Makes wyvern the parent of any processes the game leaves orphaned, so launchers that exit before the game does can be waited through
+fn adopt_orphans() {
+    if unsafe { libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 1, 0, 0, 0) } != 0 {
+        warn!(
+            "Could not watch the game's child processes. Error: {}",
+            io::Error::last_os_error()
+        );
+    }
+}
+/// Waits until every process the game started has exited
+fn wait_for_descendants() {
+    loop {
+        let mut status = 0;
+        if unsafe { libc::waitpid(-1, &mut status, 0) } < 0 {
+            if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            break;
+        }
+    }
+}
+/// Runs a game and waits for it and every process it started to exit, with its output going to its log file
+pub fn run(game: &InstalledGame) -> Result<(), String> {
+    let inner = game_command(game)?;
+    let mut words: Vec<String> = vec![];
+    for wrapper in game.wrappers.iter() {
+        words.extend(
+            shell_words::split(wrapper)
+                .map_err(|err| format!("Could not parse wrapper {}. Error: {}", wrapper, err))?,
+        );
+    }
+    let mut command = if words.is_empty() {
+        inner
+    } else {
+        let mut command = Command::new(words.remove(0));
+        command.args(words);
+        command.arg(inner.get_program()).args(inner.get_args());
+        for (key, value) in inner.get_envs() {
+            if let Some(value) = value {
+                command.env(key, value);
+            }
+        }
+        if let Some(dir) = inner.get_current_dir() {
+            command.current_dir(dir);
+        }
+        command
+    };
+    for var in game.env.iter() {
+        match var.find('=') {
+            Some(split) => command.env(&var[..split], &var[split + 1..]),
+            None => return Err(format!("Environment variable {} isn't in KEY=VALUE form", var)),
+        };
+    }
+    let log = log_path(&game.name);
+    if let Some(parent) = log.parent() {
+        fs::create_dir_all(parent).map_err(|err| err.to_string())?;
+    }
+    let log_file = File::create(&log).map_err(|err| err.to_string())?;
+    let log_err = log_file.try_clone().map_err(|err| err.to_string())?;
+    println!("Starting {}. Logging output to {}", game.name, log.display());
+    info!("Running {:?}", command);
+    adopt_orphans();
+    let status = command
+        .stdin(Stdio::null())
+        .stdout(log_file)
+        .stderr(log_err)
+        .status()
+        .map_err(|err| format!("Could not start {}. Error: {}", game.name, err))?;
+    info!("Waiting for the game's child processes to exit");
+    wait_for_descendants();
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("{} exited with {}", game.name, status))
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn splits_plain_arguments() {
+        assert_eq!(windows_args("-skipintro  -windowed "), vec!["-skipintro", "-windowed"]);
+        assert!(windows_args("   ").is_empty());
+    }
+    #[test]
+    fn keeps_quoted_arguments_together() {
+        assert_eq!(
+            windows_args(r#"-config "My Documents\game.ini" -x"#),
+            vec!["-config", r"My Documents\game.ini", "-x"]
+        );
+        assert_eq!(windows_args(r#"-name="a b"c"#), vec!["-name=a bc"]);
+        assert_eq!(windows_args(r#""""#), vec![""]);
+    }
+    #[test]
+    fn keeps_backslashes_in_paths() {
+        assert_eq!(
+            windows_args(r"C:\Games\ ..\data\"),
+            vec![r"C:\Games\", r"..\data\"]
+        );
+        assert_eq!(windows_args(r#"a\"b"#), vec![r#"a"b"#]);
+        assert_eq!(windows_args(r#""dir\\" x"#), vec![r"dir\", "x"]);
+    }
+}
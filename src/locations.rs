@@ -0,0 +1,174 @@
+/// Known save file locations of GOG games, so that syncing a game's saves doesn't need their path typed in by hand.
+/// wyvern ships a database of them, which can be extended or corrected with `wyvern sync update-locations`.
+use config::*;
+use paths;
+use prompt;
+use serde_json;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use wine;
+const BUILTIN: &str = include_str!("save_locations.json");
+/// Where a game keeps its saves. Paths can use ~ and environment variables like $USER or $XDG_DATA_HOME, plus <game> for the install directory.
+/// Paths of windows games can use <prefix> for their wine prefix, and %USERPROFILE%, %APPDATA%, %LOCALAPPDATA% and %DOCUMENTS% for folders in it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct KnownSaves {
+    pub name: String,
+    #[serde(default)]
+    pub linux: Vec<String>,
+    #[serde(default)]
+    pub windows: Vec<String>,
+}
+/// Known save locations by GOG product id
+pub type Locations = BTreeMap<String, KnownSaves>;
+/// Where locations added with `wyvern sync update-locations` are kept
+fn local_path() -> PathBuf {
+    dirs::data_dir()
+        .unwrap_or_else(|| dirs::home_dir().unwrap().join(".local/share"))
+        .join("wyvern/save-locations.json")
+}
+fn parse(unparsed: &str) -> Result<Locations, io::Error> {
+    serde_json::from_str(unparsed).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+/// The built-in locations, with any local ones taking precedence
+pub fn load() -> Locations {
+    let mut locations = parse(BUILTIN).expect("Built-in save locations are invalid");
+    match fs::read_to_string(local_path()) {
+        Ok(unparsed) => match parse(&unparsed) {
+            Ok(local) => locations.extend(local),
+            Err(err) => warn!("Could not parse local save locations. Error: {}", err),
+        },
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => {}
+        Err(err) => warn!("Could not read local save locations. Error: {}", err),
+    }
+    locations
+}
+/// Adds the locations in a file to the local ones, replacing those of the same games. Returns how many were added.
+pub fn update(file: &Path) -> Result<usize, io::Error> {
+    let added = parse(&fs::read_to_string(file)?)?;
+    let path = local_path();
+    let mut local = match fs::read_to_string(&path) {
+        Ok(unparsed) => parse(&unparsed)?,
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => Locations::new(),
+        Err(err) => return Err(err),
+    };
+    let count = added.len();
+    local.extend(added);
+    fs::create_dir_all(path.parent().unwrap())?;
+    fs::write(&path, serde_json::to_string_pretty(&local).unwrap())?;
+    Ok(count)
+}
+/// Fills in the variables of a known save path. Returns None if one of them has no value here.
+pub fn expand(path: &str, game_dir: &Path, prefix: Option<&Path>) -> Option<PathBuf> {
+    let expanded = paths::try_expand(path)?
+        .to_string_lossy()
+        .replace("<game>", &game_dir.display().to_string());
+    if wine::in_prefix(&expanded) {
+        return Some(wine::expand(&expanded, prefix?));
+    }
+    Some(PathBuf::from(expanded))
+}
+/// Where a game installed at `game_dir` might keep its saves, most likely first
+pub fn candidates(known: &KnownSaves, game_dir: &Path) -> Vec<PathBuf> {
+    let (paths, prefix) = match LaunchConfig::load(game_dir) {
+        Ok(launch) => (
+            &known.windows,
+            Some(launch.prefix.unwrap_or_else(wine::default_prefix)),
+        ),
+        Err(_) => (&known.linux, None),
+    };
+    let mut candidates: Vec<PathBuf> = paths
+        .iter()
+        .filter_map(|x| expand(x, game_dir, prefix.as_deref()))
+        .collect();
+    // Saves that already exist are the ones worth syncing
+    candidates.sort_by_key(|x| !x.exists());
+    candidates
+}
+/// Looks up where a game keeps its saves and asks whether to use that
+pub fn suggest(id: i64, game_dir: &Path) -> Option<PathBuf> {
+    let locations = load();
+    let known = locations.get(&format!("{}", id))?;
+    let found = candidates(known, game_dir).into_iter().next()?;
+    let confirmed = prompt::confirm(
+        &format!(
+            "{} usually keeps its save files at {}. Sync them from there?",
+            known.name,
+            found.display()
+        ),
+        true,
+    );
+    if confirmed {
+        Some(found)
+    } else {
+        None
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn builtin(id: &str) -> KnownSaves {
+        parse(BUILTIN).unwrap()[id].clone()
+    }
+    #[test]
+    fn parses_builtin_locations() {
+        let locations = parse(BUILTIN).unwrap();
+        assert!(!locations.is_empty());
+        for (id, known) in locations.iter() {
+            assert!(id.parse::<i64>().is_ok(), "{} is not a GOG product id", id);
+            assert!(!known.linux.is_empty() || !known.windows.is_empty());
+        }
+    }
+    #[test]
+    fn expands_known_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let game_dir = dir.path().join("game");
+        let prefix = dir.path().join("prefix");
+        let documents = prefix.join("drive_c/users/player/My Documents");
+        fs::create_dir_all(&documents).unwrap();
+        let heroes = builtin("1207658787");
+        assert_eq!(
+            expand(&heroes.windows[0], &game_dir, None),
+            Some(game_dir.join("Games"))
+        );
+        let witcher = builtin("1207658924");
+        assert_eq!(
+            expand(&witcher.windows[0], &game_dir, Some(&prefix)),
+            Some(documents.join("The Witcher/saves"))
+        );
+        assert_eq!(expand(&witcher.windows[0], &game_dir, None), None);
+        let ftl = builtin("1207659102");
+        let expanded = expand(&ftl.linux[0], &game_dir, None).unwrap();
+        assert!(expanded.is_absolute());
+        assert!(expanded.ends_with("FasterThanLight"));
+        assert!(!expanded.to_string_lossy().contains('$'));
+    }
+    #[test]
+    fn picks_paths_for_how_the_game_runs() {
+        let dir = tempfile::tempdir().unwrap();
+        let game_dir = dir.path().join("game");
+        let prefix = dir.path().join("prefix");
+        fs::create_dir_all(prefix.join("drive_c/users/player/Documents")).unwrap();
+        fs::create_dir_all(&game_dir).unwrap();
+        let ftl = builtin("1207659102");
+        let linux = candidates(&ftl, &game_dir);
+        assert_eq!(linux.len(), 1);
+        assert!(linux[0].ends_with("FasterThanLight"));
+        LaunchConfig {
+            name: ftl.name.clone(),
+            game_id: Some("1207659102".to_string()),
+            wine: "wine".to_string(),
+            prefix: Some(prefix.clone()),
+            exe: PathBuf::from("FTLGame.exe"),
+            arguments: None,
+            working_dir: None,
+        }
+        .store(&game_dir)
+        .unwrap();
+        assert_eq!(
+            candidates(&ftl, &game_dir),
+            vec![prefix.join("drive_c/users/player/Documents/My Games/FasterThanLight")]
+        );
+    }
+}
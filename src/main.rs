@@ -8,6 +8,8 @@ extern crate human_panic;
 extern crate serde_derive;
 #[macro_use]
 extern crate log;
+#[macro_use]
+extern crate lazy_static;
 extern crate clap_verbosity_flag;
 extern crate confy;
 extern crate crc;
@@ -24,16 +26,47 @@ extern crate url;
 extern crate walkdir;
 extern crate zip;
 extern crate anyhow;
+extern crate argon2;
+extern crate chacha20poly1305;
+extern crate flate2;
+extern crate hmac;
+extern crate humantime;
+extern crate libc;
+extern crate lzma_rust2;
+extern crate notify;
+extern crate percent_encoding;
+extern crate reqwest;
+extern crate sha1;
+extern crate sha2;
+extern crate shell_words;
+extern crate ssh2;
 mod args;
 mod config;
 mod connect;
+mod crypto;
+mod dirsync;
 mod games;
+mod gitrepo;
+mod hashing;
+mod inno;
 mod interactive;
+mod launch;
+mod locations;
+mod merge;
+mod paths;
+mod profiles;
+mod prompt;
+mod remote;
+mod snapshots;
+mod storage;
+mod tokens;
 mod sync;
+mod watch;
+mod wine;
 use args::Command::Download;
 use args::Command::*;
 use args::Wyvern;
-use args::{DownloadOptions, ShortcutOptions};
+use args::{DownloadOptions, ShortcutOptions, WineOptions};
 
 use anyhow::Result;
 use config::*;
@@ -57,25 +90,55 @@ fn main() -> Result<(), anyhow::Error> {
     #[cfg(not(debug_assertions))]
     setup_panic!();
     let mut config: Config = confy::load("wyvern", "wyvern")?;
-    let args = Wyvern::from_args();
+    let mut args = Wyvern::from_args();
     args.verbose
         .setup_env_logger("wyvern")
         .expect("Couldn't set up logger");
+    prompt::setup(args.non_interactive);
+    let profile = args
+        .profile
+        .clone()
+        .or_else(|| config.default_profile.clone());
+    match tokens::migrate(&mut config) {
+        Ok(0) => {}
+        Ok(moved) => {
+            println!("Moved {} logins into the encrypted token store", moved);
+            confy::store("wyvern", "wyvern", &config)?;
+        }
+        Err(err) => {
+            error!("Could not move logins into the encrypted token store. Error: {}", err);
+            std::process::exit(64);
+        }
+    }
     match args.command {
         Login {
             code,
             username,
             password,
+            print_url,
         } => {
+            if print_url {
+                println!("{}", AUTH_URL);
+                println!("Log in there, then run wyvern login --code with the URL it ends up at");
+                ::std::process::exit(0);
+            }
             let mut config: Config = confy::load("wyvern", "wyvern")?;
+            if let Err(err) = config.account(profile.as_deref()) {
+                error!("{}", err);
+                std::process::exit(64);
+            }
+            let profile = profile.as_deref();
             if let Some(code) = code {
-                if let Ok(token) = Token::from_login_code(code) {
-                    config.token = Some(token);
+                if let Ok(token) = Token::from_login_code(login_code(&code)) {
+                    store_token(&mut config, profile, token);
                 } else {
                     error!("Could not login with code");
                 }
             } else if let Some(username) = username {
                 let password = password.unwrap_or_else(|| {
+                    if !prompt::interactive() {
+                        prompt::needs_input("Your password", "Pass it with --password");
+                    }
                     let pword: String = Password::new()
                         .with_prompt("Password")
                         .interact()
@@ -86,6 +149,9 @@ fn main() -> Result<(), anyhow::Error> {
                     username,
                     password,
                     Some(|| {
+                        if !prompt::interactive() {
+                            prompt::needs_input("A two factor authentication code", "Log in with --code instead");
+                        }
                         println!("A two factor authentication code is required. Please check your email for one and enter it here.");
                         let mut token: String;
                         loop {
@@ -107,37 +173,90 @@ fn main() -> Result<(), anyhow::Error> {
                     _ => error!("Error: {:?}", err),
                 };
                 } else {
-                    config.token = Some(token.unwrap());
+                    store_token(&mut config, profile, token.unwrap());
                 }
             } else {
-                config.token = Some(login());
+                store_token(&mut config, profile, login());
             }
             confy::store("wyvern", "wyvern", config)?;
             ::std::process::exit(0);
         }
+        Profile(profile_args) => {
+            profiles::parse_args(&mut config, profile_args);
+            confy::store("wyvern", "wyvern", config)?;
+            ::std::process::exit(0);
+        }
+        Tokens(token_args) => {
+            tokens::parse_args(&mut config, token_args);
+            confy::store("wyvern", "wyvern", config)?;
+            ::std::process::exit(0);
+        }
+        Logout => {
+            if let Err(err) = config.account(profile.as_deref()) {
+                error!("{}", err);
+                std::process::exit(64);
+            }
+            if !tokens::has_token(&config, profile.as_deref()) {
+                println!("You're not logged in");
+                ::std::process::exit(0);
+            }
+            if let Err(err) = tokens::set(&mut config, profile.as_deref(), None) {
+                error!("Could not remove your login. Error: {}", err);
+                std::process::exit(64);
+            }
+            confy::store("wyvern", "wyvern", config)?;
+            println!("Logged out");
+            ::std::process::exit(0);
+        }
+        Whoami => {
+            whoami(&mut config, profile.as_deref());
+            confy::store("wyvern", "wyvern", config)?;
+            ::std::process::exit(0);
+        }
         _ => {}
     }
-    if config.token.is_none() {
-        let token = login();
-        config.token = Some(token);
-    }
-    let token_try = config.token.unwrap().refresh();
-    if token_try.is_err() {
-        error!("Could not refresh token. You may need to log in again.");
-        let token = login();
-        config.token = Some(token);
-    } else {
-        config.token = Some(token_try.unwrap());
-    }
-    let gog = Gog::new(config.token.clone().unwrap());
-    let mut sync_saves = config.sync_saves.clone();
-    if sync_saves.is_some() {
-        sync_saves = Some(
-            sync_saves
-                .unwrap()
-                .replace("~", dirs::home_dir().unwrap().to_str().unwrap()),
-        );
+    let account = match config.account(profile.as_deref()) {
+        Ok(account) => account,
+        Err(err) => {
+            error!("{}", err);
+            std::process::exit(64);
+        }
+    };
+    let stored = match tokens::get(&config, profile.as_deref()) {
+        Ok(stored) => stored,
+        Err(err) => {
+            error!("Could not read your login. Error: {}", err);
+            std::process::exit(64);
+        }
+    };
+    let token = match stored {
+        Some(token) => match token.refresh() {
+            Ok(token) => token,
+            Err(_) => {
+                error!("Could not refresh token. You may need to log in again.");
+                login()
+            }
+        },
+        None => login(),
+    };
+    store_token(&mut config, profile.as_deref(), token.clone());
+    let gog = Gog::new(token);
+    // Download defaults of the account turn options on, since flags can't be turned off
+    match args.command {
+        Download {
+            ref mut options, ..
+        } => {
+            options.windows_auto |= account.downloads.windows_auto;
+            options.dlc |= account.downloads.dlc;
+            options.wine.wine = Some(wine::wine_binary(options.wine.wine.take(), &account));
+        }
+        Install { ref mut wine, .. } => {
+            wine.wine = Some(wine::wine_binary(wine.wine.take(), &account));
+        }
+        _ => {}
     }
+    // Sync locations are expanded when they're opened, since they can also be sync profile names
+    let sync_saves = account.sync_saves;
     confy::store("wyvern", "wyvern", config)?;
     parse_args(args, gog, sync_saves)?;
     Ok(())
@@ -196,7 +315,8 @@ fn parse_args(
                 if search_results.is_ok() {
                     info!("Game search results OK");
                     let e = search_results.unwrap().products;
-                    if !options.first {
+                    // Without prompts, the first result is downloaded as with --first
+                    if !options.first && prompt::interactive() {
                         if e.len() > 0 {
                             let mut items: Vec<String> = vec![];
                             for pd in e.iter() {
@@ -215,7 +335,7 @@ fn parse_args(
                             let pname = details.title.clone();
                             info!("Beginning download process");
                             let (name, downloaded_windows) =
-                                download_prep(&gog, details, &options).unwrap();
+                                download_or_exit(&gog, details, &options);
                             if options.install_after.is_some() {
                                 println!("Installing game");
                                 info!("Installing game");
@@ -226,6 +346,7 @@ fn parse_args(
                                     &shortcuts,
                                     downloaded_windows,
                                     options.external_zip,
+                                    &options.wine,
                                 );
                             }
                         } else {
@@ -238,7 +359,7 @@ fn parse_args(
                         let pname = details.title.clone();
                         info!("Beginning download process");
                         let (name, downloaded_windows) =
-                            download_prep(&gog, details, &options).unwrap();
+                            download_or_exit(&gog, details, &options);
                         if options.install_after.is_some() {
                             println!("Installing game");
                             info!("Installing game");
@@ -249,6 +370,7 @@ fn parse_args(
                                 &shortcuts,
                                 downloaded_windows,
                                 options.external_zip,
+                                &options.wine,
                             );
                         }
                     }
@@ -259,7 +381,7 @@ fn parse_args(
                 let details = gog.get_game_details(id).unwrap();
                 let pname = details.title.clone();
                 info!("Beginning download process");
-                let (name, downloaded_windows) = download_prep(&gog, details, &options).unwrap();
+                let (name, downloaded_windows) = download_or_exit(&gog, details, &options);
                 if options.install_after.is_some() {
                     println!("Installing game");
                     info!("Installing game");
@@ -270,6 +392,7 @@ fn parse_args(
                         &shortcuts,
                         downloaded_windows,
                         options.external_zip,
+                        &options.wine,
                     );
                 }
             } else if options.all {
@@ -278,7 +401,7 @@ fn parse_args(
                 for game in games {
                     let details = gog.get_game_details(game).unwrap();
                     info!("Beginning download process");
-                    download_prep(&gog, details, &options).unwrap();
+                    download_or_exit(&gog, details, &options);
                 }
                 if options.install_after.is_some() {
                     println!("--install does not work with --all");
@@ -287,7 +410,7 @@ fn parse_args(
                 error!("Did not specify a game to download. Exiting.");
             }
         }
-        Login  {..} => {}
+        Login  {..} | Profile(..) | Tokens(..) | Logout | Whoami => {}
         Extras {
             game,
             all,
@@ -306,7 +429,7 @@ fn parse_args(
                         error!("Found no games named {} in your library.", search)
                     }
                     let mut i = 0;
-                    if !first {
+                    if !first && prompt::interactive() {
                         let selects = Select::new();
                         let select =
                             selects.with_prompt("Select a game to download extras from:");
@@ -338,13 +461,16 @@ fn parse_args(
             println!("Downloading extras for game {}", details.title);
             let mut folder_name = PathBuf::from(format!("{} Extras", details.title));
             if let Some(output) = output {
-                folder_name = output;
+                folder_name = paths::expand(&output.to_string_lossy());
             }
             if fs::metadata(&folder_name).is_err() {
                 fs::create_dir(&folder_name).expect("Couldn't create extras folder");
             }
             let mut picked: Vec<usize> = vec![];
             if !all && slug.is_none() {
+                if !prompt::interactive() {
+                    prompt::needs_input("Which extras to download", "Pass --all or --slug");
+                }
                 let check = MultiSelect::new();
                 let checks = check.with_prompt("Pick the extras you want to download");
                 for ex in details.extras.iter() {
@@ -434,6 +560,9 @@ fn parse_args(
             }
         }
         Interactive => {
+            if !prompt::interactive() {
+                prompt::needs_input("Everything in interactive mode", "Use the other commands instead");
+            }
             gog = interactive::interactive(gog, sync_saves);
         }
         Install {
@@ -441,6 +570,7 @@ fn parse_args(
             path,
             mut shortcuts,
             windows,
+            wine,
             external_zip,
         } => {
             if shortcuts.shortcuts {
@@ -455,6 +585,7 @@ fn parse_args(
                 &shortcuts,
                 windows,
                 external_zip,
+                &wine,
             );
         }
         #[cfg(feature = "eidolonint")]
@@ -480,6 +611,46 @@ fn parse_args(
         Connect { .. } => {
             gog = connect::parse_args(gog, args);
         }
+        Run {
+            game,
+            env,
+            wrappers,
+            save,
+            sync,
+        } => {
+            let mut installed = match launch::find_game(&game) {
+                Some(installed) => installed,
+                None => {
+                    error!("Could not find an installed game named {}", game);
+                    std::process::exit(64);
+                }
+            };
+            if save {
+                let mut registry = InstallRegistry::load()?;
+                registry.add(installed.clone());
+                if let Some(saved) = registry.games.iter_mut().find(|x| x.path == installed.path) {
+                    saved.env = env.clone();
+                    saved.wrappers = wrappers.clone();
+                }
+                registry.store()?;
+                info!("Saved launch options for {}", installed.name);
+            }
+            if !env.is_empty() || save {
+                installed.env = env;
+            }
+            if !wrappers.is_empty() || save {
+                installed.wrappers = wrappers;
+            }
+            let launched = if sync {
+                sync::around_launch(&gog, sync_saves, &installed, || launch::run(&installed))
+            } else {
+                launch::run(&installed)
+            };
+            if let Err(err) = launched {
+                error!("{}", err);
+                std::process::exit(64);
+            }
+        }
         Update { mut path, dlc } => {
             if path.is_none() {
                 info!("Path not specified. Using current dir");
@@ -493,7 +664,106 @@ fn parse_args(
     };
     Ok(gog)
 }
+/// GOG's login page, which ends up at a URL with a login code once logged in
+const AUTH_URL: &str = "https://login.gog.com/auth?client_id=46899977096215655&layout=client2%22&redirect_uri=https%3A%2F%2Fembed.gog.com%2Fon_login_success%3Forigin%3Dclient&response_type=code";
+/// The login code in the URL GOG's login page ends up at, or what was given if it's not such a URL
+fn login_code(input: &str) -> String {
+    let input = input.trim();
+    url::Url::parse(input)
+        .ok()
+        .and_then(|x| {
+            x.query_pairs()
+                .find(|(key, _)| key == "code")
+                .map(|(_, code)| code.into_owned())
+        })
+        .unwrap_or_else(|| input.to_string())
+}
+/// Opens a URL in the default browser. Returns whether one could be started.
+fn open_browser(url: &str) -> bool {
+    Command::new("xdg-open")
+        .arg(url)
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .is_ok()
+}
+/// Shows the account a profile is logged in as, refreshing its login if it expired but never asking for a new one
+fn whoami(config: &mut Config, profile: Option<&str>) {
+    let stored = match config.account(profile).map(|_| tokens::get(config, profile)) {
+        Ok(Ok(stored)) => stored,
+        Ok(Err(err)) => {
+            error!("Could not read your login. Error: {}", err);
+            std::process::exit(64);
+        }
+        Err(err) => {
+            error!("{}", err);
+            std::process::exit(64);
+        }
+    };
+    let mut token = match stored {
+        Some(token) => token,
+        None => {
+            println!("You're not logged in. Log in with wyvern login");
+            std::process::exit(64);
+        }
+    };
+    if token.is_expired() {
+        match token.refresh() {
+            Ok(refreshed) => {
+                token = refreshed;
+                store_token(config, profile, token.clone());
+            }
+            Err(err) => {
+                error!("Your login expired and could not be refreshed. Log in again with wyvern login. Error: {}", err);
+                std::process::exit(64);
+            }
+        }
+    }
+    let expires = std::time::UNIX_EPOCH
+        + std::time::Duration::from_secs(token.updated_at + token.expires_in);
+    match Gog::new(token.clone()).get_user_data() {
+        Ok(user) => {
+            println!("Logged in as {} ({})", user.username, user.email);
+            println!("User id: {}", user.user_id);
+        }
+        Err(err) => {
+            warn!("Could not fetch your account's details. Error: {}", err);
+            println!("User id: {}", token.user_id);
+        }
+    }
+    if let Some(profile) = profile {
+        println!("Profile: {}", profile);
+    }
+    println!(
+        "Access token expires: {}",
+        humantime::format_rfc3339_seconds(expires)
+    );
+}
+/// Downloads a game, exiting if the download fails or is incomplete
+fn download_or_exit(
+    gog: &Gog,
+    details: GameDetails,
+    options: &DownloadOptions,
+) -> (Vec<String>, bool) {
+    download_prep(gog, details, options).unwrap_or_else(|err| {
+        error!("Could not download game. Error: {}", err);
+        std::process::exit(64);
+    })
+}
+/// Stores a login the way the config asks for, exiting if it can't be
+fn store_token(config: &mut Config, profile: Option<&str>, token: Token) {
+    if let Err(err) = tokens::set(config, profile, Some(token)) {
+        error!("Could not store your login. Error: {}", err);
+        std::process::exit(64);
+    }
+}
 pub fn login() -> Token {
+    if !prompt::interactive() {
+        prompt::needs_input(
+            "A GOG login",
+            "Log in first with wyvern login --code, using the URL from wyvern login --print-url",
+        );
+    }
     let choices = ["OAuth Token Login", "Username/Password Login"];
     println!("It appears that you have not logged into GOG. Please pick a login method.");
     let pick = Select::new()
@@ -504,14 +774,19 @@ pub fn login() -> Token {
     match pick {
         //OAuth Token
         0 => {
-            println!("Please go to the following URL, log into GOG, and paste the code from the resulting url's ?code parameter into the input here.");
-            println!("https://login.gog.com/auth?client_id=46899977096215655&layout=client2%22&redirect_uri=https%3A%2F%2Fembed.gog.com%2Fon_login_success%3Forigin%3Dclient&response_type=code");
+            if open_browser(AUTH_URL) {
+                println!("Log into GOG in the browser that just opened. If it didn't, go to the following URL.");
+            } else {
+                println!("Please go to the following URL and log into GOG.");
+            }
+            println!("{}", AUTH_URL);
+            println!("Once you're logged in, paste the URL the page ends up at, or just its ?code parameter, into the input here.");
             io::stdout().flush().unwrap();
             let token: Token;
             loop {
-                let code: String = Input::new().with_prompt("Code:").interact().unwrap();
+                let code: String = Input::new().with_prompt("URL or code:").interact().unwrap();
                 info!("Creating token from input");
-                let attempt_token = Token::from_login_code(code.as_str());
+                let attempt_token = Token::from_login_code(login_code(&code));
                 if attempt_token.is_ok() {
                     token = attempt_token.unwrap();
                     println!("Got token. Thanks!");
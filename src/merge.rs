@@ -0,0 +1,602 @@
+/// Three-way syncing of save files. Each machine remembers what every save file looked like when it last synced, so that it can tell
+/// whether a file changed locally, in the sync folder, or on both sides since then, instead of guessing from modification times.
+use dialoguer::Select;
+use dirsync;
+use hashing;
+use prompt;
+use serde_json;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::SystemTime;
+use storage::{self, StoredFile};
+lazy_static! {
+    /// Sync states of pushes that haven't reached the remote sync location yet. If they were stored before, a failed upload would make the stale remote saves look like newer ones on the next sync.
+    static ref PUSHED: Mutex<Vec<(PathBuf, SyncState)>> = Mutex::new(vec![]);
+}
+/// Which side wins when a save file changed on both sides
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Prefer {
+    Local,
+    Remote,
+    /// The side that was modified most recently
+    Newest,
+}
+impl FromStr for Prefer {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Prefer, String> {
+        match s {
+            "local" => Ok(Prefer::Local),
+            "remote" => Ok(Prefer::Remote),
+            "newest" => Ok(Prefer::Newest),
+            _ => Err(format!("{} isn't one of local, remote or newest", s)),
+        }
+    }
+}
+/// How a save file differs from when it was last synced
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Change {
+    Unchanged,
+    Local,
+    Remote,
+    Both,
+}
+/// How conflicts get resolved
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Resolution {
+    pub prefer: Option<Prefer>,
+    /// Without a preference, let whichever side is being synced from win
+    pub force: bool,
+    /// Without a preference, give up instead of asking
+    pub refuse: bool,
+}
+/// What a three-way sync did
+#[derive(Default, Debug)]
+pub struct Summary {
+    pub pulled: usize,
+    pub deleted: usize,
+    pub conflicts: usize,
+    pub pushed: Option<dirsync::Summary>,
+}
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} pulled, {} deleted locally, {} conflicts resolved",
+            self.pulled, self.deleted, self.conflicts
+        )?;
+        if let Some(ref pushed) = self.pushed {
+            write!(f, ". Pushed: {}", pushed)?;
+        }
+        Ok(())
+    }
+}
+/// Hashes of every save file as of the last sync, kept on this machine for each sync folder
+#[derive(Serialize, Deserialize, Default, Clone)]
+struct SyncState {
+    files: BTreeMap<PathBuf, String>,
+    #[serde(default)]
+    pushed: Option<SystemTime>,
+    #[serde(default)]
+    pulled: Option<SystemTime>,
+}
+impl SyncState {
+    fn path(sync_folder: &Path) -> PathBuf {
+        let absolute = std::env::current_dir()
+            .map(|x| x.join(sync_folder))
+            .unwrap_or_else(|_| sync_folder.to_path_buf());
+        let key = hashing::sha1(absolute.to_string_lossy().as_bytes());
+        dirs::data_dir()
+            .unwrap_or_else(|| dirs::home_dir().unwrap().join(".local/share"))
+            .join("wyvern/sync-state")
+            .join(key + ".json")
+    }
+    fn load(sync_folder: &Path) -> Result<SyncState, io::Error> {
+        let pushed = PUSHED.lock().unwrap();
+        if let Some((_, state)) = pushed.iter().rev().find(|x| x.0 == sync_folder) {
+            return Ok(state.clone());
+        }
+        match fs::read_to_string(SyncState::path(sync_folder)) {
+            Ok(read) => serde_json::from_str(&read)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)),
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(SyncState::default()),
+            Err(err) => Err(err),
+        }
+    }
+    fn store(&self, sync_folder: &Path) -> Result<(), io::Error> {
+        let path = SyncState::path(sync_folder);
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(path, serde_json::to_string(&self).unwrap())
+    }
+}
+/// Stores the sync states of pushes once they've been uploaded to the remote sync location
+pub fn store_pushed() -> Result<(), io::Error> {
+    for (sync_folder, state) in PUSHED.lock().unwrap().drain(..) {
+        state.store(&sync_folder)?;
+    }
+    Ok(())
+}
+/// When this machine last pushed and pulled a game's saves with its sync folder
+pub fn last_synced(sync_folder: &Path) -> (Option<SystemTime>, Option<SystemTime>) {
+    SyncState::load(sync_folder)
+        .map(|x| (x.pushed, x.pulled))
+        .unwrap_or((None, None))
+}
+/// Forgets what a game's saves looked like when they were last synced, so that the next sync treats them as new
+pub fn forget(sync_folder: &Path) -> Result<(), io::Error> {
+    match fs::remove_file(SyncState::path(sync_folder)) {
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        removed => removed,
+    }
+}
+fn classify(base: Option<&String>, local: Option<&String>, remote: Option<&String>) -> Change {
+    if local == remote {
+        Change::Unchanged
+    } else if local == base {
+        Change::Remote
+    } else if remote == base {
+        Change::Local
+    } else {
+        Change::Both
+    }
+}
+/// Asks which side should win for files changed on both sides
+fn ask(conflicts: &[PathBuf]) -> Option<Prefer> {
+    println!(
+        "These save files changed both here and in the sync folder since they were last synced:"
+    );
+    for path in conflicts {
+        println!("  {}", path.display());
+    }
+    if !prompt::interactive() {
+        warn!("Aborting, since conflicts are only resolved without asking with --prefer");
+        return None;
+    }
+    let choices = [
+        "Keep the local versions",
+        "Keep the synced versions",
+        "Keep whichever is newest",
+        "Abort",
+    ];
+    let pick = Select::new()
+        .with_prompt("Resolve conflicts")
+        .default(3)
+        .items(&choices)
+        .interact()
+        .ok()?;
+    match pick {
+        0 => Some(Prefer::Local),
+        1 => Some(Prefer::Remote),
+        2 => Some(Prefer::Newest),
+        _ => None,
+    }
+}
+/// Syncs a game's saves with its sync folder. Changes made in the sync folder are always brought into the local saves; when pushing, the merged local saves are then written back to the sync folder.
+/// Returns None if syncing was aborted because of conflicts.
+pub fn sync(
+    saves: &Path,
+    sync_folder: &Path,
+    push: bool,
+    resolution: &Resolution,
+    options: &dirsync::Options,
+) -> Result<Option<Summary>, io::Error> {
+    let mut state = SyncState::load(sync_folder)?;
+    let stored = storage::stored_files(saves, sync_folder)?;
+    let single_file = storage::is_single_file(saves, &stored);
+    let local_paths = storage::local_files(saves)?;
+    let mut local = BTreeMap::new();
+    for (relative, path) in local_paths.iter() {
+        local.insert(relative.clone(), hashing::sha1_file(path)?);
+    }
+    let remote: BTreeMap<PathBuf, String> = stored
+        .iter()
+        .map(|(relative, file)| (relative.clone(), hashing::sha1(&file.contents)))
+        .collect();
+    let all: BTreeSet<&PathBuf> = local.keys().chain(remote.keys()).collect();
+    let mut take_remote = vec![];
+    let mut conflicts = vec![];
+    for relative in all {
+        let change = classify(
+            state.files.get(relative),
+            local.get(relative),
+            remote.get(relative),
+        );
+        match change {
+            Change::Remote => take_remote.push(relative.clone()),
+            Change::Both => conflicts.push(relative.clone()),
+            Change::Local | Change::Unchanged => {}
+        }
+    }
+    let mut summary = Summary::default();
+    if !conflicts.is_empty() {
+        let prefer = match (resolution.prefer, resolution.force, resolution.refuse) {
+            (Some(prefer), _, _) => Some(prefer),
+            (None, true, _) if push => Some(Prefer::Local),
+            (None, true, _) => Some(Prefer::Remote),
+            (None, false, true) => None,
+            (None, false, false) => ask(&conflicts),
+        };
+        let prefer = match prefer {
+            Some(prefer) => prefer,
+            None => return Ok(None),
+        };
+        summary.conflicts = conflicts.len();
+        for relative in conflicts {
+            let remote_wins = match prefer {
+                Prefer::Local => false,
+                Prefer::Remote => true,
+                Prefer::Newest => newer_remote(local_paths.get(&relative), stored.get(&relative)),
+            };
+            if remote_wins {
+                take_remote.push(relative);
+            }
+        }
+    }
+    let mut kept = vec![];
+    for relative in take_remote {
+        let path = storage::local_path(saves, &relative, single_file);
+        match stored.get(&relative) {
+            Some(file) => {
+                storage::write_file(&path, file)?;
+                summary.pulled += 1;
+            }
+            None if !options.delete => {
+                if let Some(hash) = local.get(&relative) {
+                    kept.push((relative.clone(), hash.clone()));
+                }
+                info!(
+                    "Keeping {}, which was deleted from the sync folder. Pass --delete to delete it",
+                    path.display()
+                );
+            }
+            None => {
+                info!("Deleting {}", path.display());
+                fs::remove_file(&path)?;
+                summary.deleted += 1;
+            }
+        }
+    }
+    if push {
+        if !saves.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("There are no save files at {}", saves.display()),
+            ));
+        }
+        summary.pushed = Some(storage::push(saves, sync_folder, options)?);
+        let mut files = BTreeMap::new();
+        for (relative, path) in storage::local_files(saves)? {
+            files.insert(relative, hashing::sha1_file(&path)?);
+        }
+        if !options.delete {
+            // Files deleted locally are still in the sync folder, and stay deleted here until they change there
+            for (relative, hash) in remote {
+                files.entry(relative).or_insert(hash);
+            }
+        }
+        state.files = files;
+        state.pushed = Some(SystemTime::now());
+        PUSHED
+            .lock()
+            .unwrap()
+            .push((sync_folder.to_path_buf(), state));
+        return Ok(Some(summary));
+    } else {
+        // Files kept after being deleted from the sync folder are remembered, so that a later --delete still deletes them
+        state.files = remote.into_iter().chain(kept).collect();
+        state.pulled = Some(SystemTime::now());
+    }
+    state.store(sync_folder)?;
+    Ok(Some(summary))
+}
+/// One side of a save file that differs between the local saves and the sync folder
+pub struct Version {
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+}
+/// A save file that differs between the local saves and the sync folder, and which side changed it since the last sync
+pub struct FileDiff {
+    pub path: PathBuf,
+    pub local: Option<Version>,
+    pub synced: Option<Version>,
+    pub change: Change,
+}
+/// Compares a game's local saves with those in its sync folder without changing either. Files that are the same on both sides are left out.
+pub fn diff(saves: &Path, sync_folder: &Path) -> Result<Vec<FileDiff>, io::Error> {
+    let state = SyncState::load(sync_folder)?;
+    let stored = storage::stored_files(saves, sync_folder)?;
+    let local_paths = storage::local_files(saves)?;
+    let mut local = BTreeMap::new();
+    for (relative, path) in local_paths.iter() {
+        local.insert(relative.clone(), hashing::sha1_file(path)?);
+    }
+    let all: BTreeSet<&PathBuf> = local.keys().chain(stored.keys()).collect();
+    let mut diffs = vec![];
+    for relative in all {
+        let remote = stored.get(relative).map(|x| hashing::sha1(&x.contents));
+        let change = classify(
+            state.files.get(relative),
+            local.get(relative),
+            remote.as_ref(),
+        );
+        if change == Change::Unchanged {
+            continue;
+        }
+        let local = match local_paths.get(relative) {
+            Some(path) => {
+                let metadata = fs::metadata(path)?;
+                Some(Version {
+                    size: metadata.len(),
+                    modified: metadata.modified().ok(),
+                })
+            }
+            None => None,
+        };
+        diffs.push(FileDiff {
+            path: relative.clone(),
+            local,
+            synced: stored.get(relative).map(|x| Version {
+                size: x.contents.len() as u64,
+                modified: x.modified,
+            }),
+            change,
+        });
+    }
+    Ok(diffs)
+}
+/// For a conflict, whether the synced version was modified more recently than the local one. Deleting a file doesn't count as modifying it, so the side that still has it wins.
+fn newer_remote(local: Option<&PathBuf>, remote: Option<&StoredFile>) -> bool {
+    let local_modified = local.and_then(|x| fs::metadata(x).ok()?.modified().ok());
+    match (local_modified, remote) {
+        (_, None) => false,
+        (None, Some(_)) => true,
+        (Some(local_modified), Some(remote)) => {
+            remote.modified.map(|x| x > local_modified).unwrap_or(false)
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::sync::MutexGuard;
+    use std::time::Duration;
+    lazy_static! {
+        static ref ENV: Mutex<()> = Mutex::new(());
+    }
+    /// Keeps the sync states and config a test uses inside its temp dir. Tests that sync run one at a time, since these are process-wide.
+    fn isolate(dir: &Path) -> MutexGuard<'static, ()> {
+        let guard = ENV.lock().unwrap_or_else(|x| x.into_inner());
+        env::set_var("XDG_DATA_HOME", dir.join("data"));
+        env::set_var("XDG_CONFIG_HOME", dir.join("config"));
+        guard
+    }
+    fn write(path: &Path, contents: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+    fn read(path: &Path) -> String {
+        fs::read_to_string(path).unwrap()
+    }
+    fn options(delete: bool) -> dirsync::Options {
+        dirsync::Options {
+            delete,
+            checksum: true,
+            ..Default::default()
+        }
+    }
+    fn prefer(prefer: Prefer) -> Resolution {
+        Resolution {
+            prefer: Some(prefer),
+            ..Default::default()
+        }
+    }
+    fn push(saves: &Path, sync_folder: &Path, delete: bool) -> Summary {
+        let summary = sync(
+            saves,
+            sync_folder,
+            true,
+            &Resolution::default(),
+            &options(delete),
+        )
+        .unwrap()
+        .unwrap();
+        store_pushed().unwrap();
+        summary
+    }
+    fn pull(
+        saves: &Path,
+        sync_folder: &Path,
+        resolution: &Resolution,
+        delete: bool,
+    ) -> Option<Summary> {
+        sync(saves, sync_folder, false, resolution, &options(delete)).unwrap()
+    }
+    /// A game's saves, pushed once so that both sides have a last synced state
+    fn synced(dir: &Path) -> (PathBuf, PathBuf) {
+        let saves = dir.join("saves");
+        let sync_folder = dir.join("sync/saves/gog_1");
+        write(&saves.join("slot1.sav"), "one");
+        write(&saves.join("profiles/me.cfg"), "me");
+        push(&saves, &sync_folder, false);
+        (saves, sync_folder)
+    }
+    #[test]
+    fn classifies_changes() {
+        let (old, new, newer) = ("old".to_string(), "new".to_string(), "newer".to_string());
+        assert_eq!(
+            classify(Some(&old), Some(&old), Some(&old)),
+            Change::Unchanged
+        );
+        assert_eq!(
+            classify(Some(&old), Some(&new), Some(&new)),
+            Change::Unchanged
+        );
+        assert_eq!(classify(None, Some(&new), Some(&new)), Change::Unchanged);
+        assert_eq!(classify(Some(&old), Some(&new), Some(&old)), Change::Local);
+        assert_eq!(classify(None, Some(&new), None), Change::Local);
+        assert_eq!(classify(Some(&old), Some(&old), Some(&new)), Change::Remote);
+        assert_eq!(classify(Some(&old), Some(&old), None), Change::Remote);
+        assert_eq!(classify(Some(&old), Some(&new), Some(&newer)), Change::Both);
+        assert_eq!(classify(None, Some(&new), Some(&newer)), Change::Both);
+        assert_eq!(classify(Some(&old), None, Some(&new)), Change::Both);
+    }
+    #[test]
+    fn syncs_changes_from_either_side() {
+        let dir = tempfile::tempdir().unwrap();
+        let _env = isolate(dir.path());
+        let (saves, sync_folder) = synced(dir.path());
+        assert_eq!(read(&sync_folder.join("slot1.sav")), "one");
+        let summary = pull(&saves, &sync_folder, &Resolution::default(), false).unwrap();
+        assert_eq!((summary.pulled, summary.conflicts), (0, 0));
+        write(&sync_folder.join("slot1.sav"), "remote");
+        write(&saves.join("profiles/me.cfg"), "local");
+        let summary = pull(&saves, &sync_folder, &Resolution::default(), false).unwrap();
+        assert_eq!((summary.pulled, summary.conflicts), (1, 0));
+        assert_eq!(read(&saves.join("slot1.sav")), "remote");
+        assert_eq!(read(&saves.join("profiles/me.cfg")), "local");
+        assert_eq!(read(&sync_folder.join("profiles/me.cfg")), "me");
+        write(&sync_folder.join("slot2.sav"), "two");
+        push(&saves, &sync_folder, false);
+        assert_eq!(read(&saves.join("slot2.sav")), "two");
+        assert_eq!(read(&sync_folder.join("profiles/me.cfg")), "local");
+        assert!(diff(&saves, &sync_folder).unwrap().is_empty());
+    }
+    #[test]
+    fn resolves_conflicts() {
+        let dir = tempfile::tempdir().unwrap();
+        let _env = isolate(dir.path());
+        let (saves, sync_folder) = synced(dir.path());
+        write(&saves.join("slot1.sav"), "local");
+        write(&sync_folder.join("slot1.sav"), "remote");
+        let diffs = diff(&saves, &sync_folder).unwrap();
+        assert_eq!(diffs.len(), 1);
+        assert_eq!(diffs[0].change, Change::Both);
+        let refuse = Resolution {
+            refuse: true,
+            ..Default::default()
+        };
+        assert!(pull(&saves, &sync_folder, &refuse, false).is_none());
+        assert_eq!(read(&saves.join("slot1.sav")), "local");
+        let summary = pull(&saves, &sync_folder, &prefer(Prefer::Local), false).unwrap();
+        assert_eq!((summary.pulled, summary.conflicts), (0, 1));
+        assert_eq!(read(&saves.join("slot1.sav")), "local");
+        // The synced version is now the last synced one, so only changing it again conflicts
+        assert_eq!(diff(&saves, &sync_folder).unwrap()[0].change, Change::Local);
+        write(&sync_folder.join("slot1.sav"), "remote again");
+        let forced = Resolution {
+            force: true,
+            ..Default::default()
+        };
+        let summary = pull(&saves, &sync_folder, &forced, false).unwrap();
+        assert_eq!((summary.pulled, summary.conflicts), (1, 1));
+        assert_eq!(read(&saves.join("slot1.sav")), "remote again");
+        write(&saves.join("slot1.sav"), "local");
+        write(&sync_folder.join("slot1.sav"), "remote");
+        let summary = sync(&saves, &sync_folder, true, &forced, &options(false))
+            .unwrap()
+            .unwrap();
+        store_pushed().unwrap();
+        assert_eq!(summary.conflicts, 1);
+        assert_eq!(read(&sync_folder.join("slot1.sav")), "local");
+    }
+    #[test]
+    fn prefers_the_newest_side() {
+        let dir = tempfile::tempdir().unwrap();
+        let _env = isolate(dir.path());
+        let (saves, sync_folder) = synced(dir.path());
+        let now = SystemTime::now();
+        let touch = |path: &Path, contents: &str, modified: SystemTime| {
+            write(path, contents);
+            let file = fs::OpenOptions::new().write(true).open(path).unwrap();
+            file.set_modified(modified).unwrap();
+        };
+        touch(
+            &saves.join("slot1.sav"),
+            "local",
+            now - Duration::from_secs(60),
+        );
+        touch(&sync_folder.join("slot1.sav"), "remote", now);
+        touch(&saves.join("profiles/me.cfg"), "local", now);
+        touch(
+            &sync_folder.join("profiles/me.cfg"),
+            "remote",
+            now - Duration::from_secs(60),
+        );
+        let summary = pull(&saves, &sync_folder, &prefer(Prefer::Newest), false).unwrap();
+        assert_eq!((summary.pulled, summary.conflicts), (1, 2));
+        assert_eq!(read(&saves.join("slot1.sav")), "remote");
+        assert_eq!(read(&saves.join("profiles/me.cfg")), "local");
+    }
+    #[test]
+    fn keeps_deleted_files_without_delete() {
+        let dir = tempfile::tempdir().unwrap();
+        let _env = isolate(dir.path());
+        let (saves, sync_folder) = synced(dir.path());
+        fs::remove_file(sync_folder.join("slot1.sav")).unwrap();
+        let summary = pull(&saves, &sync_folder, &Resolution::default(), false).unwrap();
+        assert_eq!(summary.deleted, 0);
+        assert!(saves.join("slot1.sav").exists());
+        // The kept file is remembered, so a later --delete still deletes it
+        let summary = pull(&saves, &sync_folder, &Resolution::default(), true).unwrap();
+        assert_eq!(summary.deleted, 1);
+        assert!(!saves.join("slot1.sav").exists());
+        fs::remove_file(saves.join("profiles/me.cfg")).unwrap();
+        push(&saves, &sync_folder, false);
+        assert!(sync_folder.join("profiles/me.cfg").exists());
+        // Without --delete the file stays in the sync folder, but isn't brought back here
+        pull(&saves, &sync_folder, &Resolution::default(), false).unwrap();
+        assert!(!saves.join("profiles/me.cfg").exists());
+        push(&saves, &sync_folder, true);
+        assert!(!sync_folder.join("profiles/me.cfg").exists());
+    }
+    #[test]
+    fn syncs_single_file_saves() {
+        let dir = tempfile::tempdir().unwrap();
+        let _env = isolate(dir.path());
+        let saves = dir.path().join("game.sav");
+        let sync_folder = dir.path().join("sync/saves/gog_1");
+        write(&saves, "saved");
+        push(&saves, &sync_folder, false);
+        assert_eq!(read(&sync_folder.join("game.sav")), "saved");
+        write(&sync_folder.join("game.sav"), "saved elsewhere");
+        let summary = pull(&saves, &sync_folder, &Resolution::default(), false).unwrap();
+        assert_eq!(summary.pulled, 1);
+        assert_eq!(read(&saves), "saved elsewhere");
+        fs::remove_file(&saves).unwrap();
+        forget(&sync_folder).unwrap();
+        pull(&saves, &sync_folder, &Resolution::default(), false).unwrap();
+        assert!(saves.is_file());
+        assert_eq!(read(&saves), "saved elsewhere");
+    }
+    #[test]
+    fn stores_push_states_once_uploaded() {
+        let dir = tempfile::tempdir().unwrap();
+        let _env = isolate(dir.path());
+        let saves = dir.path().join("saves");
+        let sync_folder = dir.path().join("sync/saves/gog_1");
+        write(&saves.join("slot1.sav"), "one");
+        assert_eq!(last_synced(&sync_folder), (None, None));
+        sync(
+            &saves,
+            &sync_folder,
+            true,
+            &Resolution::default(),
+            &options(false),
+        )
+        .unwrap()
+        .unwrap();
+        assert!(last_synced(&sync_folder).0.is_some());
+        assert!(!SyncState::path(&sync_folder).exists());
+        store_pushed().unwrap();
+        assert!(SyncState::path(&sync_folder).exists());
+        let state = SyncState::load(&sync_folder).unwrap();
+        assert_eq!(state.files[Path::new("slot1.sav")], hashing::sha1(b"one"));
+        forget(&sync_folder).unwrap();
+        assert_eq!(last_synced(&sync_folder), (None, None));
+    }
+}
@@ -0,0 +1,157 @@
+/// Expanding the home directory and environment variables in paths from the config and savedb, and writing paths so that they work on other machines.
+use std::env;
+use std::path::{Path, PathBuf};
+/// What XDG base directory variables mean when they aren't set, relative to the home directory
+const XDG_DEFAULTS: &[(&str, &str)] = &[
+    ("XDG_DATA_HOME", ".local/share"),
+    ("XDG_CONFIG_HOME", ".config"),
+    ("XDG_CACHE_HOME", ".cache"),
+    ("XDG_STATE_HOME", ".local/state"),
+];
+fn home() -> PathBuf {
+    dirs::home_dir().unwrap()
+}
+fn variable(name: &str) -> Option<String> {
+    if let Ok(value) = env::var(name) {
+        if !value.is_empty() {
+            return Some(value);
+        }
+    }
+    if name == "HOME" {
+        return Some(home().display().to_string());
+    }
+    XDG_DEFAULTS
+        .iter()
+        .find(|&&(variable, _)| variable == name)
+        .map(|&(_, default)| home().join(default).display().to_string())
+}
+/// Expands variables, calling `unset` with the name and written form of those that aren't set
+fn expand_with<F>(path: &str, mut unset: F) -> String
+where
+    F: FnMut(&str, &str) -> Option<String>,
+{
+    let mut expanded = String::new();
+    let mut rest = path;
+    if rest == "~" || rest.starts_with("~/") {
+        expanded.push_str(&home().display().to_string());
+        rest = &rest[1..];
+    }
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let (name, written) = if after.starts_with('{') {
+            match after.find('}') {
+                Some(end) => (&after[1..end], &rest[start..start + end + 2]),
+                None => ("", "$"),
+            }
+        } else {
+            let end = after
+                .find(|x: char| !(x.is_ascii_alphanumeric() || x == '_'))
+                .unwrap_or(after.len());
+            (&after[..end], &rest[start..start + end + 1])
+        };
+        let valid = !name.is_empty() && !name.starts_with(|x: char| x.is_ascii_digit());
+        match variable(name).filter(|_| valid) {
+            Some(value) => expanded.push_str(&value),
+            None if valid => expanded.push_str(&unset(name, written).unwrap_or_default()),
+            None => expanded.push_str(written),
+        }
+        rest = &rest[start + written.len()..];
+    }
+    expanded.push_str(rest);
+    expanded
+}
+/// Expands a leading ~ and $VARIABLE or ${VARIABLE} environment variables in a path. XDG base directory variables that aren't set get their usual values. Other variables that aren't set are left as written.
+pub fn expand(path: &str) -> PathBuf {
+    PathBuf::from(expand_with(path, |name, written| {
+        warn!("{} is not set, so {} can't be expanded", name, path);
+        Some(written.to_string())
+    }))
+}
+/// Like expand, but returns None if a variable isn't set
+pub fn try_expand(path: &str) -> Option<PathBuf> {
+    let mut missing = false;
+    let expanded = expand_with(path, |_, _| {
+        missing = true;
+        None
+    });
+    if missing {
+        None
+    } else {
+        Some(PathBuf::from(expanded))
+    }
+}
+/// Writes a path under the home directory with a leading ~, so that it stays valid on machines where the home directory differs
+pub fn portable(path: &Path) -> String {
+    match path.strip_prefix(home()) {
+        Ok(rest) if rest.as_os_str().is_empty() => "~".to_string(),
+        Ok(rest) => format!("~/{}", rest.display()),
+        Err(_) => path.display().to_string(),
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn home() -> String {
+        super::home().display().to_string()
+    }
+    #[test]
+    fn expands_home() {
+        assert_eq!(expand_with("~", |_, _| None), home());
+        assert_eq!(expand_with("~/saves", |_, _| None), home() + "/saves");
+        assert_eq!(expand_with("~other/saves", |_, _| None), "~other/saves");
+        assert_eq!(
+            expand_with("C:/PROGRA~1/Game", |_, _| None),
+            "C:/PROGRA~1/Game"
+        );
+    }
+    #[test]
+    fn expands_variables() {
+        env::set_var("WYVERN_PATHS_TEST", "/games");
+        assert_eq!(
+            expand_with("$WYVERN_PATHS_TEST/saves", |_, _| None),
+            "/games/saves"
+        );
+        assert_eq!(
+            expand_with("${WYVERN_PATHS_TEST}saves", |_, _| None),
+            "/gamessaves"
+        );
+        assert_eq!(
+            expand_with("$XDG_DATA_HOME/game", |_, _| None),
+            variable("XDG_DATA_HOME").unwrap() + "/game"
+        );
+    }
+    #[test]
+    fn leaves_other_dollars_alone() {
+        assert_eq!(
+            expand_with("${WYVERN_PATHS_TEST/saves", |_, _| None),
+            "${WYVERN_PATHS_TEST/saves"
+        );
+        assert_eq!(expand_with("save$5.dat", |_, _| None), "save$5.dat");
+        assert_eq!(expand_with("cost$", |_, _| None), "cost$");
+        assert_eq!(expand_with("$/saves", |_, _| None), "$/saves");
+    }
+    #[test]
+    fn reports_unset_variables() {
+        let mut unset = vec![];
+        let expanded = expand_with(
+            "$WYVERN_PATHS_UNSET/${WYVERN_PATHS_UNSET}",
+            |name, written| {
+                unset.push(name.to_string());
+                Some(written.to_string())
+            },
+        );
+        assert_eq!(expanded, "$WYVERN_PATHS_UNSET/${WYVERN_PATHS_UNSET}");
+        assert_eq!(unset, vec!["WYVERN_PATHS_UNSET", "WYVERN_PATHS_UNSET"]);
+        assert_eq!(try_expand("$WYVERN_PATHS_UNSET/saves"), None);
+    }
+    #[test]
+    fn writes_portable_paths() {
+        let home = super::home();
+        assert_eq!(portable(&home), "~");
+        assert_eq!(portable(&home.join("saves/slot")), "~/saves/slot");
+        assert_eq!(portable(Path::new("/opt/game")), "/opt/game");
+        let sibling = format!("{}-other", home.display());
+        assert_eq!(portable(Path::new(&sibling)), sibling);
+    }
+}
@@ -0,0 +1,90 @@
+/// Managing the profiles of GOG accounts in the config, for `wyvern profile`
+use args::Profiles;
+use args::Profiles::*;
+use config::*;
+use tokens;
+pub fn parse_args(config: &mut Config, args: Profiles) {
+    match args {
+        Add {
+            name,
+            sync_saves,
+            windows_auto,
+            dlc,
+        } => {
+            if config.profiles.contains_key(&name) {
+                error!("There already is a profile named {}", name);
+                std::process::exit(64);
+            }
+            config.profiles.insert(
+                name.clone(),
+                Account {
+                    sync_saves,
+                    downloads: DownloadDefaults { windows_auto, dlc },
+                    ..Account::default()
+                },
+            );
+            println!(
+                "Added profile {}. Log into its account with wyvern --profile {} login",
+                name, name
+            );
+        }
+        List => {
+            let default = config.default_profile.as_deref();
+            println!(
+                "{}(top of the config) - {}",
+                if default.is_none() { "* " } else { "" },
+                logged_in(tokens::has_token(config, None))
+            );
+            for (name, account) in config.profiles.iter() {
+                print!(
+                    "{}{} - {}",
+                    if default == Some(name.as_str()) {
+                        "* "
+                    } else {
+                        ""
+                    },
+                    name,
+                    logged_in(tokens::has_token(config, Some(name)))
+                );
+                match account.sync_saves {
+                    Some(ref sync_saves) => println!(", syncs saves to {}", sync_saves),
+                    None => println!(),
+                }
+            }
+        }
+        Remove { name } => {
+            if !config.profiles.contains_key(&name) {
+                error!("There is no profile named {}", name);
+                std::process::exit(64);
+            }
+            if let Err(err) = tokens::set(config, Some(&name), None) {
+                error!("Could not remove the login of {}. Error: {}", name, err);
+                std::process::exit(64);
+            }
+            config.profiles.remove(&name);
+            if config.default_profile.as_deref() == Some(name.as_str()) {
+                config.default_profile = None;
+            }
+            println!("Removed profile {}", name);
+        }
+        Default { name } => {
+            if let Some(ref name) = name {
+                if !config.profiles.contains_key(name) {
+                    error!("There is no profile named {}", name);
+                    std::process::exit(64);
+                }
+                println!("Using profile {} by default", name);
+            } else {
+                println!("Using the account at the top of the config by default");
+            }
+            config.default_profile = name;
+        }
+    }
+}
+fn logged_in(token: bool) -> &'static str {
+    if token {
+        "logged in"
+    } else {
+        "not logged in"
+    }
+}
@@ -0,0 +1,40 @@
+/// Whether wyvern may ask for input. With --non-interactive, or when stdin isn't a terminal, prompts take their default answer, or wyvern exits with NEEDS_INPUT when there's no sensible default.
+use dialoguer::Confirm;
+use std::io::{self, IsTerminal};
+use std::sync::atomic::{AtomicBool, Ordering};
+/// Exit code for when wyvern needs input it isn't allowed to ask for
+pub const NEEDS_INPUT: i32 = 65;
+static INTERACTIVE: AtomicBool = AtomicBool::new(true);
+pub fn setup(non_interactive: bool) {
+    INTERACTIVE.store(
+        !non_interactive && io::stdin().is_terminal(),
+        Ordering::SeqCst,
+    );
+}
+pub fn interactive() -> bool {
+    INTERACTIVE.load(Ordering::SeqCst)
+}
+/// Exits because something has to be asked for. The hint says how to give it without being asked.
+pub fn needs_input(what: &str, hint: &str) -> ! {
+    error!(
+        "{} can't be asked for when running non-interactively. {}",
+        what, hint
+    );
+    std::process::exit(NEEDS_INPUT);
+}
+/// Asks a yes or no question, answering it with the default when running non-interactively
+pub fn confirm(question: &str, default: bool) -> bool {
+    if !interactive() {
+        info!(
+            "Answering {} to: {}",
+            if default { "yes" } else { "no" },
+            question
+        );
+        return default;
+    }
+    Confirm::new()
+        .with_prompt(question)
+        .default(default)
+        .interact()
+        .unwrap_or(default)
+}
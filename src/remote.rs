@@ -0,0 +1,1033 @@
+/// Places save files can be synced to besides a local directory. Syncing works on a local mirror of the remote sync folder:
+/// it's brought up to date before a sync command runs, and whatever the command changed in it is uploaded afterwards.
+use config::*;
+use dirsync;
+use gitrepo;
+use hashing::{self, to_hex};
+use hmac::{Hmac, Mac};
+use merge;
+use paths;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use reqwest::blocking::{Client, RequestBuilder, Response};
+use reqwest::{Method, StatusCode};
+use serde_json;
+use sha2::{Digest, Sha256};
+use ssh2::{CheckResult, KnownHostFileKind, RenameFlags, Session};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use url::Url;
+use walkdir::WalkDir;
+/// Characters that S3 and WebDAV paths need escaped
+const PATH_ESCAPE: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+/// A configured place to sync saves to, picked by setting sync_saves to its name
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "backend", rename_all = "lowercase")]
+pub enum Profile {
+    Local {
+        path: PathBuf,
+    },
+    Webdav {
+        /// URL of the folder to sync into, like https://cloud.example.com/remote.php/dav/files/me/wyvern
+        url: String,
+        username: Option<String>,
+        password: Option<String>,
+    },
+    S3 {
+        /// Endpoint of the S3-compatible service, like https://minio.example.com. Buckets are addressed path-style.
+        endpoint: String,
+        bucket: String,
+        #[serde(default = "default_region")]
+        region: String,
+        /// Key prefix to sync under, if not the root of the bucket
+        #[serde(default)]
+        prefix: String,
+        access_key: String,
+        secret_key: String,
+    },
+    Sftp {
+        host: String,
+        port: Option<u16>,
+        username: String,
+        /// Directory on the server to sync into
+        path: PathBuf,
+        password: Option<String>,
+        /// Private key to log in with. Without a key or password, keys from the ssh agent are tried.
+        key: Option<PathBuf>,
+    },
+    /// A git repository, with every push committed and merged through a remote
+    Git {
+        /// Remote to pull from and push to, which can be a local bare repository
+        url: String,
+        #[serde(default = "default_branch")]
+        branch: String,
+    },
+}
+fn default_region() -> String {
+    "us-east-1".to_string()
+}
+fn default_branch() -> String {
+    "main".to_string()
+}
+/// A file in a remote sync folder
+pub struct RemoteFile {
+    /// Path relative to the sync folder, separated by slashes
+    pub path: String,
+    /// Changes whenever the file's contents do, like an etag or a size and modification time
+    pub version: String,
+}
+pub trait Backend {
+    /// Every file in the sync folder
+    fn list(&self) -> Result<Vec<RemoteFile>, io::Error>;
+    fn get(&self, path: &str) -> Result<Vec<u8>, io::Error>;
+    fn put(&self, path: &str, data: &[u8]) -> Result<(), io::Error>;
+    fn delete(&self, path: &str) -> Result<(), io::Error>;
+    /// Where the sync folder is, if it's reachable as a local directory and doesn't need mirroring
+    fn local_path(&self) -> Option<&Path> {
+        None
+    }
+}
+impl Profile {
+    pub fn connect(&self) -> Result<Box<dyn Backend>, io::Error> {
+        Ok(match self.clone() {
+            Profile::Local { path } => Box::new(Local {
+                root: paths::expand(&path.to_string_lossy()),
+            }),
+            Profile::Webdav {
+                url,
+                username,
+                password,
+            } => Box::new(WebDav::new(&url, username, password)?),
+            Profile::S3 {
+                endpoint,
+                bucket,
+                region,
+                prefix,
+                access_key,
+                secret_key,
+            } => Box::new(S3 {
+                client: Client::new(),
+                endpoint: Url::parse(&endpoint).map_err(invalid)?,
+                bucket,
+                region,
+                prefix: prefix.trim_matches('/').to_string(),
+                access_key,
+                secret_key,
+            }),
+            Profile::Sftp {
+                host,
+                port,
+                username,
+                path,
+                password,
+                key,
+            } => Box::new(Sftp::connect(
+                &host,
+                port.unwrap_or(22),
+                &username,
+                password,
+                key.map(|x| paths::expand(&x.to_string_lossy())),
+                path,
+            )?),
+            Profile::Git { .. } => {
+                return Err(invalid(
+                    "Git sync profiles are repositories, not file backends",
+                ))
+            }
+        })
+    }
+}
+fn invalid<E: ToString>(err: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, err.to_string())
+}
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or(0)
+}
+fn escape_path(path: &str) -> String {
+    path.split('/')
+        .map(|x| utf8_percent_encode(x, PATH_ESCAPE).to_string())
+        .collect::<Vec<String>>()
+        .join("/")
+}
+/// Contents of every element with the given name, ignoring namespaces. Enough for the few simple responses WebDAV and S3 send back.
+fn xml_elements<'a>(xml: &'a str, name: &str) -> Vec<&'a str> {
+    let mut found = vec![];
+    let mut rest = xml;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+        let end = match rest.find('>') {
+            Some(end) => end,
+            None => break,
+        };
+        let tag = &rest[..end];
+        let tag_name = tag.split_whitespace().next().unwrap_or("");
+        let local = tag_name.rsplit(':').next().unwrap_or("");
+        rest = &rest[end + 1..];
+        if local != name || tag.starts_with('/') || tag.ends_with('/') {
+            continue;
+        }
+        let close = format!("</{}>", tag_name);
+        if let Some(close_at) = rest.find(&close) {
+            found.push(&rest[..close_at]);
+            rest = &rest[close_at + close.len()..];
+        }
+    }
+    found
+}
+fn xml_text(xml: &str, name: &str) -> Option<String> {
+    xml_elements(xml, name).first().map(|x| {
+        x.replace("&quot;", "\"")
+            .replace("&apos;", "'")
+            .replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&amp;", "&")
+    })
+}
+fn check(response: Response) -> Result<Response, io::Error> {
+    let status = response.status();
+    if status.is_success() {
+        Ok(response)
+    } else {
+        let body = response.text().unwrap_or_default();
+        Err(io::Error::other(format!(
+            "Server responded with {}. {}",
+            status, body
+        )))
+    }
+}
+fn send(request: RequestBuilder) -> Result<Response, io::Error> {
+    request.send().map_err(io::Error::other)
+}
+/// A sync folder on a local filesystem
+pub struct Local {
+    root: PathBuf,
+}
+impl Backend for Local {
+    fn list(&self) -> Result<Vec<RemoteFile>, io::Error> {
+        let mut files = vec![];
+        for entry in WalkDir::new(&self.root).min_depth(1) {
+            let entry = entry.map_err(io::Error::from)?;
+            if entry.file_type().is_file() {
+                let meta = entry.metadata().map_err(io::Error::from)?;
+                let relative = entry.path().strip_prefix(&self.root).unwrap();
+                files.push(RemoteFile {
+                    path: relative.to_string_lossy().to_string(),
+                    version: format!("{}-{}", meta.len(), unix_secs(meta.modified()?)),
+                });
+            }
+        }
+        Ok(files)
+    }
+    fn get(&self, path: &str) -> Result<Vec<u8>, io::Error> {
+        fs::read(self.root.join(path))
+    }
+    fn put(&self, path: &str, data: &[u8]) -> Result<(), io::Error> {
+        let path = self.root.join(path);
+        fs::create_dir_all(path.parent().unwrap())?;
+        let temp = dirsync::temp_path(&path);
+        fs::write(&temp, data)?;
+        fs::rename(temp, path)
+    }
+    fn delete(&self, path: &str) -> Result<(), io::Error> {
+        fs::remove_file(self.root.join(path))
+    }
+    fn local_path(&self) -> Option<&Path> {
+        Some(&self.root)
+    }
+}
+/// A sync folder on a WebDAV server, like Nextcloud
+pub struct WebDav {
+    client: Client,
+    url: Url,
+    username: Option<String>,
+    password: Option<String>,
+    /// Folders known to exist already
+    created: RefCell<HashSet<String>>,
+}
+impl WebDav {
+    fn new(
+        url: &str,
+        username: Option<String>,
+        password: Option<String>,
+    ) -> Result<WebDav, io::Error> {
+        let mut url = Url::parse(url).map_err(invalid)?;
+        if !url.path().ends_with('/') {
+            let path = format!("{}/", url.path());
+            url.set_path(&path);
+        }
+        Ok(WebDav {
+            client: Client::new(),
+            url,
+            username,
+            password,
+            created: RefCell::new(HashSet::new()),
+        })
+    }
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        let url = format!("{}{}", self.url, escape_path(path));
+        let request = self.client.request(method, &url);
+        match self.username {
+            Some(ref username) => request.basic_auth(username, self.password.as_ref()),
+            None => request,
+        }
+    }
+    /// Makes sure the folders a file goes in exist
+    fn create_parents(&self, path: &str) -> Result<(), io::Error> {
+        let parts: Vec<&str> = path.split('/').collect();
+        for i in 1..parts.len() {
+            let folder = parts[..i].join("/") + "/";
+            if self.created.borrow().contains(&folder) {
+                continue;
+            }
+            let response = send(self.request(Method::from_bytes(b"MKCOL").unwrap(), &folder))?;
+            // 405 means the folder is already there
+            if response.status() != StatusCode::METHOD_NOT_ALLOWED {
+                check(response)?;
+            }
+            self.created.borrow_mut().insert(folder);
+        }
+        Ok(())
+    }
+}
+impl Backend for WebDav {
+    fn list(&self) -> Result<Vec<RemoteFile>, io::Error> {
+        let body = r#"<?xml version="1.0"?><d:propfind xmlns:d="DAV:"><d:prop><d:getetag/><d:getcontentlength/><d:getlastmodified/><d:resourcetype/></d:prop></d:propfind>"#;
+        let root = percent_decode_str(self.url.path())
+            .decode_utf8_lossy()
+            .to_string();
+        let mut files = vec![];
+        let mut folders = vec![String::new()];
+        // Servers tend to refuse listing everything at once, so go one folder at a time
+        while let Some(folder) = folders.pop() {
+            let response = send(
+                self.request(Method::from_bytes(b"PROPFIND").unwrap(), &folder)
+                    .header("Depth", "1")
+                    .header("Content-Type", "application/xml")
+                    .body(body),
+            )?;
+            if response.status() == StatusCode::NOT_FOUND && folder.is_empty() {
+                return Ok(files);
+            }
+            let listing = check(response)?.text().map_err(io::Error::other)?;
+            for entry in xml_elements(&listing, "response") {
+                let href = match xml_text(entry, "href") {
+                    Some(href) => href,
+                    None => continue,
+                };
+                let href = Url::parse(&href)
+                    .map(|x| x.path().to_string())
+                    .unwrap_or(href);
+                let href = percent_decode_str(&href).decode_utf8_lossy().to_string();
+                let relative = match href.strip_prefix(&root) {
+                    Some(relative) => relative.trim_end_matches('/').to_string(),
+                    None => continue,
+                };
+                if relative.is_empty() || relative == folder.trim_end_matches('/') {
+                    continue;
+                }
+                if xml_elements(entry, "collection").is_empty() && !entry.contains("collection/>") {
+                    let version = xml_text(entry, "getetag").unwrap_or_else(|| {
+                        format!(
+                            "{}-{}",
+                            xml_text(entry, "getcontentlength").unwrap_or_default(),
+                            xml_text(entry, "getlastmodified").unwrap_or_default()
+                        )
+                    });
+                    files.push(RemoteFile {
+                        path: relative,
+                        version,
+                    });
+                } else {
+                    self.created.borrow_mut().insert(relative.clone() + "/");
+                    folders.push(relative + "/");
+                }
+            }
+        }
+        Ok(files)
+    }
+    fn get(&self, path: &str) -> Result<Vec<u8>, io::Error> {
+        let response = check(send(self.request(Method::GET, path))?)?;
+        Ok(response.bytes().map_err(io::Error::other)?.to_vec())
+    }
+    fn put(&self, path: &str, data: &[u8]) -> Result<(), io::Error> {
+        self.create_parents(path)?;
+        check(send(self.request(Method::PUT, path).body(data.to_vec()))?)?;
+        Ok(())
+    }
+    fn delete(&self, path: &str) -> Result<(), io::Error> {
+        let response = send(self.request(Method::DELETE, path))?;
+        if response.status() != StatusCode::NOT_FOUND {
+            check(response)?;
+        }
+        Ok(())
+    }
+}
+/// The canonical form of an S3 request that's signed, covering the host, x-amz-content-sha256 and x-amz-date headers
+fn canonical_request(
+    method: &str,
+    path: &str,
+    query: &str,
+    host: &str,
+    payload_hash: &str,
+    timestamp: &str,
+) -> String {
+    format!(
+        "{}\n{}\n{}\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\nhost;x-amz-content-sha256;x-amz-date\n{}",
+        method, path, query, host, payload_hash, timestamp, payload_hash
+    )
+}
+/// Signs a canonical request with AWS signature version 4. Returns the credential scope and the signature.
+fn sign(secret_key: &str, region: &str, timestamp: &str, canonical: &str) -> (String, String) {
+    let date = &timestamp[..8];
+    let scope = format!("{}/{}/s3/aws4_request", date, region);
+    let to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        timestamp,
+        scope,
+        to_hex(&Sha256::digest(canonical.as_bytes()))
+    );
+    let mut key = format!("AWS4{}", secret_key).into_bytes();
+    for part in [date, region, "s3", "aws4_request", &to_sign].iter() {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&key).unwrap();
+        mac.update(part.as_bytes());
+        key = mac.finalize().into_bytes().to_vec();
+    }
+    (scope, to_hex(&key))
+}
+/// A sync folder in an S3-compatible bucket, like MinIO
+pub struct S3 {
+    client: Client,
+    endpoint: Url,
+    bucket: String,
+    region: String,
+    prefix: String,
+    access_key: String,
+    secret_key: String,
+}
+impl S3 {
+    fn key(&self, path: &str) -> String {
+        if self.prefix.is_empty() {
+            path.to_string()
+        } else {
+            format!("{}/{}", self.prefix, path)
+        }
+    }
+    /// Builds a request signed with AWS signature version 4
+    fn request(
+        &self,
+        method: Method,
+        key: &str,
+        query: &[(&str, &str)],
+        payload: &[u8],
+    ) -> RequestBuilder {
+        let path = format!(
+            "{}/{}/{}",
+            self.endpoint.path().trim_end_matches('/'),
+            escape_path(&self.bucket),
+            escape_path(key)
+        );
+        let mut query: Vec<String> = query
+            .iter()
+            .map(|(name, value)| {
+                format!(
+                    "{}={}",
+                    utf8_percent_encode(name, PATH_ESCAPE),
+                    utf8_percent_encode(value, PATH_ESCAPE)
+                )
+            })
+            .collect();
+        query.sort();
+        let query = query.join("&");
+        let mut host = self.endpoint.host_str().unwrap_or("").to_string();
+        if let Some(port) = self.endpoint.port() {
+            host = format!("{}:{}", host, port);
+        }
+        let timestamp = humantime::format_rfc3339_seconds(SystemTime::now())
+            .to_string()
+            .replace(['-', ':'], "");
+        let payload_hash = to_hex(&Sha256::digest(payload));
+        let canonical = canonical_request(
+            method.as_str(),
+            &path,
+            &query,
+            &host,
+            &payload_hash,
+            &timestamp,
+        );
+        let (scope, signature) = sign(&self.secret_key, &self.region, &timestamp, &canonical);
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders=host;x-amz-content-sha256;x-amz-date, Signature={}",
+            self.access_key, scope, signature
+        );
+        let mut url = format!("{}://{}{}", self.endpoint.scheme(), host, path);
+        if !query.is_empty() {
+            url = format!("{}?{}", url, query);
+        }
+        self.client
+            .request(method, &url)
+            .header("x-amz-content-sha256", payload_hash)
+            .header("x-amz-date", timestamp)
+            .header("Authorization", authorization)
+    }
+}
+impl Backend for S3 {
+    fn list(&self) -> Result<Vec<RemoteFile>, io::Error> {
+        let prefix = if self.prefix.is_empty() {
+            String::new()
+        } else {
+            format!("{}/", self.prefix)
+        };
+        let mut files = vec![];
+        let mut token: Option<String> = None;
+        loop {
+            let mut query = vec![("list-type", "2"), ("prefix", prefix.as_str())];
+            if let Some(ref token) = token {
+                query.push(("continuation-token", token));
+            }
+            let listing = check(send(self.request(Method::GET, "", &query, &[]))?)?
+                .text()
+                .map_err(io::Error::other)?;
+            for object in xml_elements(&listing, "Contents") {
+                if let (Some(key), Some(etag)) = (xml_text(object, "Key"), xml_text(object, "ETag"))
+                {
+                    if let Some(path) = key.strip_prefix(&prefix) {
+                        files.push(RemoteFile {
+                            path: path.to_string(),
+                            version: etag,
+                        });
+                    }
+                }
+            }
+            token = xml_text(&listing, "NextContinuationToken");
+            if xml_text(&listing, "IsTruncated").as_deref() != Some("true") || token.is_none() {
+                break;
+            }
+        }
+        Ok(files)
+    }
+    fn get(&self, path: &str) -> Result<Vec<u8>, io::Error> {
+        let response = check(send(self.request(Method::GET, &self.key(path), &[], &[]))?)?;
+        Ok(response.bytes().map_err(io::Error::other)?.to_vec())
+    }
+    fn put(&self, path: &str, data: &[u8]) -> Result<(), io::Error> {
+        let request = self.request(Method::PUT, &self.key(path), &[], data);
+        check(send(request.body(data.to_vec()))?)?;
+        Ok(())
+    }
+    fn delete(&self, path: &str) -> Result<(), io::Error> {
+        check(send(self.request(
+            Method::DELETE,
+            &self.key(path),
+            &[],
+            &[],
+        ))?)?;
+        Ok(())
+    }
+}
+/// A sync folder on an SFTP server
+pub struct Sftp {
+    sftp: ssh2::Sftp,
+    root: PathBuf,
+}
+impl Sftp {
+    fn connect(
+        host: &str,
+        port: u16,
+        username: &str,
+        password: Option<String>,
+        key: Option<PathBuf>,
+        root: PathBuf,
+    ) -> Result<Sftp, io::Error> {
+        let mut session = Session::new()?;
+        session.set_tcp_stream(TcpStream::connect((host, port))?);
+        session.handshake()?;
+        // Only talk to servers already trusted by ssh itself
+        let mut known_hosts = session.known_hosts()?;
+        if let Some(home) = dirs::home_dir() {
+            let _ =
+                known_hosts.read_file(&home.join(".ssh/known_hosts"), KnownHostFileKind::OpenSSH);
+        }
+        let (host_key, _) = session
+            .host_key()
+            .ok_or_else(|| io::Error::other("Server sent no host key"))?;
+        match known_hosts.check_port(host, port, host_key) {
+            CheckResult::Match => {}
+            CheckResult::NotFound => {
+                return Err(io::Error::other(format!(
+                    "{} is not in ~/.ssh/known_hosts. Connect to it with ssh once to trust it.",
+                    host
+                )))
+            }
+            _ => {
+                return Err(io::Error::other(format!(
+                    "The host key of {} doesn't match the one in ~/.ssh/known_hosts",
+                    host
+                )))
+            }
+        }
+        match (key, password) {
+            (Some(key), password) => {
+                session.userauth_pubkey_file(username, None, &key, password.as_deref())?
+            }
+            (None, Some(password)) => session.userauth_password(username, &password)?,
+            (None, None) => session.userauth_agent(username)?,
+        }
+        Ok(Sftp {
+            sftp: session.sftp()?,
+            root,
+        })
+    }
+    fn create_parents(&self, path: &Path) -> Result<(), io::Error> {
+        let mut folder = PathBuf::new();
+        for part in path.parent().into_iter().flat_map(|x| x.components()) {
+            folder.push(part);
+            if self.sftp.stat(&folder).is_err() {
+                self.sftp.mkdir(&folder, 0o755)?;
+            }
+        }
+        Ok(())
+    }
+}
+impl Backend for Sftp {
+    fn list(&self) -> Result<Vec<RemoteFile>, io::Error> {
+        let mut files = vec![];
+        if self.sftp.stat(&self.root).is_err() {
+            return Ok(files);
+        }
+        let mut folders = vec![self.root.clone()];
+        while let Some(folder) = folders.pop() {
+            for (path, stat) in self.sftp.readdir(&folder)? {
+                if stat.is_dir() {
+                    folders.push(path);
+                } else if stat.is_file() {
+                    let relative = path.strip_prefix(&self.root).unwrap();
+                    files.push(RemoteFile {
+                        path: relative.to_string_lossy().to_string(),
+                        version: format!("{}-{}", stat.size.unwrap_or(0), stat.mtime.unwrap_or(0)),
+                    });
+                }
+            }
+        }
+        Ok(files)
+    }
+    fn get(&self, path: &str) -> Result<Vec<u8>, io::Error> {
+        let mut data = vec![];
+        self.sftp
+            .open(self.root.join(path))?
+            .read_to_end(&mut data)?;
+        Ok(data)
+    }
+    fn put(&self, path: &str, data: &[u8]) -> Result<(), io::Error> {
+        let path = self.root.join(path);
+        self.create_parents(&path)?;
+        let temp = dirsync::temp_path(&path);
+        self.sftp.create(&temp)?.write_all(data)?;
+        self.sftp.rename(
+            &temp,
+            &path,
+            Some(RenameFlags::OVERWRITE | RenameFlags::ATOMIC | RenameFlags::NATIVE),
+        )?;
+        Ok(())
+    }
+    fn delete(&self, path: &str) -> Result<(), io::Error> {
+        self.sftp.unlink(&self.root.join(path))?;
+        Ok(())
+    }
+}
+#[derive(Serialize, Deserialize, Clone)]
+struct Cached {
+    version: String,
+    hash: String,
+}
+/// What the local mirror of a remote sync folder held when it was last brought up to date
+#[derive(Serialize, Deserialize, Default)]
+struct Index {
+    files: BTreeMap<String, Cached>,
+}
+/// How a sync folder is kept in sync with where it's stored
+enum Store {
+    /// A local directory, used as is
+    Local,
+    /// Mirrored one file at a time through a backend
+    Files {
+        backend: Box<dyn Backend>,
+        index_path: PathBuf,
+        index: Index,
+    },
+    Git(gitrepo::Repo),
+}
+/// A sync folder, mirrored locally if it isn't a local directory already
+pub struct Mirror {
+    /// Local directory sync commands should work on
+    pub path: PathBuf,
+    store: Store,
+}
+/// Opens a sync location, which is either the name of a sync profile in the config or a local directory. Remote sync folders are downloaded into their mirror first.
+pub fn open(location: &str) -> Result<Mirror, io::Error> {
+    let profiles: HashMap<String, Profile> = confy::load::<Config>("wyvern", "wyvern")
+        .map(|x| x.sync_profiles)
+        .unwrap_or_default();
+    let home = dirs::home_dir().unwrap();
+    let profile = match profiles.get(location) {
+        Some(profile) => profile.clone(),
+        None => Profile::Local {
+            path: PathBuf::from(location),
+        },
+    };
+    let dir = dirs::data_dir()
+        .unwrap_or_else(|| home.join(".local/share"))
+        .join("wyvern/remotes")
+        .join(location);
+    if let Profile::Git { url, branch } = profile {
+        let url = paths::expand(&url).to_string_lossy().into_owned();
+        let path = dir.join("repo");
+        let repo = gitrepo::Repo::open(&path, &url, &branch)?;
+        return Ok(Mirror {
+            path,
+            store: Store::Git(repo),
+        });
+    }
+    let backend = profile.connect()?;
+    if let Some(path) = backend.local_path() {
+        return Ok(Mirror {
+            path: path.to_path_buf(),
+            store: Store::Local,
+        });
+    }
+    let index_path = dir.join("index.json");
+    let index = match fs::read_to_string(&index_path) {
+        Ok(read) => serde_json::from_str(&read)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?,
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => Index::default(),
+        Err(err) => return Err(err),
+    };
+    let mut mirror = Mirror {
+        path: dir.join("files"),
+        store: Store::Files {
+            backend,
+            index_path,
+            index,
+        },
+    };
+    mirror.download()?;
+    Ok(mirror)
+}
+/// Whether a path from a listing stays inside the folder it's relative to
+fn inside(path: &str) -> bool {
+    !path.is_empty()
+        && Path::new(path)
+            .components()
+            .all(|x| matches!(x, Component::Normal(_)))
+}
+fn relative(root: &Path, path: &Path) -> String {
+    path.strip_prefix(root)
+        .unwrap()
+        .to_string_lossy()
+        .to_string()
+}
+fn store_index(index_path: &Path, index: &Index) -> Result<(), io::Error> {
+    fs::write(index_path, serde_json::to_string(index).unwrap())
+}
+impl Mirror {
+    /// Brings the mirror up to date with the remote sync folder, only fetching files that changed
+    pub fn download(&mut self) -> Result<(), io::Error> {
+        let (backend, index_path, index) = match self.store {
+            Store::Local => return Ok(()),
+            Store::Git(ref repo) => return repo.pull(),
+            Store::Files {
+                ref backend,
+                ref index_path,
+                ref mut index,
+            } => (backend, index_path, index),
+        };
+        info!("Checking remote sync folder for changes");
+        fs::create_dir_all(&self.path)?;
+        let remote = backend.list()?;
+        // Others can write to the sync location, so what it lists isn't trusted to stay inside the mirror
+        if let Some(file) = remote.iter().find(|x| !inside(&x.path)) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "The sync location has a file outside the sync folder: {}",
+                    file.path
+                ),
+            ));
+        }
+        let mut present = HashSet::new();
+        for file in remote {
+            let local = self.path.join(&file.path);
+            present.insert(file.path.clone());
+            let current = match index.files.get(&file.path) {
+                Some(cached) if cached.version == file.version && local.is_file() => {
+                    hashing::sha1_file(&local)? == cached.hash
+                }
+                _ => false,
+            };
+            if current {
+                continue;
+            }
+            info!("Downloading {}", file.path);
+            let data = backend.get(&file.path)?;
+            fs::create_dir_all(local.parent().unwrap())?;
+            fs::write(&local, &data)?;
+            index.files.insert(
+                file.path,
+                Cached {
+                    version: file.version,
+                    hash: hashing::sha1(&data),
+                },
+            );
+        }
+        index.files.retain(|path, _| present.contains(path));
+        // Anything else in the mirror is gone remotely, or a leftover from an upload that failed
+        for entry in WalkDir::new(&self.path).min_depth(1).contents_first(true) {
+            let entry = entry.map_err(io::Error::from)?;
+            if entry.file_type().is_dir() {
+                let _ = fs::remove_dir(entry.path());
+            } else if !present.contains(&relative(&self.path, entry.path())) {
+                fs::remove_file(entry.path())?;
+            }
+        }
+        store_index(index_path, index)
+    }
+    /// Uploads whatever changed in the mirror to the remote sync folder, described as `description` where the history is kept. Returns how many files were uploaded and deleted.
+    /// Saves pushed into the mirror only count as synced once this succeeded, so pushes that failed to upload stay pending until a later upload does.
+    pub fn upload(&mut self, description: &str) -> Result<(usize, usize), io::Error> {
+        let changed = self.upload_changes(description)?;
+        merge::store_pushed()?;
+        Ok(changed)
+    }
+    fn upload_changes(&mut self, description: &str) -> Result<(usize, usize), io::Error> {
+        let (backend, index_path, index) = match self.store {
+            Store::Local => return Ok((0, 0)),
+            Store::Git(ref repo) => return repo.commit_and_push(description),
+            Store::Files {
+                ref backend,
+                ref index_path,
+                ref mut index,
+            } => (backend, index_path, index),
+        };
+        let mut changed = vec![];
+        let mut present = HashSet::new();
+        for entry in WalkDir::new(&self.path).min_depth(1).sort_by_file_name() {
+            let entry = entry.map_err(io::Error::from)?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let relative = relative(&self.path, entry.path());
+            present.insert(relative.clone());
+            let data = fs::read(entry.path())?;
+            let hash = hashing::sha1(&data);
+            if index.files.get(&relative).map(|x| &x.hash) == Some(&hash) {
+                continue;
+            }
+            changed.push((relative, data, hash));
+        }
+        let deleted: Vec<String> = index
+            .files
+            .keys()
+            .filter(|x| !present.contains(*x))
+            .cloned()
+            .collect();
+        // Locking the savedb only keeps apart commands on machines sharing the mirror, so files another machine changed since they
+        // were downloaded are left alone rather than overwritten. The next sync downloads and merges them first.
+        let versions: HashMap<String, String> = backend
+            .list()?
+            .into_iter()
+            .map(|x| (x.path, x.version))
+            .collect();
+        let outdated =
+            |path: &String| versions.get(path) != index.files.get(path).map(|x| &x.version);
+        let outdated = changed
+            .iter()
+            .map(|x| &x.0)
+            .find(|x| outdated(x))
+            .or_else(|| {
+                deleted
+                    .iter()
+                    .find(|x| versions.contains_key(*x) && outdated(x))
+            });
+        if let Some(path) = outdated {
+            return Err(io::Error::other(format!(
+                "{} changed in the sync location since it was downloaded. Sync again to merge the changes.",
+                path
+            )));
+        }
+        let mut uploaded = HashSet::new();
+        for (relative, data, hash) in changed {
+            info!("Uploading {}", relative);
+            backend.put(&relative, &data)?;
+            index.files.insert(
+                relative.clone(),
+                Cached {
+                    version: String::new(),
+                    hash,
+                },
+            );
+            uploaded.insert(relative);
+        }
+        for path in deleted.iter() {
+            // Another machine may have deleted it already
+            if versions.contains_key(path) {
+                info!("Deleting {} remotely", path);
+                backend.delete(path)?;
+            }
+            index.files.remove(path);
+        }
+        if !uploaded.is_empty() {
+            // Uploading changes the versions the server reports, which the next download compares against
+            for file in backend.list()? {
+                if uploaded.contains(&file.path) {
+                    if let Some(cached) = index.files.get_mut(&file.path) {
+                        cached.version = file.version;
+                    }
+                }
+            }
+        }
+        store_index(index_path, index)?;
+        Ok((uploaded.len(), deleted.len()))
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    /// A backend that lists whatever it's given and holds no files
+    struct Listing(Vec<&'static str>);
+    impl Backend for Listing {
+        fn list(&self) -> Result<Vec<RemoteFile>, io::Error> {
+            Ok(self
+                .0
+                .iter()
+                .map(|x| RemoteFile {
+                    path: x.to_string(),
+                    version: "1".to_string(),
+                })
+                .collect())
+        }
+        fn get(&self, _: &str) -> Result<Vec<u8>, io::Error> {
+            Ok(b"saved".to_vec())
+        }
+        fn put(&self, _: &str, _: &[u8]) -> Result<(), io::Error> {
+            Ok(())
+        }
+        fn delete(&self, _: &str) -> Result<(), io::Error> {
+            Ok(())
+        }
+    }
+    fn mirror(dir: &Path, listed: Vec<&'static str>) -> Mirror {
+        Mirror {
+            path: dir.join("files"),
+            store: Store::Files {
+                backend: Box::new(Listing(listed)),
+                index_path: dir.join("index.json"),
+                index: Index::default(),
+            },
+        }
+    }
+    /// The GET Bucket Lifecycle and List Objects examples from the AWS signature version 4 documentation
+    const SECRET_KEY: &str = "wJalrXUtnFEMI/K7MDENG/bPxRfiCYEXAMPLEKEY";
+    const EMPTY_HASH: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+    fn signature(query: &str) -> (String, String) {
+        let canonical = canonical_request(
+            "GET",
+            "/",
+            query,
+            "examplebucket.s3.amazonaws.com",
+            EMPTY_HASH,
+            "20130524T000000Z",
+        );
+        sign(SECRET_KEY, "us-east-1", "20130524T000000Z", &canonical)
+    }
+    #[test]
+    fn signs_s3_requests() {
+        let (scope, lifecycle) = signature("lifecycle=");
+        assert_eq!(scope, "20130524/us-east-1/s3/aws4_request");
+        assert_eq!(
+            lifecycle,
+            "fea454ca298b7da1c68078a5d1bdbfbbe0d65c699e0f91ac7a200a0136783543"
+        );
+        let (_, list) = signature("max-keys=2&prefix=J");
+        assert_eq!(
+            list,
+            "34b48302e7b5fa45bde8084f4b7868a86f0a534bc59db6670ed5711ef69dc6f7"
+        );
+    }
+    #[test]
+    fn finds_xml_elements() {
+        let xml = r#"<?xml version="1.0"?>
+<d:multistatus xmlns:d="DAV:">
+  <d:response><d:href>/dav/a.sav</d:href><d:getetag>"1"</d:getetag></d:response>
+  <d:response><d:href>/dav/b%20c.sav</d:href><d:resourcetype/></d:response>
+</d:multistatus>"#;
+        assert_eq!(
+            xml_elements(xml, "href"),
+            vec!["/dav/a.sav", "/dav/b%20c.sav"]
+        );
+        assert_eq!(xml_elements(xml, "response").len(), 2);
+        assert!(xml_elements(xml, "resourcetype").is_empty());
+        assert!(xml_elements(xml, "missing").is_empty());
+        assert!(xml_elements("<Key>unclosed", "Key").is_empty());
+    }
+    #[test]
+    fn decodes_xml_text() {
+        let xml = "<ListBucketResult><Key>a&amp;b &lt;1&gt;.sav</Key><ETag>&quot;abc&quot;</ETag><Key>second</Key></ListBucketResult>";
+        assert_eq!(xml_text(xml, "Key").unwrap(), "a&b <1>.sav");
+        assert_eq!(xml_text(xml, "ETag").unwrap(), "\"abc\"");
+        assert_eq!(xml_text(xml, "IsTruncated"), None);
+        assert_eq!(xml_text("<a>&amp;lt;</a>", "a").unwrap(), "&lt;");
+    }
+    #[test]
+    fn writes_to_a_temp_file_per_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let local = Local {
+            root: dir.path().to_path_buf(),
+        };
+        assert_eq!(
+            dirsync::temp_path(&dir.path().join("slot1.sav")),
+            dir.path().join("slot1.sav.wyvern-tmp")
+        );
+        local.put("slot1.sav", b"save").unwrap();
+        local.put("slot1.bak", b"backup").unwrap();
+        assert_eq!(fs::read(dir.path().join("slot1.sav")).unwrap(), b"save");
+        assert_eq!(fs::read(dir.path().join("slot1.bak")).unwrap(), b"backup");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+    #[test]
+    fn checks_listed_paths() {
+        assert!(inside("savedb.json"));
+        assert!(inside("saves/gog_1/slot 1.sav"));
+        assert!(!inside(""));
+        assert!(!inside("../.bashrc"));
+        assert!(!inside("saves/../../.bashrc"));
+        assert!(!inside("/home/me/.bashrc"));
+        assert!(!inside("./savedb.json"));
+    }
+    #[test]
+    fn downloads_listed_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut mirror = mirror(dir.path(), vec!["savedb.json", "saves/gog_1/slot.sav"]);
+        mirror.download().unwrap();
+        assert_eq!(
+            fs::read(dir.path().join("files/saves/gog_1/slot.sav")).unwrap(),
+            b"saved"
+        );
+    }
+    #[test]
+    fn refuses_files_outside_the_mirror() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut mirror = mirror(dir.path(), vec!["savedb.json", "../escaped"]);
+        assert!(mirror.download().is_err());
+        assert!(!dir.path().join("escaped").exists());
+        assert!(!dir.path().join("files/savedb.json").exists());
+    }
+}
@@ -0,0 +1,344 @@
+/// Versioned snapshots of synced save files, so that pushing a broken save never destroys the only good copy.
+/// Snapshots live in the sync folder: snapshots/<game>/<id>.json lists the files of each one, and the file contents are stored once per unique hash in snapshots/objects.
+use hashing;
+use serde_json;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io;
+use std::os::unix::fs::{symlink, PermissionsExt};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use walkdir::WalkDir;
+const DAY: u64 = 24 * 60 * 60;
+/// How many snapshots to keep of each game's saves
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(default)]
+pub struct Retention {
+    /// The most recent snapshots are always kept
+    pub keep_last: usize,
+    /// Days to keep the last snapshot of each day for
+    pub daily: u64,
+    /// Weeks to keep the last snapshot of each week for
+    pub weekly: u64,
+}
+impl Default for Retention {
+    fn default() -> Retention {
+        Retention {
+            keep_last: 10,
+            daily: 7,
+            weekly: 4,
+        }
+    }
+}
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct SnapshotFile {
+    pub path: PathBuf,
+    /// SHA1 of the contents, which names the object they're stored in
+    pub hash: Option<String>,
+    pub mode: u32,
+    pub modified: u64,
+    pub symlink: Option<PathBuf>,
+}
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Snapshot {
+    /// Seconds since the unix epoch when the snapshot was taken
+    pub id: u64,
+    pub files: Vec<SnapshotFile>,
+}
+impl Snapshot {
+    pub fn date(&self) -> String {
+        humantime::format_rfc3339_seconds(UNIX_EPOCH + Duration::from_secs(self.id)).to_string()
+    }
+    pub fn size(&self, sync_dir: &Path) -> u64 {
+        self.files
+            .iter()
+            .filter_map(|x| x.hash.as_ref())
+            .filter_map(|x| fs::metadata(object_path(sync_dir, x)).ok())
+            .map(|x| x.len())
+            .sum()
+    }
+    /// Whether a snapshot holds the same files as another, regardless of when they were taken
+    fn same_files(&self, other: &Snapshot) -> bool {
+        self.files == other.files
+    }
+}
+fn snapshots_dir(sync_dir: &Path) -> PathBuf {
+    sync_dir.join("snapshots")
+}
+fn object_path(sync_dir: &Path, hash: &str) -> PathBuf {
+    snapshots_dir(sync_dir)
+        .join("objects")
+        .join(&hash[..2])
+        .join(hash)
+}
+fn manifest_path(sync_dir: &Path, folder: &str, id: u64) -> PathBuf {
+    snapshots_dir(sync_dir)
+        .join(folder)
+        .join(format!("{}.json", id))
+}
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or(0)
+}
+/// Stores a file's contents in the object store, unless they're already there
+fn store_object(sync_dir: &Path, path: &Path) -> Result<String, io::Error> {
+    let hash = hashing::sha1_file(path)?;
+    let object = object_path(sync_dir, &hash);
+    if !object.exists() {
+        fs::create_dir_all(object.parent().unwrap())?;
+        let temp = object.with_extension("tmp");
+        fs::copy(path, &temp)?;
+        fs::rename(&temp, &object)?;
+    }
+    Ok(hash)
+}
+fn snapshot_file(
+    sync_dir: &Path,
+    path: &Path,
+    relative: PathBuf,
+) -> Result<SnapshotFile, io::Error> {
+    let meta = fs::symlink_metadata(path)?;
+    let symlink = if meta.file_type().is_symlink() {
+        Some(fs::read_link(path)?)
+    } else {
+        None
+    };
+    Ok(SnapshotFile {
+        hash: match symlink {
+            Some(_) => None,
+            None => Some(store_object(sync_dir, path)?),
+        },
+        path: relative,
+        mode: meta.permissions().mode(),
+        modified: unix_secs(meta.modified()?),
+        symlink,
+    })
+}
+/// Snapshots the saves at `source`, which can be a directory or a single file. Returns the new snapshot's id, or None if nothing changed since the latest snapshot.
+pub fn take(sync_dir: &Path, folder: &str, source: &Path) -> Result<Option<u64>, io::Error> {
+    let mut files = vec![];
+    if source.is_dir() {
+        for entry in WalkDir::new(source).min_depth(1).sort_by_file_name() {
+            let entry = entry.map_err(io::Error::from)?;
+            if entry.file_type().is_dir() {
+                continue;
+            }
+            let relative = entry.path().strip_prefix(source).unwrap().to_path_buf();
+            files.push(snapshot_file(sync_dir, entry.path(), relative)?);
+        }
+    } else {
+        let relative = PathBuf::from(source.file_name().unwrap());
+        files.push(snapshot_file(sync_dir, source, relative)?);
+    }
+    let mut id = unix_secs(SystemTime::now());
+    let snapshot = Snapshot { id, files };
+    if let Some(latest) = list(sync_dir, folder)?.last() {
+        if latest.same_files(&snapshot) {
+            return Ok(None);
+        }
+        id = id.max(latest.id + 1);
+    }
+    let snapshot = Snapshot { id, ..snapshot };
+    let manifest = manifest_path(sync_dir, folder, id);
+    fs::create_dir_all(manifest.parent().unwrap())?;
+    serde_json::to_writer(File::create(manifest)?, &snapshot)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    Ok(Some(id))
+}
+/// Every snapshot of a game's saves, oldest first
+pub fn list(sync_dir: &Path, folder: &str) -> Result<Vec<Snapshot>, io::Error> {
+    let dir = snapshots_dir(sync_dir).join(folder);
+    if !dir.is_dir() {
+        return Ok(vec![]);
+    }
+    let mut snapshots = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().map(|x| x == "json").unwrap_or(false) {
+            let snapshot: Snapshot = serde_json::from_reader(File::open(&path)?)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            snapshots.push(snapshot);
+        }
+    }
+    snapshots.sort_by_key(|x| x.id);
+    Ok(snapshots)
+}
+/// Finds a snapshot by its id or its date, as shown by `wyvern sync history`
+pub fn find(sync_dir: &Path, folder: &str, name: &str) -> Result<Option<Snapshot>, io::Error> {
+    let id = match name.parse::<u64>() {
+        Ok(id) => Some(id),
+        Err(_) => humantime::parse_rfc3339_weak(name).ok().map(unix_secs),
+    };
+    Ok(list(sync_dir, folder)?
+        .into_iter()
+        .find(|x| Some(x.id) == id))
+}
+/// Checks that a snapshot's manifest only names files inside the saves and stored objects by their hash.
+/// Manifests come from the sync folder, which can be shared, so they aren't trusted to stay inside the save directory.
+fn check(snapshot: &Snapshot) -> Result<(), io::Error> {
+    for file in snapshot.files.iter() {
+        let relative = file.path.components().count() > 0
+            && file
+                .path
+                .components()
+                .all(|x| matches!(x, Component::Normal(_)));
+        if !relative {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Snapshot {} has a file outside the save directory: {}",
+                    snapshot.id,
+                    file.path.display()
+                ),
+            ));
+        }
+        if let Some(ref hash) = file.hash {
+            if hash.len() != 40 || !hash.chars().all(|x| x.is_ascii_hexdigit()) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Snapshot {} has an invalid hash: {}", snapshot.id, hash),
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+/// Writes a snapshot's files into a directory, removing any files that aren't part of it
+pub fn restore(sync_dir: &Path, snapshot: &Snapshot, to: &Path) -> Result<(), io::Error> {
+    check(snapshot)?;
+    fs::create_dir_all(to)?;
+    let wanted: HashSet<PathBuf> = snapshot.files.iter().map(|x| to.join(&x.path)).collect();
+    let extra: Vec<PathBuf> = WalkDir::new(to)
+        .min_depth(1)
+        .into_iter()
+        .filter_map(|x| x.ok())
+        .filter(|x| !x.file_type().is_dir() && !wanted.contains(x.path()))
+        .map(|x| x.path().to_path_buf())
+        .collect();
+    for path in extra {
+        info!("Removing {}", path.display());
+        fs::remove_file(path)?;
+    }
+    for file in snapshot.files.iter() {
+        let path = to.join(&file.path);
+        fs::create_dir_all(path.parent().unwrap())?;
+        if fs::symlink_metadata(&path).is_ok() {
+            fs::remove_file(&path)?;
+        }
+        info!("Restoring {}", path.display());
+        match (file.symlink.as_ref(), file.hash.as_ref()) {
+            (Some(target), _) => symlink(target, &path)?,
+            (None, Some(hash)) => {
+                fs::copy(object_path(sync_dir, hash), &path)?;
+                fs::set_permissions(&path, fs::Permissions::from_mode(file.mode))?;
+                File::open(&path)?.set_modified(UNIX_EPOCH + Duration::from_secs(file.modified))?;
+            }
+            (None, None) => {}
+        }
+    }
+    Ok(())
+}
+/// Which snapshots the retention policy keeps, given their ids
+fn kept(ids: &[u64], retention: &Retention, now: u64) -> HashSet<u64> {
+    let mut newest_first = ids.to_vec();
+    newest_first.sort_unstable_by(|a, b| b.cmp(a));
+    let mut keep: HashSet<u64> = newest_first
+        .iter()
+        .take(retention.keep_last)
+        .cloned()
+        .collect();
+    for &(period, count) in [(DAY, retention.daily), (7 * DAY, retention.weekly)].iter() {
+        let mut seen = HashSet::new();
+        for &id in newest_first.iter() {
+            if now.saturating_sub(id) < period * count && seen.insert(id / period) {
+                keep.insert(id);
+            }
+        }
+    }
+    keep
+}
+/// Deletes the snapshots of a game that the retention policy doesn't keep, then any stored files no snapshot uses anymore. Returns how many snapshots were deleted.
+pub fn prune(sync_dir: &Path, folder: &str, retention: &Retention) -> Result<usize, io::Error> {
+    let ids: Vec<u64> = list(sync_dir, folder)?.iter().map(|x| x.id).collect();
+    let keep = kept(&ids, retention, unix_secs(SystemTime::now()));
+    let mut removed = 0;
+    for id in ids.into_iter().filter(|x| !keep.contains(x)) {
+        info!("Removing snapshot {}", id);
+        fs::remove_file(manifest_path(sync_dir, folder, id))?;
+        removed += 1;
+    }
+    if removed > 0 {
+        collect_garbage(sync_dir)?;
+    }
+    Ok(removed)
+}
+/// Removes stored files that no snapshot of any game refers to
+fn collect_garbage(sync_dir: &Path) -> Result<(), io::Error> {
+    let dir = snapshots_dir(sync_dir);
+    let mut used = HashSet::new();
+    for entry in fs::read_dir(&dir)? {
+        let entry = entry?;
+        if entry.file_name() == "objects" || !entry.file_type()?.is_dir() {
+            continue;
+        }
+        for snapshot in list(sync_dir, &entry.file_name().to_string_lossy())? {
+            used.extend(snapshot.files.into_iter().filter_map(|x| x.hash));
+        }
+    }
+    let objects: HashMap<String, PathBuf> = WalkDir::new(dir.join("objects"))
+        .into_iter()
+        .filter_map(|x| x.ok())
+        .filter(|x| x.file_type().is_file())
+        .map(|x| {
+            (
+                x.file_name().to_string_lossy().to_string(),
+                x.path().to_path_buf(),
+            )
+        })
+        .collect();
+    for (hash, path) in objects {
+        if !used.contains(&hash) {
+            info!("Removing unused object {}", hash);
+            fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn snapshot(path: &str, hash: Option<&str>) -> Snapshot {
+        Snapshot {
+            id: 1,
+            files: vec![SnapshotFile {
+                path: PathBuf::from(path),
+                hash: hash.map(|x| x.to_string()),
+                mode: 0o644,
+                modified: 0,
+                symlink: None,
+            }],
+        }
+    }
+    const HASH: &str = "da39a3ee5e6b4b0d3255bfef95601890afd80709";
+    #[test]
+    fn accepts_relative_paths() {
+        assert!(check(&snapshot("save.dat", Some(HASH))).is_ok());
+        assert!(check(&snapshot("slot 1/save.dat", Some(HASH))).is_ok());
+        assert!(check(&snapshot("link", None)).is_ok());
+    }
+    #[test]
+    fn rejects_paths_outside_the_saves() {
+        assert!(check(&snapshot("../outside", Some(HASH))).is_err());
+        assert!(check(&snapshot("slot/../../outside", Some(HASH))).is_err());
+        assert!(check(&snapshot("/etc/passwd", Some(HASH))).is_err());
+        assert!(check(&snapshot("./save.dat", Some(HASH))).is_err());
+        assert!(check(&snapshot("", Some(HASH))).is_err());
+    }
+    #[test]
+    fn rejects_invalid_hashes() {
+        assert!(check(&snapshot("save.dat", Some("../../../etc/passwd"))).is_err());
+        assert!(check(&snapshot("save.dat", Some(&HASH[1..]))).is_err());
+        assert!(check(&snapshot("save.dat", Some(&HASH.replace('d', "/")))).is_err());
+    }
+}
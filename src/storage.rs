@@ -0,0 +1,540 @@
+/// How a game's saves are stored in its sync folder: as a plain copy of the save files, or as a single zip archive that can be encrypted with a passphrase.
+/// Pulling works out which one a folder holds by itself, so the config only decides what pushes write.
+use config::*;
+use crypto::{self, Key};
+use dialoguer::Password;
+use dirsync;
+use prompt;
+use std::collections::{BTreeMap, HashSet};
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Cursor, Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+use walkdir::WalkDir;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, DateTime, ZipArchive, ZipWriter};
+pub const ARCHIVE_NAME: &str = "saves.zip";
+pub const ENCRYPTED_NAME: &str = "saves.zip.enc";
+/// Environment variable the archive passphrase is read from before asking for it
+pub const PASSPHRASE_VAR: &str = "WYVERN_SAVE_PASSPHRASE";
+const MAGIC: &[u8] = b"wyvern-saves\x01";
+lazy_static! {
+    /// The passphrase is only asked for once per run, even when syncing many games
+    static ref PASSPHRASE: Mutex<Option<String>> = Mutex::new(None);
+}
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default)]
+#[serde(default)]
+pub struct Settings {
+    /// Push saves as a zip archive instead of copying the files
+    pub archive: bool,
+    /// Encrypt pushed archives with a passphrase. Implies archive.
+    pub encrypt: bool,
+}
+impl Settings {
+    pub fn configured() -> Settings {
+        confy::load::<Config>("wyvern", "wyvern")
+            .map(|x| x.save_storage)
+            .unwrap_or_default()
+    }
+}
+/// Where a game's saves are kept inside its sync folder, when they're stored as plain files. Saves that are a single file are kept in the folder under the same name.
+pub fn synced_path(saves: &Path, sync_folder: &Path) -> PathBuf {
+    let file_name = match saves.file_name() {
+        Some(file_name) => file_name,
+        None => return sync_folder.to_path_buf(),
+    };
+    if saves.is_file() || (!saves.exists() && sync_folder.join(file_name).is_file()) {
+        sync_folder.join(file_name)
+    } else {
+        sync_folder.to_path_buf()
+    }
+}
+/// The path in a sync folder that holds a game's saves, whichever way they're stored
+pub fn stored_path(saves: &Path, sync_folder: &Path) -> PathBuf {
+    let settings = Settings::configured();
+    let encrypted = sync_folder.join(ENCRYPTED_NAME);
+    let archive = sync_folder.join(ARCHIVE_NAME);
+    if encrypted.exists() || (settings.encrypt && !archive.exists()) {
+        encrypted
+    } else if archive.exists() || settings.archive {
+        archive
+    } else {
+        synced_path(saves, sync_folder)
+    }
+}
+/// Pushes saves into a sync folder, stored the way the config asks for
+pub fn push(
+    saves: &Path,
+    sync_folder: &Path,
+    options: &dirsync::Options,
+) -> Result<dirsync::Summary, io::Error> {
+    let settings = Settings::configured();
+    if !settings.archive && !settings.encrypt {
+        remove_except(sync_folder, None)?;
+        return dirsync::sync(saves, &synced_path(saves, sync_folder), options);
+    }
+    let name = if settings.encrypt {
+        ENCRYPTED_NAME
+    } else {
+        ARCHIVE_NAME
+    };
+    // Without --delete, saves that are gone locally stay in the archive, as they would if stored as plain files
+    let kept = if options.delete {
+        BTreeMap::new()
+    } else {
+        stored_files(saves, sync_folder)?
+    };
+    fs::create_dir_all(sync_folder)?;
+    remove_except(sync_folder, Some(name))?;
+    let (packed, files) = pack(saves, &kept)?;
+    let mut summary = dirsync::Summary::default();
+    let stored = sync_folder.join(name);
+    if let Ok(existing) = fs::read(&stored) {
+        let existing = if settings.encrypt {
+            decrypt(&existing)?
+        } else {
+            existing
+        };
+        if existing == packed {
+            summary.skipped = files;
+            return Ok(summary);
+        }
+    }
+    let data = if settings.encrypt {
+        encrypt(&packed)?
+    } else {
+        packed
+    };
+    let temp = stored.with_extension("tmp");
+    fs::write(&temp, &data)?;
+    fs::rename(&temp, &stored)?;
+    summary.copied = files;
+    summary.bytes = data.len() as u64;
+    Ok(summary)
+}
+/// Pulls saves out of a sync folder, decrypting and extracting them if they're stored as an archive
+pub fn pull(
+    sync_folder: &Path,
+    saves: &Path,
+    options: &dirsync::Options,
+) -> Result<dirsync::Summary, io::Error> {
+    let encrypted = sync_folder.join(ENCRYPTED_NAME);
+    let archive = sync_folder.join(ARCHIVE_NAME);
+    if encrypted.exists() {
+        unpack(&decrypt(&fs::read(encrypted)?)?, saves, options)
+    } else if archive.exists() {
+        unpack(&fs::read(archive)?, saves, options)
+    } else {
+        dirsync::sync(&synced_path(saves, sync_folder), saves, options)
+    }
+}
+/// Removes whatever a previous push left in a sync folder that the current way of storing saves doesn't use
+fn remove_except(sync_folder: &Path, keep: Option<&str>) -> Result<(), io::Error> {
+    for name in [ARCHIVE_NAME, ENCRYPTED_NAME].iter() {
+        let path = sync_folder.join(name);
+        if Some(*name) == keep || !path.exists() {
+            continue;
+        }
+        info!("Removing {}", path.display());
+        fs::remove_file(path)?;
+    }
+    if keep.is_some() {
+        for entry in fs::read_dir(sync_folder)? {
+            let entry = entry?;
+            if Some(entry.file_name().to_string_lossy().as_ref()) == keep {
+                continue;
+            }
+            info!("Removing {}", entry.path().display());
+            if entry.file_type()?.is_dir() {
+                fs::remove_dir_all(entry.path())?;
+            } else {
+                fs::remove_file(entry.path())?;
+            }
+        }
+    }
+    Ok(())
+}
+fn zip_error(err: zip::result::ZipError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+/// Zip timestamps have no time zone, so they're always written and read as UTC
+fn zip_time(time: SystemTime) -> DateTime {
+    let formatted = humantime::format_rfc3339_seconds(time).to_string();
+    let field = |range: std::ops::Range<usize>| formatted[range].parse().unwrap_or(0);
+    DateTime::from_date_and_time(
+        field(0..4),
+        field(5..7) as u8,
+        field(8..10) as u8,
+        field(11..13) as u8,
+        field(14..16) as u8,
+        field(17..19) as u8,
+    )
+    .unwrap_or_default()
+}
+fn system_time(time: DateTime) -> Option<SystemTime> {
+    humantime::parse_rfc3339(&format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        time.year(),
+        time.month(),
+        time.day(),
+        time.hour(),
+        time.minute(),
+        time.second()
+    ))
+    .ok()
+}
+/// Zips up saves, which can be a directory or a single file, along with stored files that aren't among them. Returns the archive and how many files are in it.
+fn pack(saves: &Path, kept: &BTreeMap<PathBuf, StoredFile>) -> Result<(Vec<u8>, usize), io::Error> {
+    let mut zip = ZipWriter::new(Cursor::new(vec![]));
+    let mut files = 0;
+    let entries: Vec<(PathBuf, PathBuf)> = if saves.is_dir() {
+        WalkDir::new(saves)
+            .min_depth(1)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_map(|x| x.ok())
+            .filter(|x| x.file_type().is_file())
+            .map(|x| {
+                let relative = x.path().strip_prefix(saves).unwrap().to_path_buf();
+                (x.path().to_path_buf(), relative)
+            })
+            .collect()
+    } else {
+        vec![(
+            saves.to_path_buf(),
+            PathBuf::from(saves.file_name().unwrap()),
+        )]
+    };
+    for (path, relative) in entries.iter() {
+        let meta = fs::metadata(path)?;
+        let options = FileOptions::default()
+            .compression_method(CompressionMethod::Deflated)
+            .last_modified_time(zip_time(meta.modified()?))
+            .unix_permissions(meta.permissions().mode());
+        zip.start_file(relative.to_string_lossy(), options)
+            .map_err(zip_error)?;
+        io::copy(&mut File::open(path)?, &mut zip)?;
+        files += 1;
+    }
+    let packed: HashSet<&PathBuf> = entries.iter().map(|x| &x.1).collect();
+    for (relative, file) in kept.iter() {
+        if packed.contains(relative) {
+            continue;
+        }
+        let mut options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+        if let Some(modified) = file.modified {
+            options = options.last_modified_time(zip_time(modified));
+        }
+        if let Some(mode) = file.mode {
+            options = options.unix_permissions(mode);
+        }
+        zip.start_file(relative.to_string_lossy(), options)
+            .map_err(zip_error)?;
+        zip.write_all(&file.contents)?;
+        files += 1;
+    }
+    let packed = zip.finish().map_err(zip_error)?.into_inner();
+    Ok((packed, files))
+}
+/// A save file as it's stored in a sync folder
+pub struct StoredFile {
+    pub contents: Vec<u8>,
+    pub modified: Option<SystemTime>,
+    pub mode: Option<u32>,
+}
+/// Every save file in a sync folder, whichever way they're stored, by their path relative to the saves. A save that's a single file is keyed by its file name.
+pub fn stored_files(
+    saves: &Path,
+    sync_folder: &Path,
+) -> Result<BTreeMap<PathBuf, StoredFile>, io::Error> {
+    let encrypted = sync_folder.join(ENCRYPTED_NAME);
+    let archive = sync_folder.join(ARCHIVE_NAME);
+    if encrypted.exists() {
+        return archive_files(&decrypt(&fs::read(encrypted)?)?);
+    } else if archive.exists() {
+        return archive_files(&fs::read(archive)?);
+    }
+    let stored = synced_path(saves, sync_folder);
+    let mut files = BTreeMap::new();
+    for (relative, path) in tree_files(&stored)? {
+        let meta = fs::metadata(&path)?;
+        let file = StoredFile {
+            contents: fs::read(&path)?,
+            modified: meta.modified().ok(),
+            mode: Some(meta.permissions().mode()),
+        };
+        files.insert(relative, file);
+    }
+    Ok(files)
+}
+/// Every local save file, keyed the same way as stored_files, with where it is
+pub fn local_files(saves: &Path) -> Result<BTreeMap<PathBuf, PathBuf>, io::Error> {
+    tree_files(saves)
+}
+/// Files under a directory by their relative path, or a single file by its name
+fn tree_files(path: &Path) -> Result<BTreeMap<PathBuf, PathBuf>, io::Error> {
+    let mut files = BTreeMap::new();
+    if path.is_file() {
+        files.insert(
+            PathBuf::from(path.file_name().unwrap()),
+            path.to_path_buf(),
+        );
+    } else if path.is_dir() {
+        for entry in WalkDir::new(path).min_depth(1) {
+            let entry = entry.map_err(io::Error::from)?;
+            if entry.file_type().is_file() {
+                let relative = entry.path().strip_prefix(path).unwrap().to_path_buf();
+                files.insert(relative, entry.path().to_path_buf());
+            }
+        }
+    }
+    Ok(files)
+}
+fn archive_files(data: &[u8]) -> Result<BTreeMap<PathBuf, StoredFile>, io::Error> {
+    let mut archive = ZipArchive::new(Cursor::new(data)).map_err(zip_error)?;
+    let mut files = BTreeMap::new();
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i).map_err(zip_error)?;
+        let relative = match file.enclosed_name() {
+            Some(relative) => relative.to_path_buf(),
+            None => {
+                warn!("Skipping {} in save archive", file.name());
+                continue;
+            }
+        };
+        let mut contents = vec![];
+        file.read_to_end(&mut contents)?;
+        let stored = StoredFile {
+            contents,
+            modified: system_time(file.last_modified()),
+            mode: file.unix_mode(),
+        };
+        files.insert(relative, stored);
+    }
+    Ok(files)
+}
+/// Whether saves are a single file rather than a directory, going by the local saves if they exist and the stored ones otherwise
+pub fn is_single_file<T>(saves: &Path, stored: &BTreeMap<PathBuf, T>) -> bool {
+    if saves.exists() {
+        return !saves.is_dir();
+    }
+    stored.len() == 1 && stored.keys().next().map(|x| x.as_os_str()) == saves.file_name()
+}
+/// Where a save file belongs locally
+pub fn local_path(saves: &Path, relative: &Path, single_file: bool) -> PathBuf {
+    if single_file {
+        saves.to_path_buf()
+    } else {
+        saves.join(relative)
+    }
+}
+/// Writes out a stored save file, without leaving it half-written if interrupted
+pub fn write_file(path: &Path, file: &StoredFile) -> Result<(), io::Error> {
+    info!("Writing {}", path.display());
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut name = path.file_name().unwrap().to_os_string();
+    name.push(".wyvern-tmp");
+    let temp = path.with_file_name(name);
+    File::create(&temp)?.write_all(&file.contents)?;
+    if let Some(mode) = file.mode {
+        fs::set_permissions(&temp, fs::Permissions::from_mode(mode))?;
+    }
+    if let Some(modified) = file.modified {
+        File::open(&temp)?.set_modified(modified)?;
+    }
+    fs::rename(&temp, path)
+}
+/// Extracts an archive of saves, skipping files that are already the same
+fn unpack(
+    data: &[u8],
+    saves: &Path,
+    options: &dirsync::Options,
+) -> Result<dirsync::Summary, io::Error> {
+    let files = archive_files(data)?;
+    let single_file = is_single_file(saves, &files);
+    let mut summary = dirsync::Summary::default();
+    let mut extracted = HashSet::new();
+    for (relative, file) in files.iter() {
+        let path = local_path(saves, relative, single_file);
+        extracted.insert(path.clone());
+        if fs::read(&path).map(|x| x == file.contents).unwrap_or(false) {
+            summary.skipped += 1;
+            continue;
+        }
+        write_file(&path, file)?;
+        summary.copied += 1;
+        summary.bytes += file.contents.len() as u64;
+    }
+    if options.delete && !single_file && saves.is_dir() {
+        for (_, path) in tree_files(saves)? {
+            if !extracted.contains(&path) {
+                info!("Deleting {}", path.display());
+                fs::remove_file(path)?;
+                summary.deleted += 1;
+            }
+        }
+    }
+    Ok(summary)
+}
+fn passphrase() -> Result<String, io::Error> {
+    if let Ok(passphrase) = env::var(PASSPHRASE_VAR) {
+        return Ok(passphrase);
+    }
+    let mut cached = PASSPHRASE.lock().unwrap();
+    if let Some(ref passphrase) = *cached {
+        return Ok(passphrase.clone());
+    }
+    if !prompt::interactive() {
+        prompt::needs_input(
+            "The save archive passphrase",
+            &format!("Set it in {}", PASSPHRASE_VAR),
+        );
+    }
+    let passphrase = Password::new()
+        .with_prompt("Save archive passphrase")
+        .interact()
+        .map_err(|err| io::Error::other(err.to_string()))?;
+    *cached = Some(passphrase.clone());
+    Ok(passphrase)
+}
+/// Encrypts with a key derived from the passphrase. The output is the magic, the salt, the nonce and then the ciphertext.
+fn encrypt(data: &[u8]) -> Result<Vec<u8>, io::Error> {
+    encrypt_with(data, &passphrase()?)
+}
+fn encrypt_with(data: &[u8], passphrase: &str) -> Result<Vec<u8>, io::Error> {
+    let key = Key::generate(passphrase)?;
+    let mut encrypted = MAGIC.to_vec();
+    encrypted.extend_from_slice(&key.salt);
+    encrypted.extend(key.encrypt(data)?);
+    Ok(encrypted)
+}
+fn decrypt(data: &[u8]) -> Result<Vec<u8>, io::Error> {
+    decrypt_with(data, &passphrase()?)
+}
+fn decrypt_with(data: &[u8], passphrase: &str) -> Result<Vec<u8>, io::Error> {
+    if data.len() < MAGIC.len() + crypto::SALT_LEN || !data.starts_with(MAGIC) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Not an encrypted save archive",
+        ));
+    }
+    let salt = &data[MAGIC.len()..MAGIC.len() + crypto::SALT_LEN];
+    Key::derive(passphrase, salt)?
+        .decrypt(&data[MAGIC.len() + crypto::SALT_LEN..])
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Could not decrypt save archive. Is the passphrase right?",
+            )
+        })
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn write(path: &Path, contents: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+    fn read(path: &Path) -> String {
+        fs::read_to_string(path).unwrap()
+    }
+    #[test]
+    fn encrypts_and_decrypts() {
+        let encrypted = encrypt_with(b"saves", "right").unwrap();
+        assert!(encrypted.starts_with(MAGIC));
+        assert_eq!(decrypt_with(&encrypted, "right").unwrap(), b"saves");
+        assert!(decrypt_with(&encrypted, "wrong").is_err());
+    }
+    #[test]
+    fn rejects_damaged_archives() {
+        let encrypted = encrypt_with(b"saves", "right").unwrap();
+        assert!(decrypt_with(&encrypted[..encrypted.len() - 1], "right").is_err());
+        assert!(decrypt_with(&encrypted[..MAGIC.len() + 4], "right").is_err());
+        let mut bad_magic = encrypted.clone();
+        bad_magic[0] ^= 1;
+        assert!(decrypt_with(&bad_magic, "right").is_err());
+        let mut changed = encrypted;
+        let last = changed.len() - 1;
+        changed[last] ^= 1;
+        assert!(decrypt_with(&changed, "right").is_err());
+    }
+    #[test]
+    fn packs_and_unpacks_directories() {
+        let dir = tempfile::tempdir().unwrap();
+        let saves = dir.path().join("saves");
+        write(&saves.join("slot1.sav"), "one");
+        write(&saves.join("profiles/me.cfg"), "me");
+        fs::set_permissions(saves.join("slot1.sav"), fs::Permissions::from_mode(0o600)).unwrap();
+        let (packed, files) = pack(&saves, &BTreeMap::new()).unwrap();
+        assert_eq!(files, 2);
+        let restored = dir.path().join("restored");
+        let summary = unpack(&packed, &restored, &dirsync::Options::default()).unwrap();
+        assert_eq!(summary.copied, 2);
+        assert_eq!(read(&restored.join("slot1.sav")), "one");
+        assert_eq!(read(&restored.join("profiles/me.cfg")), "me");
+        let mode = fs::metadata(restored.join("slot1.sav"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o600);
+        let summary = unpack(&packed, &restored, &dirsync::Options::default()).unwrap();
+        assert_eq!(summary.skipped, 2);
+    }
+    #[test]
+    fn packs_and_unpacks_single_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let saves = dir.path().join("game.sav");
+        write(&saves, "saved");
+        let (packed, files) = pack(&saves, &BTreeMap::new()).unwrap();
+        assert_eq!(files, 1);
+        fs::remove_file(&saves).unwrap();
+        unpack(&packed, &saves, &dirsync::Options::default()).unwrap();
+        assert_eq!(read(&saves), "saved");
+    }
+    #[test]
+    fn packs_kept_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let saves = dir.path().join("saves");
+        write(&saves.join("slot1.sav"), "new");
+        let stored = |contents: &str| StoredFile {
+            contents: contents.as_bytes().to_vec(),
+            modified: None,
+            mode: Some(0o644),
+        };
+        let mut kept = BTreeMap::new();
+        kept.insert(PathBuf::from("slot1.sav"), stored("old"));
+        kept.insert(PathBuf::from("slot2.sav"), stored("deleted locally"));
+        let (packed, files) = pack(&saves, &kept).unwrap();
+        assert_eq!(files, 2);
+        let unpacked = archive_files(&packed).unwrap();
+        assert_eq!(unpacked[Path::new("slot1.sav")].contents, b"new");
+        assert_eq!(
+            unpacked[Path::new("slot2.sav")].contents,
+            b"deleted locally"
+        );
+    }
+    #[test]
+    fn skips_entries_outside_the_saves() {
+        let mut zip = ZipWriter::new(Cursor::new(vec![]));
+        for name in ["../escaped", "/absolute", "slot1.sav"].iter() {
+            zip.start_file(*name, FileOptions::default()).unwrap();
+            zip.write_all(b"saved").unwrap();
+        }
+        let packed = zip.finish().unwrap().into_inner();
+        let files = archive_files(&packed).unwrap();
+        assert_eq!(
+            files.keys().collect::<Vec<_>>(),
+            vec![Path::new("slot1.sav")]
+        );
+        let dir = tempfile::tempdir().unwrap();
+        let saves = dir.path().join("saves");
+        unpack(&packed, &saves, &dirsync::Options::default()).unwrap();
+        assert!(!dir.path().join("escaped").exists());
+        assert_eq!(read(&saves.join("slot1.sav")), "saved");
+    }
+}
@@ -1,102 +1,116 @@
 use args::Command::*;
+use args::SaveDb::*;
 use args::Sync::*;
 use config::*;
+use dirsync;
 use gog::gog::FilterParam::*;
 use gog::gog::*;
 use gog::*;
+use launch;
+use locations;
+use merge;
+use paths;
+use prompt;
+use remote;
+use snapshots;
+use storage;
+use std::collections::HashMap;
 use std::env::current_dir;
-use std::fs::{self, File};
+use std::fs::File;
 use std::io::{self, *};
 use std::path::*;
-use std::process::*;
+use std::result::Result;
+use std::time::{Duration, SystemTime};
+use walkdir::WalkDir;
+use watch;
+use wine;
 /// Parses args, assuming a sync subcommand
 pub fn parse_args(gog: Gog, sync_saves: Option<String>, args: ::args::Wyvern) -> Gog {
     match args.command {
-        Sync(Push { game_dir, sync_to }) => {
+        Sync(Push {
+            game_dir,
+            sync_to,
+            name,
+            transfer,
+        }) => {
+            // Games given by name have no game directory, so a single path is where to sync to
+            let (game_dir, sync_to) = match (name.is_some(), sync_to) {
+                (true, None) => (None, game_dir),
+                (_, sync_to) => (game_dir, sync_to),
+            };
             if sync_saves.is_some() {
                 let mut sync_saves = sync_saves.unwrap();
                 if sync_to.is_some() {
                     info!("Using manual argument sync path");
                     sync_saves = sync_to.unwrap().to_str().unwrap().to_string();
                 }
-                info!("Opening gameinfo file");
-                let gameinfo = File::open(game_dir.join("gameinfo"));
-                if gameinfo.is_ok() {
-                    let mut ginfo_string = String::new();
-                    info!("Reading from gameinfo file");
-                    gameinfo.unwrap().read_to_string(&mut ginfo_string).unwrap();
-                    info!("Parsing gameinfo");
-                    let gameinfo =
-                        GameInfo::parse(ginfo_string).expect("Couldn't parse gameinfo file");
-                    info!("Fetching details about game from GOG");
-                    if let Ok(details) =
-                        gog.get_products(FilterParams::from_one(Search(gameinfo.name.clone())))
-                    {
-                        let id = details[0].id;
-                        let savedb_path = PathBuf::from(sync_saves.clone()).join("savedb.json");
-                        let mut save_db = SaveDB::load(&savedb_path).unwrap();
-                        let path: PathBuf;
-                        if save_db.saves.contains_key(&format!("{}", id)) {
-                            info!("Savedb has path to saves confgured already");
-                            path = PathBuf::from(
-                                save_db.saves.get(&format!("{}", id)).unwrap().path.clone(),
-                            );
-                        } else {
-                            let mut input = String::new();
-                            let mut yn = String::new();
-                            println!("You haven't specified where this game's save files are yet. Please insert a path to where they are located.");
-                            loop {
-                                io::stdout().flush().unwrap();
-                                io::stdin().read_line(&mut input).unwrap();
-                                print!("Are you sure this where the save files are located?(Y/n)");
-                                io::stdout().flush().unwrap();
-                                io::stdin().read_line(&mut yn).unwrap();
-                                if &yn == "n" || &yn == "N" {
-                                    continue;
-                                }
-                                break;
-                            }
-                            let save_path =
-                                current_dir().unwrap().join(PathBuf::from(&input.trim()));
-                            info!("Inserting saveinfo into savedb");
-                            save_db.saves.insert(
-                                format!("{}", id),
-                                SaveInfo {
-                                    path: save_path.to_str().unwrap().to_string(),
-                                    identifier: SaveType::GOG(id),
-                                },
-                            );
-                            path = save_path;
-                            info!("Storing savedb");
-                            save_db.store(&savedb_path).unwrap();
-                        }
-                        let mut path_string = path.to_str().unwrap().to_string();
-                        if path.exists() && path.is_dir() {
-                            path_string += "/";
-                        }
-                        let save_dir = PathBuf::from(sync_saves);
-                        let save_folder =
-                            save_dir.clone().join("saves").join(format!("gog_{}", id));
-                        if fs::metadata(&save_folder).is_err() {
-                            info!("Creating directories for files");
-                            fs::create_dir_all(&save_folder).unwrap();
+                let game = synced_game(&gog, name, game_dir.as_ref());
+                let mirror = open_location(&sync_saves);
+                let savedb_path = mirror.path.join("savedb.json");
+                let save_db = load_savedb(&savedb_path);
+                let path: PathBuf;
+                if save_db.saves.contains_key(&game.key) {
+                    info!("Savedb has path to saves confgured already");
+                    path = local_saves(&save_db.saves[&game.key], game_dir.as_ref());
+                } else if let Some(found) = suggest_saves(&game, game_dir.as_ref()) {
+                    info!("Inserting saveinfo into savedb");
+                    store_save_info(
+                        &savedb_path,
+                        game.key.clone(),
+                        SaveInfo {
+                            path: recorded_path(&found, &game.identifier, game_dir.as_ref()),
+                            identifier: game.identifier.clone(),
+                            title: Some(game.title.clone()),
+                        },
+                    );
+                    path = found;
+                } else {
+                    let mut input = String::new();
+                    let mut yn = String::new();
+                    if !prompt::interactive() {
+                        prompt::needs_input(
+                            "Where this game's save files are",
+                            "Set it with wyvern sync set-path",
+                        );
+                    }
+                    println!("You haven't specified where this game's save files are yet. Please insert a path to where they are located.");
+                    loop {
+                        io::stdout().flush().unwrap();
+                        io::stdin().read_line(&mut input).unwrap();
+                        print!("Are you sure this where the save files are located?(Y/n)");
+                        io::stdout().flush().unwrap();
+                        io::stdin().read_line(&mut yn).unwrap();
+                        if &yn == "n" || &yn == "N" {
+                            continue;
                         }
-                        info!("Start Rsyncing files");
-                        Command::new("rsync")
-                            .arg(
-                                path_string
-                                    .replace("~", dirs::home_dir().unwrap().to_str().unwrap()),
-                            )
-                            .arg(save_folder.to_str().unwrap())
-                            .arg("-a")
-                            .output()
-                            .unwrap();
-                        println!("Synced save files to save folder!");
-                    } else {
-                        error!("Could not find a game named {}.", gameinfo.name);
+                        break;
                     }
+                    let save_path = current_dir().unwrap().join(paths::expand(input.trim()));
+                    info!("Inserting saveinfo into savedb");
+                    store_save_info(
+                        &savedb_path,
+                        game.key.clone(),
+                        SaveInfo {
+                            path: recorded_path(&save_path, &game.identifier, game_dir.as_ref()),
+                            identifier: game.identifier.clone(),
+                            title: Some(game.title.clone()),
+                        },
+                    );
+                    path = save_path;
+                }
+                let save_dir = mirror.path.clone();
+                let save_folder = save_dir.join("saves").join(game.folder());
+                info!("Start syncing files");
+                let options = dirsync::Options::configured(transfer.delete, transfer.checksum);
+                let resolution = merge::Resolution {
+                    prefer: transfer.prefer,
+                    ..Default::default()
+                };
+                if sync(path, save_folder, false, &resolution, &options) {
+                    snapshot_pushed(&save_dir, &game.folder());
+                    finish(mirror, &format!("Push saves of {}", game.title));
                 } else {
-                    error!("Game directory or gameinfo file missing")
+                    std::process::exit(64);
                 }
             } else {
                 error!("You have not configured a directory to sync your saves to. Edit ~/.config/wyvern/wyvern.toml to get started!");
@@ -105,55 +119,41 @@ pub fn parse_args(gog: Gog, sync_saves: Option<String>, args: ::args::Wyvern) ->
         Sync(Pull {
             game_dir,
             sync_from,
+            name,
+            transfer,
             force,
             ignore_older,
         }) => {
+            // Games given by name have no game directory, so a single path is where to sync from
+            let (game_dir, sync_from) = match (name.is_some(), sync_from) {
+                (true, None) => (None, game_dir),
+                (_, sync_from) => (game_dir, sync_from),
+            };
             if sync_saves.is_some() {
-                let sync_saves = sync_saves
-                    .unwrap()
-                    .replace("~", dirs::home_dir().unwrap().to_str().unwrap());
-                info!("Opening gameinfo file");
-                let gameinfo = File::open(game_dir.join("gameinfo"));
-                if gameinfo.is_ok() {
-                    let mut ginfo_string = String::new();
-                    info!("Reading in gameinfo file");
-                    gameinfo
-                        .unwrap()
-                        .read_to_string(&mut ginfo_string)
-                        .expect("Couldn't read from gameinfo file.");
-                    info!("Parsing gameinfo file");
-                    let gameinfo =
-                        GameInfo::parse(ginfo_string).expect("Couldn't parse gameinfo file");
-                    if let Ok(details) =
-                        gog.get_products(FilterParams::from_one(Search(gameinfo.name.clone())))
-                    {
-                        let id = details[0].id;
-                        let mut savedb_path = PathBuf::from(sync_saves.clone()).join("savedb.json");
-                        if sync_from.is_some() {
-                            savedb_path = sync_from.unwrap().join("savedb.json");
-                        }
-                        info!("Loading savedb");
-                        let save_db = SaveDB::load(&savedb_path).unwrap();
-                        if save_db.saves.contains_key(&format!("{}", id)) {
-                            let save_path = PathBuf::from(sync_saves.clone())
-                                .join("saves")
-                                .join(format!("gog_{}", id));
-                            let save_files = save_db.saves.get(&format!("{}", id)).unwrap();
-                            let saved_path = PathBuf::from(
-                                save_files
-                                    .path
-                                    .replace("~", dirs::home_dir().unwrap().to_str().unwrap()),
-                            );
-                            info!("Syncing files now");
-                            sync(save_path, saved_path, force, ignore_older);
-                        } else {
-                            error!("This game's saves have not been configured to be synced yet. Push first!");
-                        }
-                    } else {
-                        error!("Could not find a game named {}", gameinfo.name)
+                let mut sync_saves = sync_saves.unwrap();
+                if sync_from.is_some() {
+                    info!("Using manual argument sync path");
+                    sync_saves = sync_from.unwrap().to_str().unwrap().to_string();
+                }
+                let game = synced_game(&gog, name, game_dir.as_ref());
+                let mirror = open_location(&sync_saves);
+                info!("Loading savedb");
+                let save_db = load_savedb(mirror.path.join("savedb.json"));
+                if let Some(save_files) = save_db.saves.get(&game.key) {
+                    let save_path = mirror.path.join("saves").join(game.folder());
+                    let saved_path = local_saves(save_files, game_dir.as_ref());
+                    info!("Syncing files now");
+                    let options = dirsync::Options::configured(transfer.delete, transfer.checksum);
+                    let resolution = merge::Resolution {
+                        prefer: transfer.prefer,
+                        force,
+                        refuse: ignore_older,
+                    };
+                    if !sync(saved_path, save_path, true, &resolution, &options) {
+                        std::process::exit(64);
                     }
                 } else {
-                    error!("Game directory or gameinfo file missing");
+                    error!("This game's saves have not been configured to be synced yet. Push first!");
                 }
             } else {
                 error!("You have not config a directory to sync your saves from. Edit ~/.config/wyvern/wyvern.toml to get started!");
@@ -161,181 +161,924 @@ pub fn parse_args(gog: Gog, sync_saves: Option<String>, args: ::args::Wyvern) ->
         }
         Sync(DbPull {
             path,
+            transfer,
             force,
             ignore_older,
         }) => {
-            let dbpath: PathBuf;
+            let location: String;
             if path.is_some() {
                 info!("Using db passed in arguments");
-                dbpath = path.unwrap();
+                location = path.unwrap().to_str().unwrap().to_string();
             } else if sync_saves.is_some() {
                 info!("Using configured db path");
-                dbpath = PathBuf::from(sync_saves.unwrap());
+                location = sync_saves.unwrap();
             } else {
                 error!("You have not specified a sync directory in the config yet. Specify one or call db with a path to your db.");
                 std::process::exit(0);
             }
+            let mirror = open_location(&location);
+            let dbpath = mirror.path.clone();
             info!("Loading savedb");
-            let savedb = SaveDB::load(&dbpath.join("savedb.json")).unwrap();
+            let savedb = load_savedb(&dbpath.join("savedb.json"));
+            let options = dirsync::Options::configured(transfer.delete, transfer.checksum);
+            let resolution = merge::Resolution {
+                prefer: transfer.prefer,
+                force,
+                refuse: ignore_older,
+            };
+            let mut failed = false;
             for (key, value) in savedb.saves.iter() {
                 println!("Syncing {} now", key);
-                let save_path = PathBuf::from(
-                    value
-                        .path
-                        .replace("~", dirs::home_dir().unwrap().to_str().unwrap()),
-                );
-                let mut folder_name = key.clone();
-                if let SaveType::GOG(id) = value.identifier {
-                    folder_name = format!("gog_{}", id);
-                }
-                let synced_path = dbpath.join("saves").join(folder_name);
+                let save_path = save_path(value);
+                let synced_path = dbpath
+                    .join("saves")
+                    .join(save_folder(key, &value.identifier));
                 info!("Syncing files now.");
-                sync(synced_path, save_path, force, ignore_older);
-                println!("Synced {}", key);
+                if sync(save_path, synced_path, true, &resolution, &options) {
+                    println!("Synced {}", key);
+                } else {
+                    failed = true;
+                }
+            }
+            if failed {
+                std::process::exit(64);
             }
         }
         Sync(DbPush {
             path,
+            transfer,
             force,
             ignore_older,
         }) => {
-            let dpath: PathBuf;
+            let location: String;
             if path.is_some() {
                 info!("Using db passed in arguments");
-                dpath = path.unwrap();
+                location = path.unwrap().to_str().unwrap().to_string();
             } else if sync_saves.is_some() {
                 info!("Using configured db path");
-                dpath = PathBuf::from(sync_saves.unwrap());
+                location = sync_saves.unwrap();
             } else {
                 error!("You have not specified a sync directory in the config yet. Specify one or call db with a path to your db.");
                 std::process::exit(0);
             }
+            let mirror = open_location(&location);
+            let dpath = mirror.path.clone();
             info!("Loading savedb");
-            let savedb = SaveDB::load(dpath.clone().join("savedb.json")).unwrap();
+            let savedb = load_savedb(dpath.clone().join("savedb.json"));
+            let options = dirsync::Options::configured(transfer.delete, transfer.checksum);
+            let resolution = merge::Resolution {
+                prefer: transfer.prefer,
+                force,
+                refuse: ignore_older,
+            };
+            let mut failed = false;
             for (key, value) in savedb.saves {
                 println!("Syncing {} now", key);
-                let save_path = PathBuf::from(
-                    value
+                if push_saves(&dpath, &key, &value, &resolution, &options) {
+                    println!("Synced {}", key);
+                } else {
+                    failed = true;
+                }
+            }
+            finish(mirror, "Push all saves");
+            if failed {
+                std::process::exit(64);
+            }
+        }
+        Sync(ListSaves { db, json }) => {
+            let mirror = open_location(&db_location(db, sync_saves));
+            let savedb = load_savedb(mirror.path.join("savedb.json"));
+            let known = locations::load();
+            let format = |x: SystemTime| humantime::format_rfc3339_seconds(x).to_string();
+            let mut listed: Vec<ListedSaves> = savedb
+                .saves
+                .iter()
+                .map(|(key, save_info)| {
+                    let synced = mirror
                         .path
-                        .replace("~", dirs::home_dir().unwrap().to_str().unwrap()),
+                        .join("saves")
+                        .join(save_folder(key, &save_info.identifier));
+                    let (pushed, pulled) = merge::last_synced(&synced);
+                    ListedSaves {
+                        key: key.clone(),
+                        title: saves_title(key, save_info, &known),
+                        path: save_path(save_info),
+                        last_push: pushed.map(format),
+                        last_pull: pulled.map(format),
+                        size: WalkDir::new(&synced)
+                            .into_iter()
+                            .filter_map(|x| x.ok()?.metadata().ok())
+                            .filter(|x| x.is_file())
+                            .map(|x| x.len())
+                            .sum(),
+                    }
+                })
+                .collect();
+            listed.sort_by_key(|x| x.title.to_lowercase());
+            if json {
+                println!(
+                    "{}",
+                    serde_json::to_string(&listed).expect("Couldn't convert synced saves to JSON")
                 );
-                let mut folder_name = key.clone();
-                if let SaveType::GOG(id) = value.identifier {
-                    folder_name = format!("gog_{}", id);
+            } else if listed.is_empty() {
+                println!("No games' saves are synced yet. Push some first!");
+            } else {
+                println!("Game - Save path - Last push - Last pull - Size");
+                for saves in listed {
+                    println!(
+                        "{} - {} - {} - {} - {} bytes",
+                        saves.title,
+                        saves.path.display(),
+                        saves.last_push.as_ref().map_or("never", |x| x.as_str()),
+                        saves.last_pull.as_ref().map_or("never", |x| x.as_str()),
+                        saves.size
+                    );
                 }
-                let dest_path = dpath.join("saves").join(&folder_name);
-                info!("Syncing file snow");
-                sync(save_path, dest_path, force, ignore_older);
-                println!("Synced {}", key);
+            }
+        }
+        Sync(RemoveSaves { game, db }) => {
+            let mirror = open_location(&db_location(db, sync_saves));
+            let savedb_path = mirror.path.join("savedb.json");
+            let savedb = load_savedb(&savedb_path);
+            let key = synced_key(&gog, &savedb, &game);
+            let save_info = &savedb.saves[&key];
+            let title = saves_title(&key, save_info, &locations::load());
+            let synced = mirror
+                .path
+                .join("saves")
+                .join(save_folder(&key, &save_info.identifier));
+            info!("Removing record from savedb");
+            let stored = SaveDB::update(&savedb_path, |x| {
+                x.saves.remove(&key);
+            });
+            if let Err(err) = stored {
+                error!("Could not store savedb. Error: {}", err);
+                std::process::exit(64);
+            }
+            if let Err(err) = merge::forget(&synced) {
+                warn!(
+                    "Could not forget when the saves were last synced. Error: {}",
+                    err
+                );
+            }
+            println!(
+                "Stopped syncing the saves of {}. Those already synced are kept in {}.",
+                title,
+                synced.display()
+            );
+            finish(mirror, &format!("Stop syncing the saves of {}", title));
+        }
+        Sync(SetPath { game, path, db }) => {
+            let mirror = open_location(&db_location(db, sync_saves));
+            let savedb_path = mirror.path.join("savedb.json");
+            let savedb = load_savedb(&savedb_path);
+            let key = synced_key(&gog, &savedb, &game);
+            let save_info = &savedb.saves[&key];
+            let title = saves_title(&key, save_info, &locations::load());
+            let path = current_dir().unwrap().join(path);
+            if !path.exists() {
+                warn!("There is nothing at {} yet", path.display());
+            }
+            info!("Updating record in savedb");
+            let stored = SaveDB::update(&savedb_path, |x| {
+                if let Some(save_info) = x.saves.get_mut(&key) {
+                    save_info.path = recorded_path(&path, &save_info.identifier, None);
+                }
+            });
+            if let Err(err) = stored {
+                error!("Could not store savedb. Error: {}", err);
+                std::process::exit(64);
+            }
+            // What the old location held says nothing about the new one, so its saves shouldn't be taken as deleted
+            let synced = mirror
+                .path
+                .join("saves")
+                .join(save_folder(&key, &save_info.identifier));
+            if let Err(err) = merge::forget(&synced) {
+                warn!(
+                    "Could not forget when the saves were last synced. Error: {}",
+                    err
+                );
+            }
+            println!("Saves of {} are now synced from {}", title, path.display());
+            finish(mirror, &format!("Set where the saves of {} are", title));
+        }
+        Sync(Diff { game, db }) => {
+            let mirror = open_location(&db_location(db, sync_saves));
+            let savedb = load_savedb(mirror.path.join("savedb.json"));
+            let key = synced_key(&gog, &savedb, &game);
+            let save_info = &savedb.saves[&key];
+            let local = save_path(save_info);
+            let synced = mirror
+                .path
+                .join("saves")
+                .join(save_folder(&key, &save_info.identifier));
+            let diffs = merge::diff(&local, &synced).unwrap_or_else(|err| {
+                error!("Could not compare save files. Error: {}", err);
+                std::process::exit(64);
+            });
+            println!(
+                "Comparing local saves at {} with synced saves at {}",
+                local.display(),
+                synced.display()
+            );
+            if diffs.is_empty() {
+                println!("Save files are the same on both sides");
+            }
+            let describe = |x: &Option<merge::Version>| match *x {
+                Some(ref version) => format!(
+                    "{} bytes, modified {}",
+                    version.size,
+                    version
+                        .modified
+                        .map_or("at an unknown time".to_string(), |x| {
+                            humantime::format_rfc3339_seconds(x).to_string()
+                        })
+                ),
+                None => "missing".to_string(),
+            };
+            for diff in diffs {
+                let status = match (&diff.local, &diff.synced) {
+                    (&Some(_), &None) => "Only local",
+                    (&None, &Some(_)) => "Only synced",
+                    _ => "Modified",
+                };
+                let changed = match diff.change {
+                    merge::Change::Local => "changed locally since the last sync",
+                    merge::Change::Remote => "changed in the sync folder since the last sync",
+                    _ => "changed on both sides since the last sync",
+                };
+                println!("{}: {} ({})", status, diff.path.display(), changed);
+                println!("    Local: {}", describe(&diff.local));
+                println!("    Synced: {}", describe(&diff.synced));
+            }
+        }
+        Sync(Db(Check { path })) => {
+            let location = match path {
+                Some(path) => path.to_str().unwrap().to_string(),
+                None => sync_saves.unwrap_or_else(|| {
+                    error!("You have not specified a sync directory in the config yet. Specify one or call check with a path to your db.");
+                    std::process::exit(64);
+                }),
+            };
+            let mirror = open_location(&location);
+            let savedb = SaveDB::load(mirror.path.join("savedb.json")).unwrap_or_else(|err| {
+                println!("{}", err);
+                std::process::exit(64);
+            });
+            let (problems, missing) = check_savedb(&savedb);
+            for line in problems.iter().chain(missing.iter()) {
+                println!("{}", line);
+            }
+            if problems.is_empty() {
+                println!("Savedb with {} games is valid", savedb.saves.len());
+            } else {
+                println!(
+                    "Found {} problems in savedb with {} games",
+                    problems.len(),
+                    savedb.saves.len()
+                );
+                std::process::exit(64);
+            }
+        }
+        Sync(Watch {
+            path,
+            delay,
+            transfer,
+            install_service,
+        }) => {
+            if install_service {
+                match watch::install_service() {
+                    Ok(path) => {
+                        println!("Installed service to {}", path.display());
+                        println!("Start it with: systemctl --user enable --now wyvern-watch.service");
+                    }
+                    Err(err) => {
+                        error!("Could not install service. Error: {}", err);
+                        std::process::exit(64);
+                    }
+                }
+                return gog;
+            }
+            let location = match path {
+                Some(path) => path.to_str().unwrap().to_string(),
+                None => sync_saves.unwrap_or_else(|| {
+                    error!("You have not specified a sync directory in the config yet. Specify one or call watch with a path to your db.");
+                    std::process::exit(64);
+                }),
+            };
+            let mirror = open_location(&location);
+            let options = dirsync::Options::configured(transfer.delete, transfer.checksum);
+            // Nobody is around to answer when both sides changed
+            let resolution = merge::Resolution {
+                prefer: transfer.prefer,
+                force: false,
+                refuse: true,
+            };
+            if let Err(err) = watch::watch(
+                mirror,
+                Duration::from_secs(delay),
+                &resolution,
+                &options,
+            ) {
+                error!("Could not watch save files. Error: {}", err);
+                std::process::exit(64);
             }
         }
         Sync(Saves {
             game_dir,
             saves,
+            name,
             db,
         }) => {
-            let dpath: PathBuf;
+            // Games given by name have no game directory, so a single path is where their saves are
+            let (game_dir, saves) = match (name.is_some(), saves) {
+                (true, None) => (None, game_dir),
+                (_, saves) => (game_dir, saves),
+            };
+            let saves = match saves {
+                Some(saves) => saves,
+                None => {
+                    error!("Pass where the game's save files are too.");
+                    std::process::exit(64);
+                }
+            };
+            let location: String;
             if db.is_some() {
                 info!("Using db passed in arguments");
-                dpath = db.unwrap();
+                location = db.unwrap().to_str().unwrap().to_string();
             } else if sync_saves.is_some() {
                 info!("Using configured db path");
-                dpath = PathBuf::from(sync_saves.unwrap());
+                location = sync_saves.unwrap();
             } else {
                 error!("You have not specified a sync directory in the config yet. Specify one or call saves with a path to your db.");
                 std::process::exit(0);
             }
+            let game = synced_game(&gog, name, game_dir.as_ref());
+            let mirror = open_location(&location);
             let dbpath = current_dir()
                 .unwrap()
-                .join(dpath.clone())
+                .join(mirror.path.clone())
                 .join("savedb.json");
-            info!("Loading savedb");
-            let mut savedb = SaveDB::load(dbpath.clone()).unwrap();
-            let gameinfo_path = current_dir().unwrap().join(game_dir).join("gameinfo");
-            if gameinfo_path.is_file() {
-                let mut ginfo_string = String::new();
-                info!("Opening and reading gameinfo file");
-                File::open(&gameinfo_path)
-                    .unwrap()
-                    .read_to_string(&mut ginfo_string)
-                    .unwrap();
-                let gameinfo = GameInfo::parse(ginfo_string).expect("Couldn't parse gameinfo");
-                if let Ok(details) =
-                    gog.get_products(FilterParams::from_one(Search(gameinfo.name.clone())))
-                {
-                    let id = details[0].id;
-                    info!("Inserting record into savedb");
-                    savedb.saves.insert(
-                        format!("{}", id),
-                        SaveInfo {
-                            path: current_dir()
-                                .unwrap()
-                                .join(saves)
-                                .to_str()
-                                .unwrap()
-                                .to_string(),
-                            identifier: SaveType::GOG(id),
-                        },
+            let saves = current_dir().unwrap().join(saves);
+            info!("Inserting record into savedb");
+            store_save_info(
+                &dbpath,
+                game.key,
+                SaveInfo {
+                    path: recorded_path(&saves, &game.identifier, game_dir.as_ref()),
+                    identifier: game.identifier,
+                    title: Some(game.title.clone()),
+                },
+            );
+            finish(
+                mirror,
+                &format!("Set where the saves of {} are", game.title),
+            );
+        }
+        Sync(History { game, name, db }) => {
+            let (mirror, savedb, key) = snapshot_target(&gog, sync_saves, db, game, name);
+            let sync_dir = mirror.path.clone();
+            let folder = save_folder(&key, &savedb.saves[&key].identifier);
+            let history = snapshots::list(&sync_dir, &folder).unwrap_or_else(|err| {
+                error!("Could not read snapshots. Error: {}", err);
+                std::process::exit(64);
+            });
+            if history.is_empty() {
+                println!("No snapshots of this game's saves yet. Push them first!");
+            } else {
+                println!("Snapshot - Date - Files - Size");
+                for snapshot in history.iter().rev() {
+                    println!(
+                        "{} - {} - {} - {} bytes",
+                        snapshot.id,
+                        snapshot.date(),
+                        snapshot.files.len(),
+                        snapshot.size(&sync_dir)
                     );
-                    info!("Storing savedb");
-                    savedb.store(dbpath).unwrap();
-                } else {
-                    error!("Could not find a game named {}", gameinfo.name);
                 }
-            } else {
-                error!("No gameinfo file at {}", gameinfo_path.to_str().unwrap());
             }
         }
+        Sync(Restore {
+            game,
+            snapshot,
+            name,
+            db,
+        }) => {
+            // Games given by label have no name, so a single argument is the snapshot
+            let (game, snapshot) = match (name.is_some(), snapshot) {
+                (true, None) => (None, game),
+                (_, snapshot) => (game, snapshot),
+            };
+            let snapshot = snapshot.unwrap_or_else(|| {
+                error!("Pass the id or date of the snapshot to restore. Run sync history to list them.");
+                std::process::exit(64);
+            });
+            let (mirror, savedb, key) = snapshot_target(&gog, sync_saves, db, game, name);
+            let sync_dir = mirror.path.clone();
+            let save_info = &savedb.saves[&key];
+            let title = saves_title(&key, save_info, &locations::load());
+            let folder = save_folder(&key, &save_info.identifier);
+            let restoring = match snapshots::find(&sync_dir, &folder, &snapshot) {
+                Ok(Some(restoring)) => restoring,
+                Ok(None) => {
+                    error!(
+                        "No snapshot {} of this game's saves. Run sync history to list them.",
+                        snapshot
+                    );
+                    std::process::exit(64);
+                }
+                Err(err) => {
+                    error!("Could not read snapshots. Error: {}", err);
+                    std::process::exit(64);
+                }
+            };
+            let local = save_path(save_info);
+            if local.exists() {
+                match snapshots::take(&sync_dir, &folder, &local) {
+                    Ok(Some(current)) => println!("Saved current saves as snapshot {}", current),
+                    Ok(None) => {}
+                    Err(err) => {
+                        error!(
+                            "Could not snapshot current saves, so not restoring. Error: {}",
+                            err
+                        );
+                        std::process::exit(64);
+                    }
+                }
+            }
+            let remote = sync_dir.join("saves").join(&folder);
+            let options = dirsync::Options::configured(true, false);
+            let restored = snapshots::restore(&sync_dir, &restoring, &remote)
+                .and_then(|_| storage::pull(&remote, &local, &options));
+            match restored {
+                Ok(_) => {
+                    println!("Restored saves from snapshot {}", restoring.date());
+                    finish(
+                        mirror,
+                        &format!("Restore saves of {} from {}", title, restoring.date()),
+                    );
+                }
+                Err(err) => {
+                    error!("Could not restore saves. Error: {}", err);
+                    std::process::exit(64);
+                }
+            }
+        }
+        Sync(UpdateLocations { file }) => match locations::update(&file) {
+            Ok(count) => println!("Added save locations for {} games", count),
+            Err(err) => {
+                error!("Could not update save locations. Error: {}", err);
+                std::process::exit(64);
+            }
+        },
         _ => println!("Wow, you should not be seeing this message."),
     };
     gog
 }
-/// Syncs save files from one location to another
-fn sync(sync_from: PathBuf, sync_to: PathBuf, ignore_older: bool, force: bool) {
-    let from_meta = fs::metadata(&sync_from);
-    let to_meta = fs::metadata(&sync_to);
-    if from_meta.is_err() {
-        error!("Can't sync nonexistent files! There should be save files at {}, but there are not. Aborting.", sync_from.to_str().unwrap());
-        return;
+/// Opens the sync location and finds which synced game the snapshot commands are for, by the label given with --name or as the other sync commands do
+fn snapshot_target(
+    gog: &Gog,
+    sync_saves: Option<String>,
+    db: Option<PathBuf>,
+    game: Option<String>,
+    name: Option<String>,
+) -> (remote::Mirror, SaveDB, String) {
+    let mirror = open_location(&db_location(db, sync_saves));
+    let savedb = load_savedb(mirror.path.join("savedb.json"));
+    let key = match (name, game) {
+        (Some(name), _) => {
+            if !savedb.saves.contains_key(&name) {
+                error!(
+                    "The saves of {} are not synced. Run sync ls to list the games whose saves are.",
+                    name
+                );
+                std::process::exit(64);
+            }
+            name
+        }
+        (None, Some(game)) => synced_key(gog, &savedb, &game),
+        (None, None) => {
+            error!("Pass the name of the game, or its label with --name.");
+            std::process::exit(64);
+        }
+    };
+    (mirror, savedb, key)
+}
+/// The sync location a command works on: the one passed with --db, or the configured one
+fn db_location(db: Option<PathBuf>, sync_saves: Option<String>) -> String {
+    match db.map(|x| x.to_str().unwrap().to_string()).or(sync_saves) {
+        Some(location) => location,
+        None => {
+            error!("You have not specified a sync directory in the config yet. Specify one or pass one with --db.");
+            std::process::exit(64);
+        }
     }
-    if let Ok(to_meta) = to_meta {
-        info!("File already synced to locations. Getting modified times");
-        let to_modified = to_meta.modified().unwrap();
-        let from_modified = from_meta.unwrap().modified().unwrap();
-        if to_modified > from_modified && !force {
-            if ignore_older {
-                println!("Aborting due to --ignore-older flag and newer save files being present");
-                return;
+}
+/// A game's synced saves, as listed by sync ls
+#[derive(Serialize)]
+struct ListedSaves {
+    key: String,
+    title: String,
+    path: PathBuf,
+    last_push: Option<String>,
+    last_pull: Option<String>,
+    /// Bytes the saves take up in the sync folder
+    size: u64,
+}
+/// What to call a game in the savedb: its recorded title, the name of a known GOG game, or its key
+fn saves_title(key: &str, save_info: &SaveInfo, known: &locations::Locations) -> String {
+    if let Some(ref title) = save_info.title {
+        return title.clone();
+    }
+    if let SaveType::GOG(id) = save_info.identifier {
+        if let Some(known) = known.get(&format!("{}", id)) {
+            return known.name.clone();
+        }
+    }
+    key.to_string()
+}
+/// Finds a game in a savedb by its key, label or title, or by the name or install directory of an installed GOG game. Exits if its saves aren't synced.
+fn synced_key(gog: &Gog, savedb: &SaveDB, game: &str) -> String {
+    if savedb.saves.contains_key(game) {
+        return game.to_string();
+    }
+    let known = locations::load();
+    let titled: Vec<&String> = savedb
+        .saves
+        .iter()
+        .filter(|&(key, save_info)| saves_title(key, save_info, &known).eq_ignore_ascii_case(game))
+        .map(|(key, _)| key)
+        .collect();
+    if titled.len() == 1 {
+        return titled[0].clone();
+    }
+    let installed = launch::find_game(game).and_then(|x| game_id(gog, &x));
+    match installed.map(|x| format!("{}", x)) {
+        Some(ref key) if savedb.saves.contains_key(key) => key.clone(),
+        _ => {
+            error!(
+                "The saves of {} are not synced. Run sync ls to list the games whose saves are.",
+                game
+            );
+            std::process::exit(64);
+        }
+    }
+}
+/// A game whose saves are synced
+struct SyncedGame {
+    /// Its key in the save database
+    key: String,
+    identifier: SaveType,
+    title: String,
+}
+impl SyncedGame {
+    fn folder(&self) -> String {
+        save_folder(&self.key, &self.identifier)
+    }
+}
+/// The folder in a sync directory a game's saves are synced to
+fn save_folder(key: &str, identifier: &SaveType) -> String {
+    match *identifier {
+        SaveType::GOG(id) => format!("gog_{}", id),
+        SaveType::Other(_) => key.to_string(),
+    }
+}
+/// Whether a label can be used for a game. Labels name folders in the sync directory, next to the ones of GOG games and the snapshot objects.
+fn valid_label(name: &str) -> bool {
+    !(name.is_empty()
+        || name.contains('/')
+        || name.starts_with('.')
+        || name.starts_with("gog_")
+        || name == "objects"
+        || name.chars().all(|x| x.is_ascii_digit()))
+}
+/// Finds what's wrong with the entries of a savedb. Returns the problems, and save paths that are missing on this machine.
+fn check_savedb(savedb: &SaveDB) -> (Vec<String>, Vec<String>) {
+    let mut problems = vec![];
+    let mut missing = vec![];
+    let mut paths: HashMap<PathBuf, &str> = HashMap::new();
+    let mut keys: Vec<&String> = savedb.saves.keys().collect();
+    keys.sort();
+    for key in keys {
+        let save_info = &savedb.saves[key];
+        match save_info.identifier {
+            SaveType::GOG(id) if id <= 0 => {
+                problems.push(format!("{}: {} is not a GOG product id", key, id))
             }
-            print!("Synced save files are more recent. Are you sure you want to proceed?(y/N)");
-            let mut answer = String::new();
-            io::stdout().flush().unwrap();
-            io::stdin().read_line(&mut answer).unwrap();
-            if answer.trim() == "y" || answer.trim() == "Y" {
-                println!("Proceeding as normal.");
-            } else {
-                println!("Sync aborted.");
-                return;
+            SaveType::GOG(id) if *key != format!("{}", id) => problems.push(format!(
+                "{}: should be keyed by its GOG product id {}",
+                key, id
+            )),
+            SaveType::Other(ref label) if !valid_label(label) => {
+                problems.push(format!("{}: {} can't be used as a label", key, label))
+            }
+            SaveType::Other(ref label) if key != label => {
+                problems.push(format!("{}: should be keyed by its label {}", key, label))
             }
+            _ => {}
+        }
+        if save_info.path.trim().is_empty() {
+            problems.push(format!("{}: has no save path", key));
+            continue;
+        }
+        let path = save_path(save_info);
+        if !path.is_absolute() {
+            problems.push(format!("{}: save path {} is relative", key, save_info.path));
+        } else if let Some(other) = paths.insert(path.clone(), key) {
+            problems.push(format!("{}: has the same save path as {}", key, other));
+        }
+        if !path.exists() {
+            missing.push(format!(
+                "{}: save path {} does not exist on this machine",
+                key,
+                path.display()
+            ));
+        }
+    }
+    (problems, missing)
+}
+/// Works out which game a sync command is for, from a label given with --name or the gameinfo file in its game directory
+fn synced_game(gog: &Gog, name: Option<String>, game_dir: Option<&PathBuf>) -> SyncedGame {
+    if let Some(name) = name {
+        // Labels name folders in the sync directory, next to the ones of GOG games and the snapshot objects
+        if !valid_label(&name) {
+            error!("{} can't be used as a label. Labels can't contain slashes, start with a dot or gog_, or be only digits.", name);
+            std::process::exit(64);
+        }
+        return SyncedGame {
+            key: name.clone(),
+            identifier: SaveType::Other(name.clone()),
+            title: name,
+        };
+    }
+    let game_dir = match game_dir {
+        Some(game_dir) => game_dir,
+        None => {
+            error!("Pass a game directory, or a label for the game with --name.");
+            std::process::exit(64);
+        }
+    };
+    info!("Opening gameinfo file");
+    let mut ginfo_string = String::new();
+    let read = File::open(game_dir.join("gameinfo"))
+        .and_then(|mut x| x.read_to_string(&mut ginfo_string));
+    if read.is_err() {
+        error!("Game directory or gameinfo file missing");
+        std::process::exit(64);
+    }
+    info!("Parsing gameinfo");
+    let gameinfo = GameInfo::parse(ginfo_string).expect("Couldn't parse gameinfo file");
+    info!("Fetching details about game from GOG");
+    match gog.get_products(FilterParams::from_one(Search(gameinfo.name.clone()))) {
+        Ok(ref details) if !details.is_empty() => SyncedGame {
+            key: format!("{}", details[0].id),
+            identifier: SaveType::GOG(details[0].id),
+            title: gameinfo.name,
+        },
+        _ => {
+            error!("Could not find a game named {}.", gameinfo.name);
+            std::process::exit(64);
         }
+    }
+}
+/// Looks up where a GOG game keeps its saves, if its game directory is known
+fn suggest_saves(game: &SyncedGame, game_dir: Option<&PathBuf>) -> Option<PathBuf> {
+    match (&game.identifier, game_dir) {
+        (&SaveType::GOG(id), Some(game_dir)) => locations::suggest(id, game_dir),
+        _ => None,
+    }
+}
+/// Loads the savedb of a sync folder, exiting if it can't be read
+fn load_savedb<P: AsRef<Path>>(path: P) -> SaveDB {
+    SaveDB::load(path.as_ref()).unwrap_or_else(|err| {
+        error!(
+            "Could not load savedb. Run sync db check for details. Error: {}",
+            err
+        );
+        std::process::exit(64);
+    })
+}
+/// Records where a game's saves are in a savedb, exiting if that fails
+fn store_save_info(path: &Path, key: String, save_info: SaveInfo) {
+    info!("Storing savedb");
+    let stored = SaveDB::update(path, |x| {
+        x.saves.insert(key, save_info);
+    });
+    if let Err(err) = stored {
+        error!("Could not store savedb. Error: {}", err);
+        std::process::exit(64);
+    }
+}
+/// Opens a sync location, downloading it first if it's a remote sync profile
+fn open_location(location: &str) -> remote::Mirror {
+    remote::open(location).unwrap_or_else(|err| {
+        error!("Could not open sync location {}. Error: {}", location, err);
+        std::process::exit(64);
+    })
+}
+/// Uploads the changes a command made to a remote sync location, with a description of them for its history
+fn finish(mut mirror: remote::Mirror, description: &str) {
+    match mirror.upload(description) {
+        Ok((0, 0)) => {}
+        Ok((uploaded, deleted)) => println!(
+            "Uploaded {} files to the sync location and deleted {}",
+            uploaded, deleted
+        ),
+        Err(err) => {
+            error!("Could not upload changes to the sync location. Error: {}", err);
+            std::process::exit(64);
+        }
+    }
+}
+/// Snapshots a game's saves in the sync folder after they've been pushed, then prunes snapshots the retention policy doesn't keep
+fn snapshot_pushed(sync_dir: &Path, folder: &str) {
+    let synced = sync_dir.join("saves").join(folder);
+    match snapshots::take(sync_dir, folder, &synced) {
+        Ok(Some(id)) => println!("Saved snapshot {}", id),
+        Ok(None) => info!("Saves unchanged since the last snapshot"),
+        Err(err) => {
+            warn!("Could not snapshot save files. Error: {}", err);
+            return;
+        }
+    }
+    let retention = confy::load::<Config>("wyvern", "wyvern")
+        .map(|x| x.snapshot_retention)
+        .unwrap_or_default();
+    match snapshots::prune(sync_dir, folder, &retention) {
+        Ok(0) => {}
+        Ok(removed) => info!("Removed {} old snapshots", removed),
+        Err(err) => warn!("Could not remove old snapshots. Error: {}", err),
+    }
+}
+/// The wine prefix of a windows game, either the one installed at `game_dir` or the installed GOG game
+fn game_prefix(identifier: &SaveType, game_dir: Option<&PathBuf>) -> Option<PathBuf> {
+    if let Some(launch) = game_dir.and_then(|x| LaunchConfig::load(x).ok()) {
+        return Some(launch.prefix.unwrap_or_else(wine::default_prefix));
+    }
+    match *identifier {
+        SaveType::GOG(id) => wine::find_prefix(id),
+        SaveType::Other(_) => None,
+    }
+}
+/// Where the saves of a game in the save database are on this machine
+pub fn save_path(save_info: &SaveInfo) -> PathBuf {
+    local_saves(save_info, None)
+}
+/// Where the saves of a game are on this machine. Saves inside a wine prefix are looked for in the prefix of the game installed at `game_dir`, or else of the installed GOG game or wine's default one.
+fn local_saves(save_info: &SaveInfo, game_dir: Option<&PathBuf>) -> PathBuf {
+    let path = paths::expand(&save_info.path)
+        .to_string_lossy()
+        .into_owned();
+    if !wine::in_prefix(&path) {
+        return PathBuf::from(path);
+    }
+    let prefix = game_prefix(&save_info.identifier, game_dir).unwrap_or_else(wine::default_prefix);
+    wine::expand(&path, &prefix)
+}
+/// How a game's save path is recorded in the save database. Saves inside its wine prefix are recorded relative to it, since prefixes and the usernames in them differ between machines.
+fn recorded_path(path: &Path, identifier: &SaveType, game_dir: Option<&PathBuf>) -> String {
+    game_prefix(identifier, game_dir)
+        .and_then(|prefix| wine::portable(path, &prefix))
+        .unwrap_or_else(|| paths::portable(path))
+}
+/// Pushes the saves of a game in the save database, then snapshots them. Returns false if syncing failed.
+pub fn push_saves(
+    sync_dir: &Path,
+    key: &str,
+    save_info: &SaveInfo,
+    resolution: &merge::Resolution,
+    options: &dirsync::Options,
+) -> bool {
+    let folder_name = save_folder(key, &save_info.identifier);
+    let dest_path = sync_dir.join("saves").join(&folder_name);
+    if sync(save_path(save_info), dest_path, false, resolution, options) {
+        snapshot_pushed(sync_dir, &folder_name);
+        true
     } else {
-        info!("No files synced to location currently");
+        false
     }
-    info!("Rsyncing files to location");
-    Command::new("rsync")
-        .arg(sync_from.to_str().unwrap().to_string() + "/")
-        .arg(
-            sync_to
-                .parent()
-                .unwrap()
-                .to_path_buf()
-                .to_str()
-                .unwrap()
-                .to_string()
-                + "/",
-        )
-        .arg("-a")
-        .arg("--force")
-        .output()
-        .unwrap();
+}
+/// Pulls a game's save files from its sync folder, or pushes them to it, resolving any files that changed on both sides since they were last synced. Returns false if syncing failed or was aborted.
+fn sync(
+    saves: PathBuf,
+    sync_folder: PathBuf,
+    pull: bool,
+    resolution: &merge::Resolution,
+    options: &dirsync::Options,
+) -> bool {
+    let sync_from = if pull {
+        storage::stored_path(&saves, &sync_folder)
+    } else {
+        saves.clone()
+    };
+    if !sync_from.exists() {
+        error!("Can't sync nonexistent files! There should be save files at {}, but there are not. Aborting.", sync_from.to_str().unwrap());
+        return false;
+    }
+    info!("Syncing files to location");
+    match merge::sync(&saves, &sync_folder, !pull, resolution, options) {
+        Ok(Some(summary)) => {
+            println!("{}", summary);
+            true
+        }
+        Ok(None) => {
+            println!("Sync aborted.");
+            false
+        }
+        Err(err) => {
+            error!("Could not sync save files. Error: {}", err);
+            false
+        }
+    }
+}
+/// Finds a game's GOG id, from its launch configuration if it's a windows game or by searching for the name in its gameinfo otherwise
+pub fn game_id(gog: &Gog, game: &InstalledGame) -> Option<i64> {
+    if let Some(id) = LaunchConfig::load(&game.path)
+        .ok()
+        .and_then(|x| x.game_id)
+        .and_then(|x| x.parse().ok())
+    {
+        return Some(id);
+    }
+    let mut ginfo_string = String::new();
+    File::open(game.path.join("gameinfo"))
+        .ok()?
+        .read_to_string(&mut ginfo_string)
+        .ok()?;
+    let gameinfo = GameInfo::parse(ginfo_string).ok()?;
+    let details = gog
+        .get_products(FilterParams::from_one(Search(gameinfo.name)))
+        .ok()?;
+    details.first().map(|x| x.id)
+}
+/// Pulls a game's saves before it's launched and pushes them after it exits. Saves that were also changed elsewhere in the meantime are treated as conflicts.
+pub fn around_launch(
+    gog: &Gog,
+    sync_saves: Option<String>,
+    game: &InstalledGame,
+    launch: impl FnOnce() -> Result<(), String>,
+) -> Result<(), String> {
+    let sync_saves = match sync_saves {
+        Some(sync_saves) => sync_saves,
+        None => return Err("You have not configured a directory to sync your saves to. Edit ~/.config/wyvern/wyvern.toml to get started!".to_string()),
+    };
+    let id = game_id(gog, game)
+        .ok_or_else(|| format!("Could not find {} on GOG to sync its saves", game.name))?;
+    let mut mirror = remote::open(&sync_saves).map_err(|err| err.to_string())?;
+    let sync_saves = mirror.path.clone();
+    info!("Loading savedb");
+    let savedb_path = sync_saves.join("savedb.json");
+    let save_db = SaveDB::load(&savedb_path).map_err(|err| err.to_string())?;
+    let local = match save_db.saves.get(&format!("{}", id)) {
+        Some(save_info) => local_saves(save_info, Some(&game.path)),
+        None => match locations::suggest(id, &game.path) {
+            Some(found) => {
+                info!("Inserting saveinfo into savedb");
+                let save_info = SaveInfo {
+                    path: recorded_path(&found, &SaveType::GOG(id), Some(&game.path)),
+                    identifier: SaveType::GOG(id),
+                    title: Some(game.name.clone()),
+                };
+                SaveDB::update(&savedb_path, |x| {
+                    x.saves.insert(format!("{}", id), save_info);
+                })
+                .map_err(|err| err.to_string())?;
+                found
+            }
+            None => {
+                warn!("This game's saves have not been configured to be synced yet. Launching without syncing.");
+                return launch();
+            }
+        },
+    };
+    let synced = sync_saves.join("saves").join(format!("gog_{}", id));
+    let options = dirsync::Options::configured(false, false);
+    let resolution = merge::Resolution::default();
+    println!("Pulling save files");
+    match merge::sync(&local, &synced, false, &resolution, &options) {
+        Ok(Some(summary)) => println!("{}", summary),
+        Ok(None) => return Err("Sync aborted. Not launching.".to_string()),
+        Err(err) => return Err(err.to_string()),
+    }
+    let launched = launch();
+    if !local.exists() {
+        warn!("No save files at {}. Nothing to push.", local.display());
+        return launched;
+    }
+    // Anything pushed from elsewhere while the game was running gets caught as a conflict here
+    mirror.download().map_err(|err| err.to_string())?;
+    println!("Pushing save files");
+    match merge::sync(&local, &synced, true, &resolution, &options) {
+        Ok(Some(summary)) => {
+            println!("{}", summary);
+            snapshot_pushed(&sync_saves, &format!("gog_{}", id));
+        }
+        Ok(None) => warn!("Sync aborted. Local save files were not pushed."),
+        Err(err) => return Err(err.to_string()),
+    }
+    mirror
+        .upload(&format!("Push saves of {} after playing", game.name))
+        .map_err(|err| err.to_string())?;
+    launched
 }
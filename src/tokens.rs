@@ -0,0 +1,396 @@
+/// Where GOG logins are kept: in the config, or in a store encrypted with a passphrase when token_storage.encrypt is set.
+/// The store stays unlocked for a while after its passphrase is entered, so that not every command asks for it.
+use args::TokenStore;
+use args::TokenStore::*;
+use config::*;
+use crypto::Key;
+use dialoguer::Password;
+use gog::token::Token;
+use hashing::{from_hex, to_hex};
+use prompt;
+use serde_json;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+/// Environment variable the token store passphrase is read from before asking for it
+pub const PASSPHRASE_VAR: &str = "WYVERN_TOKEN_PASSPHRASE";
+/// Encrypted into every store, to tell whether a passphrase is right
+const CHECK: &[u8] = b"wyvern-tokens";
+lazy_static! {
+    /// The store is only unlocked once per run
+    static ref KEY: Mutex<Option<Key>> = Mutex::new(None);
+}
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(default)]
+pub struct Settings {
+    /// Keep logins in an encrypted store instead of the config
+    pub encrypt: bool,
+    /// How many minutes the store stays unlocked after its passphrase is entered. 0 asks for it on every command.
+    pub session_minutes: u64,
+}
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            encrypt: false,
+            session_minutes: 15,
+        }
+    }
+}
+/// The encrypted store. Which accounts have a login is readable without the passphrase, so that they can be listed and removed without it.
+#[derive(Serialize, Deserialize, Default)]
+struct Store {
+    salt: String,
+    check: String,
+    #[serde(default)]
+    account: Option<String>,
+    #[serde(default)]
+    profiles: BTreeMap<String, String>,
+}
+impl Store {
+    fn path() -> PathBuf {
+        dirs::data_dir()
+            .unwrap_or_else(|| dirs::home_dir().unwrap().join(".local/share"))
+            .join("wyvern/tokens.json")
+    }
+    fn load() -> Result<Option<Store>, io::Error> {
+        match fs::read_to_string(Store::path()) {
+            Ok(read) => serde_json::from_str(&read)
+                .map(Some)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)),
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+    /// Starts an empty store with a new passphrase
+    fn create(settings: &Settings) -> Result<Store, io::Error> {
+        let passphrase = match env::var(PASSPHRASE_VAR) {
+            Ok(passphrase) => passphrase,
+            Err(_) => {
+                if !prompt::interactive() {
+                    prompt::needs_input(
+                        "A passphrase for the token store",
+                        &format!("Set it in {}", PASSPHRASE_VAR),
+                    );
+                }
+                println!("Choose a passphrase to encrypt your GOG logins with.");
+                Password::new()
+                    .with_prompt("Token store passphrase")
+                    .with_confirmation("Repeat passphrase", "The passphrases don't match")
+                    .interact()
+                    .map_err(|err| io::Error::other(err.to_string()))?
+            }
+        };
+        let key = Key::generate(&passphrase)?;
+        let store = Store {
+            salt: to_hex(&key.salt),
+            check: to_hex(&key.encrypt(CHECK)?),
+            ..Store::default()
+        };
+        session::save(&key, settings);
+        *KEY.lock().unwrap() = Some(key);
+        Ok(store)
+    }
+    /// Writes the store so that only its owner can read it
+    fn store(&self) -> Result<(), io::Error> {
+        let path = Store::path();
+        let temp = path.with_extension("json.tmp");
+        fs::create_dir_all(path.parent().unwrap())?;
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&temp)?;
+        file.write_all(serde_json::to_string_pretty(self)?.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(temp, path)
+    }
+    fn is_key(&self, key: &Key) -> bool {
+        from_hex(&self.salt).as_deref() == Some(&key.salt[..])
+            && from_hex(&self.check).and_then(|x| key.decrypt(&x)) == Some(CHECK.to_vec())
+    }
+    /// The key of the store, from this run, the session or the passphrase
+    fn unlock(&self, settings: &Settings) -> Result<Key, io::Error> {
+        let mut cached = KEY.lock().unwrap();
+        if let Some(ref key) = *cached {
+            if self.is_key(key) {
+                return Ok(key.clone());
+            }
+        }
+        let salt = from_hex(&self.salt)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Invalid salt"))?;
+        let key = match session::load(settings).filter(|x| self.is_key(x)) {
+            Some(key) => key,
+            None => {
+                let passphrase = match env::var(PASSPHRASE_VAR) {
+                    Ok(passphrase) => passphrase,
+                    Err(_) if !prompt::interactive() => prompt::needs_input(
+                        "The token store passphrase",
+                        &format!("Set it in {}", PASSPHRASE_VAR),
+                    ),
+                    Err(_) => Password::new()
+                        .with_prompt("Token store passphrase")
+                        .interact()
+                        .map_err(|err| io::Error::other(err.to_string()))?,
+                };
+                let key = Key::derive(&passphrase, &salt)?;
+                if !self.is_key(&key) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Wrong passphrase for the token store",
+                    ));
+                }
+                key
+            }
+        };
+        session::save(&key, settings);
+        *cached = Some(key.clone());
+        Ok(key)
+    }
+    fn sealed(&self, profile: Option<&str>) -> Option<&String> {
+        match profile {
+            Some(name) => self.profiles.get(name),
+            None => self.account.as_ref(),
+        }
+    }
+    fn seal(&mut self, key: &Key, profile: Option<&str>, token: &Token) -> Result<(), io::Error> {
+        let sealed = to_hex(&key.encrypt(serde_json::to_string(token)?.as_bytes())?);
+        match profile {
+            Some(name) => {
+                self.profiles.insert(name.to_string(), sealed);
+            }
+            None => self.account = Some(sealed),
+        }
+        Ok(())
+    }
+    fn open(&self, key: &Key, profile: Option<&str>) -> Result<Option<Token>, io::Error> {
+        let sealed = match self.sealed(profile) {
+            Some(sealed) => sealed,
+            None => return Ok(None),
+        };
+        let opened = from_hex(sealed)
+            .and_then(|x| key.decrypt(&x))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Could not decrypt login"))?;
+        serde_json::from_slice(&opened)
+            .map(Some)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+}
+/// The derived key cached in the runtime directory, which only its user can read and which is cleared on logout
+mod session {
+    use super::*;
+    fn path() -> Option<PathBuf> {
+        dirs::runtime_dir().map(|x| x.join("wyvern/token-key"))
+    }
+    pub fn load(settings: &Settings) -> Option<Key> {
+        let path = path()?;
+        let modified = fs::metadata(&path).and_then(|x| x.modified()).ok()?;
+        let age = SystemTime::now()
+            .duration_since(modified)
+            .unwrap_or_default();
+        if age > Duration::from_secs(settings.session_minutes * 60) {
+            let _ = fs::remove_file(&path);
+            return None;
+        }
+        Key::from_bytes(&from_hex(fs::read_to_string(&path).ok()?.trim())?)
+    }
+    /// Caches a key, starting its expiry over
+    pub fn save(key: &Key, settings: &Settings) {
+        let path = match path() {
+            Some(path) => path,
+            None => return,
+        };
+        if settings.session_minutes == 0 {
+            return;
+        }
+        let written = fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(path.parent().unwrap())
+            .and_then(|_| {
+                OpenOptions::new()
+                    .write(true)
+                    .create(true)
+                    .truncate(true)
+                    .mode(0o600)
+                    .open(&path)
+            })
+            .and_then(|mut file| file.write_all(to_hex(&key.to_bytes()).as_bytes()));
+        if let Err(err) = written {
+            warn!("Could not keep the token store unlocked. Error: {}", err);
+        }
+    }
+    /// Forgets the cached key. Returns whether there was one.
+    pub fn clear() -> Result<bool, io::Error> {
+        match path().map(fs::remove_file) {
+            Some(Ok(())) => Ok(true),
+            Some(Err(ref err)) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+            Some(Err(err)) => Err(err),
+            None => Ok(false),
+        }
+    }
+}
+/// Logins are kept under profiles that exist, and otherwise under the account at the top of the config, like Config::set_token does
+fn profile<'a>(config: &Config, profile: Option<&'a str>) -> Option<&'a str> {
+    profile.filter(|x| config.profiles.contains_key(*x))
+}
+/// The stored login of an account
+pub fn get(config: &Config, profile: Option<&str>) -> Result<Option<Token>, io::Error> {
+    if !config.token_storage.encrypt {
+        return Ok(config.account(profile).ok().and_then(|x| x.token));
+    }
+    let profile = self::profile(config, profile);
+    match Store::load()? {
+        Some(ref store) if store.sealed(profile).is_some() => {
+            store.open(&store.unlock(&config.token_storage)?, profile)
+        }
+        _ => Ok(None),
+    }
+}
+/// Stores the login of an account, or removes it
+pub fn set(
+    config: &mut Config,
+    profile: Option<&str>,
+    token: Option<Token>,
+) -> Result<(), io::Error> {
+    if !config.token_storage.encrypt {
+        config.set_token(profile, token);
+        return Ok(());
+    }
+    config.set_token(profile, None);
+    let profile = self::profile(config, profile);
+    let mut store = match (Store::load()?, token.is_some()) {
+        (Some(store), _) => store,
+        (None, true) => Store::create(&config.token_storage)?,
+        (None, false) => return Ok(()),
+    };
+    match token {
+        Some(token) => {
+            let key = store.unlock(&config.token_storage)?;
+            store.seal(&key, profile, &token)?;
+        }
+        None => match profile {
+            Some(name) => {
+                store.profiles.remove(name);
+            }
+            None => store.account = None,
+        },
+    }
+    store.store()
+}
+/// Whether an account has a login stored, without unlocking the store
+pub fn has_token(config: &Config, profile: Option<&str>) -> bool {
+    if !config.token_storage.encrypt {
+        return config
+            .account(profile)
+            .map(|x| x.token.is_some())
+            .unwrap_or(false);
+    }
+    let profile = self::profile(config, profile);
+    match Store::load() {
+        Ok(Some(store)) => store.sealed(profile).is_some(),
+        _ => false,
+    }
+}
+/// Moves logins that are still in the config into the encrypted store, if logins are to be encrypted. Returns how many were moved.
+pub fn migrate(config: &mut Config) -> Result<usize, io::Error> {
+    if !config.token_storage.encrypt {
+        return Ok(0);
+    }
+    let mut plain: Vec<(Option<String>, Token)> = vec![];
+    if let Some(token) = config.token.clone() {
+        plain.push((None, token));
+    }
+    for (name, account) in config.profiles.iter() {
+        if let Some(ref token) = account.token {
+            plain.push((Some(name.clone()), token.clone()));
+        }
+    }
+    if plain.is_empty() {
+        return Ok(0);
+    }
+    let mut store = match Store::load()? {
+        Some(store) => store,
+        None => Store::create(&config.token_storage)?,
+    };
+    let key = store.unlock(&config.token_storage)?;
+    for (profile, token) in plain.iter() {
+        store.seal(&key, profile.as_deref(), token)?;
+    }
+    store.store()?;
+    for (profile, _) in plain.iter() {
+        config.set_token(profile.as_deref(), None);
+    }
+    Ok(plain.len())
+}
+/// Moves the logins in the encrypted store back into the config, saving it before the store is removed. Returns how many were moved.
+fn export(config: &mut Config) -> Result<usize, io::Error> {
+    config.token_storage.encrypt = false;
+    let store = match Store::load()? {
+        Some(store) => store,
+        None => return Ok(0),
+    };
+    let key = store.unlock(&config.token_storage)?;
+    let mut moved = 0;
+    let names: Vec<Option<String>> = Some(None)
+        .into_iter()
+        .chain(store.profiles.keys().map(|x| Some(x.clone())))
+        .collect();
+    for name in names {
+        let profile = name.as_deref();
+        if profile.is_some() && self::profile(config, profile).is_none() {
+            continue;
+        }
+        if let Some(token) = store.open(&key, profile)? {
+            config.set_token(profile, Some(token));
+            moved += 1;
+        }
+    }
+    // The store is all that's left of the logins until the config holding them is saved
+    confy::store("wyvern", "wyvern", &*config).map_err(io::Error::other)?;
+    fs::remove_file(Store::path())?;
+    session::clear()?;
+    Ok(moved)
+}
+pub fn parse_args(config: &mut Config, args: TokenStore) {
+    match args {
+        Encrypt => {
+            config.token_storage.encrypt = true;
+            match migrate(config) {
+                Ok(moved) => println!(
+                    "Logins are now encrypted. Moved {} logins out of the config.",
+                    moved
+                ),
+                Err(err) => {
+                    error!("Could not encrypt logins. Error: {}", err);
+                    std::process::exit(64);
+                }
+            }
+        }
+        Decrypt => match export(config) {
+            Ok(moved) => {
+                println!(
+                    "Logins are now kept in the config. Moved {} logins into it.",
+                    moved
+                );
+            }
+            Err(err) => {
+                error!("Could not decrypt logins. Error: {}", err);
+                std::process::exit(64);
+            }
+        },
+        Lock => match session::clear() {
+            Ok(true) => println!("Locked the token store"),
+            Ok(false) => println!("The token store wasn't unlocked"),
+            Err(err) => {
+                error!("Could not lock the token store. Error: {}", err);
+                std::process::exit(64);
+            }
+        },
+    }
+}
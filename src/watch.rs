@@ -0,0 +1,256 @@
+/// Watches the save paths in a save database and pushes a game's saves shortly after it writes them, for `wyvern sync watch`
+use config::*;
+use dirsync;
+use merge;
+use notify::{self, Event, EventKind, RecursiveMode, Watcher};
+use remote;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, Instant};
+use sync;
+/// How often save paths that didn't exist yet are checked for again
+const RESCAN: Duration = Duration::from_secs(60);
+/// How long to wait before retrying a failed upload, doubling after each failure up to MAX_BACKOFF
+const BACKOFF: Duration = Duration::from_secs(30);
+const MAX_BACKOFF: Duration = Duration::from_secs(30 * 60);
+/// Pushed saves that couldn't be uploaded yet. The sync location isn't downloaded until they are, since that would replace them with the older remote ones.
+struct Unuploaded {
+    games: Vec<String>,
+    backoff: Duration,
+    retry: Instant,
+}
+/// What's being watched, and which game each save path belongs to
+struct Watched {
+    watcher: notify::RecommendedWatcher,
+    targets: HashSet<PathBuf>,
+    saves: HashMap<String, PathBuf>,
+}
+impl Watched {
+    /// Starts watching the save paths in the save database that exist now and weren't watched yet
+    fn scan(&mut self, sync_dir: &Path) {
+        let savedb = match SaveDB::load(sync_dir.join("savedb.json")) {
+            Ok(savedb) => savedb,
+            Err(err) => {
+                warn!("Could not load savedb. Error: {}", err);
+                return;
+            }
+        };
+        self.saves.clear();
+        for (key, info) in savedb.saves.iter() {
+            let path = sync::save_path(info);
+            // Games often replace a save file rather than write to it, which drops a watch on the file itself
+            let (target, mode) = if path.is_dir() {
+                (path.clone(), RecursiveMode::Recursive)
+            } else {
+                match path.parent() {
+                    Some(parent) => (parent.to_path_buf(), RecursiveMode::NonRecursive),
+                    None => continue,
+                }
+            };
+            self.saves.insert(key.clone(), path);
+            if !target.exists() || self.targets.contains(&target) {
+                continue;
+            }
+            match self.watcher.watch(&target, mode) {
+                Ok(()) => {
+                    info!("Watching {} for saves of {}", target.display(), key);
+                    self.targets.insert(target);
+                }
+                Err(err) => warn!("Could not watch {}. Error: {}", target.display(), err),
+            }
+        }
+    }
+    /// Games whose saves an event touched
+    fn games(&mut self, event: &Event) -> Vec<String> {
+        if let EventKind::Remove(_) = event.kind {
+            // Watches go away with what they watch, so that it's watched again once it's back
+            for path in event.paths.iter() {
+                self.targets.remove(path);
+            }
+        }
+        self.saves
+            .iter()
+            .filter(|&(_, save)| event.paths.iter().any(|x| x.starts_with(save)))
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+}
+/// Watches the saves in the save database of a sync location until stopped, pushing each game's saves once they've been left alone for `delay`
+pub fn watch(
+    mut mirror: remote::Mirror,
+    delay: Duration,
+    resolution: &merge::Resolution,
+    options: &dirsync::Options,
+) -> Result<(), notify::Error> {
+    let (tx, rx) = mpsc::channel();
+    let mut watched = Watched {
+        watcher: notify::recommended_watcher(tx)?,
+        targets: HashSet::new(),
+        saves: HashMap::new(),
+    };
+    watched.scan(&mirror.path);
+    println!(
+        "Watching the saves of {} games for changes",
+        watched.saves.len()
+    );
+    let mut pending: HashMap<String, Instant> = HashMap::new();
+    let mut scanned = Instant::now();
+    let mut unuploaded: Option<Unuploaded> = None;
+    loop {
+        match rx.recv_timeout(Duration::from_secs(1)) {
+            Ok(Ok(event)) => {
+                if event.kind.is_access() {
+                    continue;
+                }
+                for key in watched.games(&event) {
+                    pending.insert(key, Instant::now());
+                }
+            }
+            Ok(Err(err)) => warn!("Error while watching save files: {}", err),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+        let ready: Vec<String> = pending
+            .iter()
+            .filter(|&(_, changed)| changed.elapsed() >= delay)
+            .map(|(key, _)| key.clone())
+            .collect();
+        if !ready.is_empty() {
+            for key in ready.iter() {
+                pending.remove(key);
+            }
+            unuploaded = push(&mut mirror, &ready, unuploaded, resolution, options);
+            watched.scan(&mirror.path);
+            scanned = Instant::now();
+        } else if unuploaded
+            .as_ref()
+            .map(|x| x.retry <= Instant::now())
+            .unwrap_or(false)
+        {
+            let retrying = unuploaded.take().unwrap();
+            unuploaded = upload(&mut mirror, retrying.games, retrying.backoff);
+        } else if scanned.elapsed() >= RESCAN {
+            watched.scan(&mirror.path);
+            scanned = Instant::now();
+        }
+    }
+}
+/// Pushes and snapshots the saves of some games, then uploads them along with earlier pushes that couldn't be uploaded if the sync location is remote.
+/// Returns what still couldn't be uploaded.
+fn push(
+    mirror: &mut remote::Mirror,
+    keys: &[String],
+    unuploaded: Option<Unuploaded>,
+    resolution: &merge::Resolution,
+    options: &dirsync::Options,
+) -> Option<Unuploaded> {
+    if unuploaded.is_none() {
+        if let Err(err) = mirror.download() {
+            warn!("Could not download the sync location. Error: {}", err);
+            return None;
+        }
+    }
+    let savedb = match SaveDB::load(mirror.path.join("savedb.json")) {
+        Ok(savedb) => savedb,
+        Err(err) => {
+            warn!("Could not load savedb. Error: {}", err);
+            return unuploaded;
+        }
+    };
+    let (mut pushed, backoff) = match unuploaded {
+        Some(unuploaded) => (unuploaded.games, unuploaded.backoff),
+        None => (vec![], BACKOFF),
+    };
+    for key in keys {
+        if let Some(info) = savedb.saves.get(key) {
+            println!("Saves of {} changed. Pushing them.", key);
+            if sync::push_saves(&mirror.path, key, info, resolution, options) {
+                if !pushed.contains(key) {
+                    pushed.push(key.clone());
+                }
+            } else {
+                warn!("Could not push the saves of {}", key);
+            }
+        }
+    }
+    if pushed.is_empty() {
+        return None;
+    }
+    upload(mirror, pushed, backoff)
+}
+/// Uploads the pushed saves of some games. Returns them to retry later if that failed.
+fn upload(
+    mirror: &mut remote::Mirror,
+    games: Vec<String>,
+    backoff: Duration,
+) -> Option<Unuploaded> {
+    match mirror.upload(&format!("Push saves of {}", games.join(", "))) {
+        Ok((0, 0)) => None,
+        Ok((uploaded, deleted)) => {
+            println!(
+                "Uploaded {} files to the sync location and deleted {}",
+                uploaded, deleted
+            );
+            None
+        }
+        Err(err) => {
+            warn!(
+                "Could not upload changes to the sync location. Retrying in {} seconds. Error: {}",
+                backoff.as_secs(),
+                err
+            );
+            Some(Unuploaded {
+                games,
+                backoff: (backoff * 2).min(MAX_BACKOFF),
+                retry: Instant::now() + backoff,
+            })
+        }
+    }
+}
+/// Quotes an argument for a systemd unit's command line
+fn quote(arg: &str) -> String {
+    let escaped = arg.replace('%', "%%").replace('$', "$$");
+    if escaped.contains(|x: char| x.is_whitespace() || x == '"' || x == '\\' || x == '\'') {
+        format!("\"{}\"", escaped.replace('\\', "\\\\").replace('"', "\\\""))
+    } else {
+        escaped
+    }
+}
+/// Writes a systemd user unit running `wyvern sync watch` with the arguments it was called with, so that it starts on login. Returns where it was written.
+pub fn install_service() -> Result<PathBuf, io::Error> {
+    let exe = env::current_exe()?;
+    let args: Vec<String> = env::args()
+        .skip(1)
+        .filter(|x| x != "--install-service")
+        .collect();
+    let command: Vec<String> = Some(exe.display().to_string())
+        .into_iter()
+        .chain(args)
+        .map(|x| quote(&x))
+        .collect();
+    let unit = format!(
+        "[Unit]\n\
+         Description=Push game saves with wyvern when they change\n\
+         \n\
+         [Service]\n\
+         WorkingDirectory={}\n\
+         ExecStart={}\n\
+         Restart=on-failure\n\
+         \n\
+         [Install]\n\
+         WantedBy=default.target\n",
+        // Relative paths in the arguments stay relative to where it was installed from
+        env::current_dir()?.display(),
+        command.join(" ")
+    );
+    let path = dirs::config_dir()
+        .unwrap_or_else(|| dirs::home_dir().unwrap().join(".config"))
+        .join("systemd/user/wyvern-watch.service");
+    fs::create_dir_all(path.parent().unwrap())?;
+    fs::write(&path, unit)?;
+    Ok(path)
+}
@@ -0,0 +1,371 @@
+/// Sets up installed windows games to run under wine: finding the main executable from GOG's goggame-*.info files, creating a dedicated prefix and recording how to launch the game.
+use config::*;
+use serde_json;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+const DEFAULT_WINE: &str = "wine";
+/// The parts of a goggame-<id>.info file that say how to start the game
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct GogGameInfo {
+    game_id: String,
+    root_game_id: Option<String>,
+    name: Option<String>,
+    #[serde(default)]
+    play_tasks: Vec<PlayTask>,
+}
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct PlayTask {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    is_primary: bool,
+    category: Option<String>,
+    path: Option<String>,
+    arguments: Option<String>,
+    working_dir: Option<String>,
+}
+impl GogGameInfo {
+    /// DLCs ship their own info files, which point back at the base game through rootGameId
+    fn is_base_game(&self) -> bool {
+        self.root_game_id
+            .as_ref()
+            .map(|root| root == &self.game_id)
+            .unwrap_or(true)
+    }
+    fn main_task(&self) -> Option<&PlayTask> {
+        let runnable = |x: &&PlayTask| x.kind == "FileTask" && x.path.is_some();
+        self.play_tasks
+            .iter()
+            .filter(runnable)
+            .find(|x| x.is_primary)
+            .or_else(|| {
+                self.play_tasks
+                    .iter()
+                    .filter(runnable)
+                    .find(|x| x.category.as_ref().map(|c| c == "game").unwrap_or(true))
+            })
+    }
+}
+/// The wine binary to use: the one given on the command line, then the account's, then plain wine
+pub fn wine_binary(wine: Option<String>, account: &Account) -> String {
+    wine.or_else(|| account.wine.clone())
+        .unwrap_or_else(|| DEFAULT_WINE.to_string())
+}
+/// Where a game's dedicated prefix lives, keyed by GOG's game id when it's known
+pub fn prefix_path(key: &str) -> PathBuf {
+    dirs::data_dir()
+        .unwrap_or_else(|| dirs::home_dir().unwrap().join(".local/share"))
+        .join("wyvern/prefixes")
+        .join(key)
+}
+/// The prefix wine uses when a game has no dedicated one
+pub fn default_prefix() -> PathBuf {
+    env::var_os("WINEPREFIX")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| dirs::home_dir().unwrap().join(".wine"))
+}
+/// The prefix an installed windows game runs in, found by its GOG id
+pub fn find_prefix(id: i64) -> Option<PathBuf> {
+    let id = format!("{}", id);
+    let registry = InstallRegistry::load().unwrap_or_default();
+    for game in registry.games.iter().filter(|x| x.windows) {
+        if let Ok(launch) = LaunchConfig::load(&game.path) {
+            if launch.game_id.as_ref() == Some(&id) {
+                return Some(launch.prefix.unwrap_or_else(default_prefix));
+            }
+        }
+    }
+    Some(prefix_path(&id)).filter(|x| x.exists())
+}
+/// The windows user profile in a prefix. Its name is the username of whoever created the prefix, which differs between machines.
+fn user_profile(prefix: &Path) -> PathBuf {
+    let users = prefix.join("drive_c/users");
+    let user = env::var("USER").unwrap_or_default();
+    if !user.is_empty() && users.join(&user).is_dir() {
+        return users.join(user);
+    }
+    let found = fs::read_dir(&users).ok().and_then(|entries| {
+        entries
+            .filter_map(|x| x.ok())
+            .map(|x| x.path())
+            .find(|x| x.is_dir() && x.file_name().is_some_and(|x| x != "Public"))
+    });
+    found.unwrap_or_else(|| users.join(user))
+}
+/// Windows variables save paths can use, and where they are in a user profile. Older wine versions name them differently.
+const FOLDERS: &[(&str, &[&str])] = &[
+    ("%APPDATA%", &["AppData/Roaming", "Application Data"]),
+    (
+        "%LOCALAPPDATA%",
+        &["AppData/Local", "Local Settings/Application Data"],
+    ),
+    ("%DOCUMENTS%", &["Documents", "My Documents"]),
+    ("%USERPROFILE%", &[""]),
+];
+fn folder(profile: &Path, names: &[&str]) -> PathBuf {
+    names
+        .iter()
+        .map(|x| profile.join(x))
+        .find(|x| x.exists())
+        .unwrap_or_else(|| profile.join(names[0]))
+}
+/// Whether a save path is inside a wine prefix, so it needs one to be expanded
+pub fn in_prefix(path: &str) -> bool {
+    path.contains("<prefix>") || FOLDERS.iter().any(|&(variable, _)| path.contains(variable))
+}
+/// Expands <prefix> and windows variables in a save path against a prefix. Backslashes after windows variables are taken as separators.
+pub fn expand(path: &str, prefix: &Path) -> PathBuf {
+    let profile = user_profile(prefix);
+    let mut expanded = path.replace("<prefix>", &prefix.display().to_string());
+    let mut windows = false;
+    for &(variable, names) in FOLDERS {
+        if expanded.contains(variable) {
+            windows = true;
+            let folder = folder(&profile, names);
+            expanded = expanded.replace(variable, folder.to_str().unwrap().trim_end_matches('/'));
+        }
+    }
+    if windows {
+        expanded = expanded.replace('\\', "/");
+    }
+    PathBuf::from(expanded)
+}
+/// Writes a path inside a prefix with windows variables or relative to the prefix, so that it works on machines where the prefix or username differ
+pub fn portable(path: &Path, prefix: &Path) -> Option<String> {
+    let under = |variable: &str, rest: &Path| {
+        if rest.as_os_str().is_empty() {
+            variable.to_string()
+        } else {
+            format!("{}/{}", variable, rest.display())
+        }
+    };
+    let profile = user_profile(prefix);
+    for &(variable, names) in FOLDERS {
+        if let Ok(rest) = path.strip_prefix(folder(&profile, names)) {
+            return Some(under(variable, rest));
+        }
+    }
+    path.strip_prefix(prefix)
+        .ok()
+        .map(|rest| under("<prefix>", rest))
+}
+/// GOG info paths use windows separators
+fn native_path(path: &str) -> PathBuf {
+    path.split(['\\', '/'])
+        .filter(|x| !x.is_empty())
+        .collect()
+}
+/// Finds the game's main executable from the goggame-*.info files in its install directory
+pub fn detect_launch(game_dir: &Path, name: &str, wine: String) -> Option<LaunchConfig> {
+    let mut infos: Vec<GogGameInfo> = fs::read_dir(game_dir)
+        .ok()?
+        .filter_map(|x| x.ok())
+        .filter(|x| {
+            let file_name = x.file_name().to_string_lossy().to_string();
+            file_name.starts_with("goggame-") && file_name.ends_with(".info")
+        })
+        .filter_map(|x| {
+            let read = fs::read_to_string(x.path()).ok()?;
+            match serde_json::from_str(&read) {
+                Ok(info) => Some(info),
+                Err(err) => {
+                    warn!("Could not parse {}. Error: {}", x.path().display(), err);
+                    None
+                }
+            }
+        })
+        .collect();
+    infos.sort_by_key(|x| !x.is_base_game());
+    infos.iter().find_map(|info| {
+        let task = info.main_task()?;
+        Some(LaunchConfig {
+            name: info.name.clone().unwrap_or_else(|| name.to_string()),
+            game_id: Some(info.game_id.clone()),
+            wine: wine.clone(),
+            prefix: None,
+            exe: native_path(task.path.as_ref()?),
+            arguments: task.arguments.clone().filter(|x| !x.trim().is_empty()),
+            working_dir: task
+                .working_dir
+                .as_ref()
+                .map(|x| native_path(x))
+                .filter(|x| !x.as_os_str().is_empty()),
+        })
+    })
+}
+/// Creates and initializes a wine prefix
+pub fn create_prefix(wine: &str, prefix: &Path) -> Result<(), io::Error> {
+    fs::create_dir_all(prefix)?;
+    let output = Command::new(wine)
+        .arg("wineboot")
+        .arg("--init")
+        .env("WINEPREFIX", prefix)
+        .env("WINEDEBUG", "-all")
+        .output()?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!(
+            "wineboot failed. Stderr: {}",
+            String::from_utf8_lossy(&output.stderr)
+        )))
+    }
+}
+/// Detects how to start a freshly installed windows game, optionally creates a prefix for it, and records the result in the install directory
+pub fn setup(game_dir: &Path, name: &str, wine: Option<String>, prefix: bool) {
+    let wine = wine.unwrap_or_else(|| DEFAULT_WINE.to_string());
+    info!("Detecting game executable");
+    let mut launch = match detect_launch(game_dir, name, wine) {
+        Some(launch) => launch,
+        None => {
+            warn!("Could not find the game's executable in its goggame info files. Not recording how to launch it.");
+            return;
+        }
+    };
+    info!("Found executable {}", launch.exe.display());
+    if prefix {
+        let key = launch.game_id.clone().unwrap_or_else(|| name.to_string());
+        let prefix_dir = prefix_path(&key);
+        println!("Creating wine prefix at {}", prefix_dir.display());
+        if let Err(err) = create_prefix(&launch.wine, &prefix_dir) {
+            error!("Could not create wine prefix. Error: {}", err);
+        } else {
+            launch.prefix = Some(prefix_dir);
+        }
+    }
+    match launch.store(game_dir) {
+        Ok(_) => println!("Recorded launch configuration for {}", launch.name),
+        Err(err) => error!("Could not record launch configuration. Error: {}", err),
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    /// A prefix whose user profile has the folders newer wine versions create
+    fn prefix(dir: &Path) -> (PathBuf, PathBuf) {
+        let prefix = dir.join("prefix");
+        let profile = prefix.join("drive_c/users/player");
+        for folder in ["AppData/Roaming", "AppData/Local", "Documents"].iter() {
+            fs::create_dir_all(profile.join(folder)).unwrap();
+        }
+        fs::create_dir_all(prefix.join("drive_c/users/Public")).unwrap();
+        (prefix, profile)
+    }
+    #[test]
+    fn expands_windows_variables() {
+        let dir = tempfile::tempdir().unwrap();
+        let (prefix, profile) = prefix(dir.path());
+        assert_eq!(
+            expand("%APPDATA%/StardewValley/Saves", &prefix),
+            profile.join("AppData/Roaming/StardewValley/Saves")
+        );
+        assert_eq!(
+            expand("%LOCALAPPDATA%\\Larian Studios", &prefix),
+            profile.join("AppData/Local/Larian Studios")
+        );
+        assert_eq!(
+            expand("%DOCUMENTS%/My Games", &prefix),
+            profile.join("Documents/My Games")
+        );
+        assert_eq!(
+            expand("%USERPROFILE%/Saved Games", &prefix),
+            profile.join("Saved Games")
+        );
+        assert_eq!(
+            expand("<prefix>/drive_c/Games/saves", &prefix),
+            prefix.join("drive_c/Games/saves")
+        );
+        assert!(in_prefix("%DOCUMENTS%/x") && in_prefix("<prefix>/x"));
+        assert!(!in_prefix("$XDG_DATA_HOME/x"));
+    }
+    #[test]
+    fn expands_older_folder_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let prefix = dir.path().join("prefix");
+        let profile = prefix.join("drive_c/users/player");
+        fs::create_dir_all(profile.join("Application Data")).unwrap();
+        fs::create_dir_all(profile.join("My Documents")).unwrap();
+        assert_eq!(
+            expand("%APPDATA%/saves", &prefix),
+            profile.join("Application Data/saves")
+        );
+        assert_eq!(
+            expand("%DOCUMENTS%/saves", &prefix),
+            profile.join("My Documents/saves")
+        );
+    }
+    #[test]
+    fn writes_portable_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let (prefix, profile) = prefix(dir.path());
+        let portable_path = |path: PathBuf| portable(&path, &prefix);
+        assert_eq!(
+            portable_path(profile.join("AppData/Roaming/StardewValley")),
+            Some("%APPDATA%/StardewValley".to_string())
+        );
+        assert_eq!(
+            portable_path(profile.join("AppData/Local/Larian Studios")),
+            Some("%LOCALAPPDATA%/Larian Studios".to_string())
+        );
+        assert_eq!(
+            portable_path(profile.join("Documents")),
+            Some("%DOCUMENTS%".to_string())
+        );
+        assert_eq!(
+            portable_path(profile.join("Saved Games/Game")),
+            Some("%USERPROFILE%/Saved Games/Game".to_string())
+        );
+        assert_eq!(
+            portable_path(prefix.join("drive_c/Games/saves")),
+            Some("<prefix>/drive_c/Games/saves".to_string())
+        );
+        assert_eq!(portable_path(dir.path().join("elsewhere")), None);
+        let saves = profile.join("Documents/My Games/saves");
+        assert_eq!(expand(&portable(&saves, &prefix).unwrap(), &prefix), saves);
+    }
+    #[test]
+    fn detects_how_to_launch_games() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join("goggame-1.info"),
+            r#"{"gameId": "1", "rootGameId": "1", "name": "Game", "playTasks": [
+                {"type": "URLTask", "isPrimary": true, "link": "https://www.gog.com"},
+                {"type": "FileTask", "category": "document", "path": "manual.pdf"},
+                {"type": "FileTask", "category": "game", "path": "bin\\game.exe", "arguments": " ", "workingDir": "bin\\"}
+            ]}"#,
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("goggame-2.info"),
+            r#"{"gameId": "2", "rootGameId": "1", "playTasks": [
+                {"type": "FileTask", "isPrimary": true, "path": "dlc.exe"}
+            ]}"#,
+        )
+        .unwrap();
+        fs::write(dir.path().join("goggame-3.info"), "not json").unwrap();
+        let launch = detect_launch(dir.path(), "fallback", "wine".to_string()).unwrap();
+        assert_eq!(launch.name, "Game");
+        assert_eq!(launch.game_id, Some("1".to_string()));
+        assert_eq!(launch.exe, PathBuf::from("bin/game.exe"));
+        assert_eq!(launch.arguments, None);
+        assert_eq!(launch.working_dir, Some(PathBuf::from("bin")));
+        assert_eq!(launch.prefix, None);
+        let empty = tempfile::tempdir().unwrap();
+        assert!(detect_launch(empty.path(), "fallback", "wine".to_string()).is_none());
+    }
+    #[test]
+    fn picks_the_wine_binary() {
+        let account = Account {
+            wine: Some("wine-staging".to_string()),
+            ..Account::default()
+        };
+        assert_eq!(wine_binary(Some("wine64".to_string()), &account), "wine64");
+        assert_eq!(wine_binary(None, &account), "wine-staging");
+        assert_eq!(wine_binary(None, &Account::default()), DEFAULT_WINE);
+    }
+}